@@ -0,0 +1,286 @@
+//! A cache of the inputs used to build each calendar, so that calendars whose inputs haven't
+//! changed since the last run don't have to be recomputed and rewritten.
+use crate::config::Policies;
+use crate::manifest::write_atomically;
+use crate::structs::{Change, Exemption, PowerOutage};
+use crate::BoxedError;
+use chrono::NaiveDate;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{read, read_to_string};
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::path::Path;
+
+/// The hash of every calendar's inputs, as of the last time it was written.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct Cache {
+    /// Maps each area name to the hash of the inputs used to build its calendar
+    pub areas: BTreeMap<String, String>,
+}
+
+impl Cache {
+    /// Read the cache from `path`. A missing or unreadable cache is treated as empty, which just
+    /// means every calendar gets rewritten.
+    pub fn read(path: &Path) -> Cache {
+        let Ok(contents) = read_to_string(path) else {
+            info!("No cache found at {path:?}, so all calendars will be rewritten");
+            return Cache::default();
+        };
+        serde_json::from_str(&contents).unwrap_or_else(|e| {
+            warn!("Ignoring the cache at {path:?} because it couldn't be parsed: {e}");
+            Cache::default()
+        })
+    }
+
+    /// Write the cache to `path`, overwriting whatever was there.
+    pub fn write(&self, path: &Path) -> Result<(), BoxedError> {
+        write_atomically(path, |file| {
+            writeln!(file, "{}", serde_json::to_string_pretty(self)?)?;
+            Ok(())
+        })
+    }
+
+    /// Whether the calendar for `area_name` has to be rebuilt, either because its inputs have
+    /// changed or because the calendar at `ics_path` doesn't exist.
+    pub fn is_stale(&self, area_name: &str, input_hash: &str, ics_path: &Path) -> bool {
+        self.areas.get(area_name).map(String::as_str) != Some(input_hash) || !ics_path.exists()
+    }
+}
+
+/// The version of the format of the calendars. Bump this whenever a change to the code changes
+/// what gets written to the calendars, so that every calendar is rewritten in the new format.
+const FORMAT_VERSION: u32 = 1;
+
+/// Hash everything that goes into the calendar for one area: the contents of its CSV schedule,
+/// the changes and exemptions which apply to it, its cancelled outages (which depend on what was
+/// published before, not just on the inputs), the policies which affect how calendars are written,
+/// the `FORMAT_VERSION`, and (for expired areas) the day on which the calendar was compiled, since
+/// that day gets its own warning event.
+pub fn area_input_hash(
+    csv_path: &Path,
+    area_name: &str,
+    changes: &[Change],
+    exemptions: &[&Exemption],
+    cancelled: &[PowerOutage],
+    policies: &Policies,
+    expired_on: Option<NaiveDate>,
+) -> Result<String, BoxedError> {
+    let mut hasher = Fnv1a::default();
+    FORMAT_VERSION.hash(&mut hasher);
+    policies.min_event_minutes.hash(&mut hasher);
+    read(csv_path)?.hash(&mut hasher);
+    for change in changes
+        .iter()
+        .filter(|c| c.area_filter.applies_to(area_name))
+    {
+        change.start.to_rfc3339().hash(&mut hasher);
+        change.finsh.to_rfc3339().hash(&mut hasher);
+        change.stage.hash(&mut hasher);
+        change.source.hash(&mut hasher);
+        change.area_filter.include_regex.as_str().hash(&mut hasher);
+        change.area_filter.exclude_regex.as_str().hash(&mut hasher);
+        change.area_filter.areas.hash(&mut hasher);
+        change.tentative.hash(&mut hasher);
+    }
+    for exemption in exemptions
+        .iter()
+        .filter(|e| e.area_filter.applies_to(area_name))
+    {
+        exemption.start.to_rfc3339().hash(&mut hasher);
+        exemption.finsh.to_rfc3339().hash(&mut hasher);
+        exemption.source.hash(&mut hasher);
+        exemption.reason.hash(&mut hasher);
+    }
+    for outage in cancelled {
+        outage.start.to_rfc3339().hash(&mut hasher);
+        outage.finsh.to_rfc3339().hash(&mut hasher);
+        outage.stage.hash(&mut hasher);
+    }
+    expired_on.map(|date| date.to_string()).hash(&mut hasher);
+    Ok(format!("{:016x}", hasher.finish()))
+}
+
+/// The 64-bit FNV-1a hash. Unlike `DefaultHasher`, this is guaranteed to give the same hash for
+/// the same input across Rust versions, so the cache stays valid when the compiler is upgraded.
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Fnv1a(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::{area_input_hash, Cache};
+    use crate::config::Policies;
+    use crate::structs::{Change, RawChange};
+    use chrono::NaiveDate;
+    use std::fs::{remove_file, write};
+    use std::path::{Path, PathBuf};
+
+    fn change(stage: u8, include: &str) -> Change {
+        RawChange {
+            start: "2023-05-29T10:00:00".to_string(),
+            finsh: "2023-05-29T12:00:00".to_string(),
+            stage,
+            source: "test source".to_string(),
+            include_regex: None,
+            exclude_regex: None,
+            include: Some(include.to_string()),
+            exclude: None,
+            selector: Default::default(),
+            until_further_notice: false,
+        }
+        .try_into()
+        .unwrap()
+    }
+
+    fn csv(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(name);
+        write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn same_inputs_give_same_hash() {
+        let path = csv("eskom-calendar-test-cache-same.csv", "a,b,c\n");
+        let changes = vec![change(2, "coct")];
+        let first = area_input_hash(
+            &path,
+            "city-of-cape-town-area-1",
+            &changes,
+            &[],
+            &[],
+            &Policies::default(),
+            None,
+        )
+        .unwrap();
+        let second = area_input_hash(
+            &path,
+            "city-of-cape-town-area-1",
+            &changes,
+            &[],
+            &[],
+            &Policies::default(),
+            None,
+        )
+        .unwrap();
+        remove_file(&path).unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn only_matching_changes_affect_hash() {
+        let path = csv("eskom-calendar-test-cache-matching.csv", "a,b,c\n");
+        let area = "city-of-cape-town-area-1";
+        let before = area_input_hash(
+            &path,
+            area,
+            &[change(2, "coct")],
+            &[],
+            &[],
+            &Policies::default(),
+            None,
+        )
+        .unwrap();
+        let unrelated = vec![change(2, "coct"), change(4, "tshwane")];
+        let related = vec![change(2, "coct"), change(4, "capetown")];
+        let after_unrelated = area_input_hash(
+            &path,
+            area,
+            &unrelated,
+            &[],
+            &[],
+            &Policies::default(),
+            None,
+        )
+        .unwrap();
+        let after_related =
+            area_input_hash(&path, area, &related, &[], &[], &Policies::default(), None).unwrap();
+        remove_file(&path).unwrap();
+        assert_eq!(before, after_unrelated);
+        assert_ne!(before, after_related);
+    }
+
+    #[test]
+    fn csv_contents_affect_hash() {
+        let first = csv("eskom-calendar-test-cache-csv-1.csv", "a,b,c\n");
+        let second = csv("eskom-calendar-test-cache-csv-2.csv", "a,b,d\n");
+        let area = "city-of-cape-town-area-1";
+        let first_hash =
+            area_input_hash(&first, area, &[], &[], &[], &Policies::default(), None).unwrap();
+        let second_hash =
+            area_input_hash(&second, area, &[], &[], &[], &Policies::default(), None).unwrap();
+        remove_file(&first).unwrap();
+        remove_file(&second).unwrap();
+        assert_ne!(first_hash, second_hash);
+    }
+
+    #[test]
+    fn expiry_date_affects_hash() {
+        let path = csv("eskom-calendar-test-cache-expiry.csv", "a,b,c\n");
+        let area = "kwazulu-natal-ethekwini-block-1";
+        let day = |d| NaiveDate::from_ymd_opt(2023, 6, d);
+        let first =
+            area_input_hash(&path, area, &[], &[], &[], &Policies::default(), day(1)).unwrap();
+        let second =
+            area_input_hash(&path, area, &[], &[], &[], &Policies::default(), day(2)).unwrap();
+        remove_file(&path).unwrap();
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn calendar_policies_affect_hash() {
+        let path = csv("eskom-calendar-test-cache-policies.csv", "a,b,c\n");
+        let area = "city-of-cape-town-area-1";
+        let before =
+            area_input_hash(&path, area, &[], &[], &[], &Policies::default(), None).unwrap();
+        let shorter = Policies {
+            min_event_minutes: 15,
+            ..Default::default()
+        };
+        let after = area_input_hash(&path, area, &[], &[], &[], &shorter, None).unwrap();
+        remove_file(&path).unwrap();
+        assert_ne!(before, after);
+    }
+
+    #[test]
+    fn stale_if_hash_differs_or_calendar_missing() {
+        let ics = csv("eskom-calendar-test-cache-exists.ics", "BEGIN:VCALENDAR\n");
+        let mut cache = Cache::default();
+        cache.areas.insert("area".to_owned(), "abc".to_owned());
+        assert!(!cache.is_stale("area", "abc", &ics));
+        assert!(cache.is_stale("area", "def", &ics));
+        assert!(cache.is_stale("other-area", "abc", &ics));
+        assert!(cache.is_stale("area", "abc", Path::new("does-not-exist.ics")));
+        remove_file(&ics).unwrap();
+    }
+
+    #[test]
+    fn round_trips_through_disk() {
+        let path = std::env::temp_dir().join("eskom-calendar-test-cache.json");
+        let mut cache = Cache::default();
+        cache.areas.insert("area".to_owned(), "abc".to_owned());
+        cache.write(&path).unwrap();
+        let read = Cache::read(&path);
+        remove_file(&path).unwrap();
+        assert_eq!(cache, read);
+        assert_eq!(Cache::read(&path), Cache::default());
+    }
+}
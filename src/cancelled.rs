@@ -0,0 +1,265 @@
+//! Keeps track of outages which were published by a previous run but have since been withdrawn,
+//! so that they're shown as cancelled instead of silently disappearing. The previous run's
+//! `machine_friendly.csv` is the record of what was published, and cancelled outages are carried
+//! forward from one run to the next until a while after they would have finished. This only works
+//! if the output directory persists between runs, so the nightly build downloads the previous
+//! release's CSV into it before generating.
+use crate::structs::PowerOutage;
+use crate::BoxedError;
+use chrono::{DateTime, Duration, FixedOffset};
+use csv::ReaderBuilder;
+use log::{info, warn};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+/// One line of a previously written `machine_friendly.csv`. Files written before outages could be
+/// cancelled (or be tentative) don't have the `cancelled` (or `tentative`) column.
+#[derive(Deserialize, Debug)]
+struct PublishedOutage {
+    area_name: String,
+    start: String,
+    finsh: String,
+    stage: u8,
+    source: String,
+    #[serde(default)]
+    cancelled: bool,
+    #[serde(default)]
+    tentative: bool,
+}
+
+impl From<PublishedOutage> for PowerOutage {
+    fn from(raw: PublishedOutage) -> Self {
+        let parse = |time: &str| {
+            DateTime::parse_from_rfc3339(time)
+                .unwrap_or_else(|_| panic!("Failed to parse {time:?} as RFC3339 in {raw:?}"))
+        };
+        PowerOutage {
+            area_name: raw.area_name.clone(),
+            stage: raw.stage,
+            start: parse(&raw.start),
+            finsh: parse(&raw.finsh),
+            source: raw.source.clone(),
+            cancelled: raw.cancelled,
+            tentative: raw.tentative,
+        }
+    }
+}
+
+/// Read the outages published by the previous run from its CSV file at `path`, grouped by area.
+/// A missing file just means that nothing was published, so nothing can be cancelled.
+pub fn read_published(path: &Path) -> Result<BTreeMap<String, Vec<PowerOutage>>, BoxedError> {
+    if !path.exists() {
+        info!("No previously published outages at {path:?}, so none will be cancelled");
+        return Ok(BTreeMap::new());
+    }
+    let mut published: BTreeMap<String, Vec<PowerOutage>> = BTreeMap::new();
+    for raw in ReaderBuilder::new()
+        .from_path(path)?
+        .deserialize::<PublishedOutage>()
+    {
+        let outage: PowerOutage = raw?.into();
+        published
+            .entry(outage.area_name.clone())
+            .or_default()
+            .push(outage);
+    }
+    info!(
+        "Read {} previously published outages from {path:?}",
+        published.values().map(Vec::len).sum::<usize>()
+    );
+    Ok(published)
+}
+
+/// The outages of one area which were `published` (or already cancelled) by a previous run, but
+/// aren't in its `current` outages any more, marked as cancelled. Outages which finished more than
+/// `keep_for` before `now` are forgotten about, and tentative outages are never cancelled since
+/// they weren't announced in the first place. Outages are matched by their times and stage, so
+/// correcting the source of an announcement doesn't cancel its outages.
+pub fn cancelled_outages(
+    published: &[PowerOutage],
+    current: &[PowerOutage],
+    now: DateTime<FixedOffset>,
+    keep_for: Duration,
+) -> Vec<PowerOutage> {
+    let key = |o: &PowerOutage| (o.start, o.finsh, o.stage);
+    let current = current
+        .iter()
+        .filter(|o| !o.cancelled)
+        .map(key)
+        .collect::<HashSet<_>>();
+    let mut seen = HashSet::new();
+    let cancelled = published
+        .iter()
+        .filter(|o| o.finsh + keep_for > now && !o.tentative)
+        .filter(|o| !current.contains(&key(o)))
+        .filter(|o| seen.insert(key(o)))
+        .map(|o| PowerOutage {
+            cancelled: true,
+            ..o.clone()
+        })
+        .collect::<Vec<_>>();
+    let withdrawn = published
+        .iter()
+        .filter(|o| !o.cancelled && cancelled.iter().any(|c| key(c) == key(o)))
+        .collect::<Vec<_>>();
+    if let Some(outage) = withdrawn.first() {
+        warn!(
+            "{} previously published outages in {} have been withdrawn, marking them as cancelled",
+            withdrawn.len(),
+            outage.area_name
+        );
+    }
+    cancelled
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cancelled::{cancelled_outages, read_published};
+    use crate::structs::PowerOutage;
+    use chrono::{DateTime, Duration};
+    use std::fs::{remove_file, write};
+
+    fn outage(stage: u8, start: &str, finsh: &str, cancelled: bool) -> PowerOutage {
+        PowerOutage {
+            area_name: "western-cape-stellenbosch".to_owned(),
+            stage,
+            start: DateTime::parse_from_rfc3339(start).unwrap(),
+            finsh: DateTime::parse_from_rfc3339(finsh).unwrap(),
+            source: "test source".to_owned(),
+            cancelled,
+            tentative: false,
+        }
+    }
+
+    #[test]
+    fn withdrawn_outages_are_cancelled_until_they_expire() {
+        let now = DateTime::parse_from_rfc3339("2023-01-02T12:00:00+02:00").unwrap();
+        let kept = outage(
+            2,
+            "2023-01-02T16:00:00+02:00",
+            "2023-01-02T18:30:00+02:00",
+            false,
+        );
+        let withdrawn = outage(
+            2,
+            "2023-01-03T16:00:00+02:00",
+            "2023-01-03T18:30:00+02:00",
+            false,
+        );
+        let superseded = outage(
+            4,
+            "2023-01-03T00:00:00+02:00",
+            "2023-01-03T02:30:00+02:00",
+            false,
+        );
+        let still_cancelled = outage(
+            2,
+            "2023-01-02T00:00:00+02:00",
+            "2023-01-02T02:30:00+02:00",
+            true,
+        );
+        let expired = outage(
+            2,
+            "2023-01-01T00:00:00+02:00",
+            "2023-01-01T02:30:00+02:00",
+            true,
+        );
+        let published = vec![
+            kept.clone(),
+            withdrawn.clone(),
+            superseded.clone(),
+            still_cancelled.clone(),
+            expired,
+        ];
+        let current = vec![
+            kept,
+            outage(
+                2,
+                "2023-01-03T00:00:00+02:00",
+                "2023-01-03T02:30:00+02:00",
+                false,
+            ),
+        ];
+
+        let cancelled = cancelled_outages(&published, &current, now, Duration::hours(24));
+        let cancel = |o: PowerOutage| PowerOutage {
+            cancelled: true,
+            ..o
+        };
+        assert_eq!(
+            cancelled,
+            vec![cancel(withdrawn), cancel(superseded), still_cancelled]
+        );
+    }
+
+    #[test]
+    fn reannounced_outages_are_not_cancelled() {
+        let now = DateTime::parse_from_rfc3339("2023-01-02T12:00:00+02:00").unwrap();
+        let cancelled = outage(
+            2,
+            "2023-01-03T16:00:00+02:00",
+            "2023-01-03T18:30:00+02:00",
+            true,
+        );
+        let reannounced = PowerOutage {
+            cancelled: false,
+            tentative: false,
+            ..cancelled.clone()
+        };
+        assert!(
+            cancelled_outages(&[cancelled], &[reannounced], now, Duration::hours(24)).is_empty()
+        );
+    }
+
+    #[test]
+    fn corrected_sources_are_not_cancelled() {
+        let now = DateTime::parse_from_rfc3339("2023-01-02T12:00:00+02:00").unwrap();
+        let published = outage(
+            2,
+            "2023-01-03T16:00:00+02:00",
+            "2023-01-03T18:30:00+02:00",
+            false,
+        );
+        let corrected = PowerOutage {
+            source: "corrected source".to_owned(),
+            ..published.clone()
+        };
+        assert!(cancelled_outages(&[published], &[corrected], now, Duration::hours(24)).is_empty());
+    }
+
+    #[test]
+    fn reads_old_and_new_csv_files() {
+        let path = std::env::temp_dir().join("eskom-calendar-test-published.csv");
+        write(
+            &path,
+            "area_name,start,finsh,stage,source\n\
+            western-cape-stellenbosch,2023-01-03T16:00:00+02:00,2023-01-03T18:30:00+02:00,2,\"test source\"\n",
+        )
+        .unwrap();
+        let old = read_published(&path).unwrap();
+        let outages = &old["western-cape-stellenbosch"];
+        assert_eq!(outages.len(), 1);
+        assert!(!outages[0].cancelled);
+
+        let mut written = outages[0].clone();
+        written.cancelled = true;
+        write(
+            &path,
+            format!("{}\n{}\n", PowerOutage::csv_header(), written),
+        )
+        .unwrap();
+        let new = read_published(&path).unwrap();
+        let _ = remove_file(&path);
+        assert_eq!(new["western-cape-stellenbosch"], vec![written]);
+    }
+
+    #[test]
+    fn missing_csv_is_empty() {
+        assert!(
+            read_published(&std::env::temp_dir().join("does-not-exist.csv"))
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,869 @@
+//! Consistency checks for the input data, which are run as part of the fast `check` command.
+use crate::structs::{AreaMetadata, Cycle, ManuallyInputSchedule, Recurrence, RecurringShedding};
+use crate::{fmt, source, BoxedError};
+use chrono::Timelike;
+use log::{info, log, trace, warn, Level};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::path::PathBuf;
+
+/// The only valid values for `province` in `area_metadata.yaml`.
+const PROVINCES: [&str; 9] = [
+    "eastern-cape",
+    "free-state",
+    "gauteng",
+    "kwazulu-natal",
+    "limpopo",
+    "mpumalanga",
+    "north-west",
+    "northern-cape",
+    "western-cape",
+];
+
+/// Something wrong with `area_metadata.yaml`, or with how it lines up with `generated/`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MetadataProblem {
+    /// The metadata mentions a calendar which has no schedule in `generated/`
+    OrphanMetadata { calendar_name: String },
+    /// There's a schedule in `generated/` which isn't mentioned in the metadata
+    MissingMetadata { area_name: String },
+    /// The same calendar is described more than once
+    DuplicateCalendar { calendar_name: String },
+    /// The same suburb of a municipality is listed more than once, either by one calendar or by
+    /// several calendars
+    DuplicateSuburb {
+        province: String,
+        municipality: String,
+        suburb: String,
+        calendar_names: Vec<String>,
+    },
+    /// A group of suburbs doesn't have a province, either directly or from its calendar
+    MissingProvince { calendar_name: String },
+    /// The province isn't one of the nine provinces of South Africa
+    UnknownProvince {
+        calendar_name: String,
+        province: String,
+    },
+    /// The same municipality is spelt in different ways
+    InconsistentMunicipality { spellings: Vec<String> },
+    /// A calendar, province or municipality name isn't lowercase-with-dashes
+    MalformedName { kind: &'static str, name: String },
+}
+
+impl MetadataProblem {
+    /// Errors mean the website will have broken links, and fail the check. Everything else is a
+    /// warning.
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self,
+            MetadataProblem::OrphanMetadata { .. } | MetadataProblem::MissingMetadata { .. }
+        )
+    }
+}
+
+impl Display for MetadataProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MetadataProblem::OrphanMetadata { calendar_name } => write!(
+                f,
+                "{calendar_name} is in area_metadata.yaml but has no schedule in generated/"
+            ),
+            MetadataProblem::MissingMetadata { area_name } => write!(
+                f,
+                "generated/{area_name}.csv has no entry in area_metadata.yaml"
+            ),
+            MetadataProblem::DuplicateCalendar { calendar_name } => {
+                write!(f, "{calendar_name} is described more than once")
+            }
+            MetadataProblem::DuplicateSuburb {
+                province,
+                municipality,
+                suburb,
+                calendar_names,
+            } => write!(
+                f,
+                "{suburb} in {municipality} ({province}) is listed more than once, by {}",
+                calendar_names.join(", ")
+            ),
+            MetadataProblem::MissingProvince { calendar_name } => {
+                write!(f, "{calendar_name} has suburbs without a province")
+            }
+            MetadataProblem::UnknownProvince {
+                calendar_name,
+                province,
+            } => write!(f, "{calendar_name} has an unknown province {province:?}"),
+            MetadataProblem::InconsistentMunicipality { spellings } => write!(
+                f,
+                "The same municipality is spelt differently: {}",
+                spellings.join(", ")
+            ),
+            MetadataProblem::MalformedName { kind, name } => write!(
+                f,
+                "The {kind} {name:?} should be lowercase, with dashes instead of spaces"
+            ),
+        }
+    }
+}
+
+/// Names should be lowercase, with dashes instead of spaces, and not start or end with a dash.
+fn is_well_formed(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && !name.ends_with('-')
+        && name
+            .chars()
+            .all(|c| !c.is_whitespace() && !c.is_uppercase() && c != '_' && c != '(' && c != ')')
+}
+
+/// Find all the problems with `metadata`, and with how it lines up with the schedules at `paths`.
+pub fn check_metadata(
+    metadata: &AreaMetadata,
+    paths: &[PathBuf],
+) -> Result<Vec<MetadataProblem>, BoxedError> {
+    let mut problems = vec![];
+    let area_names = paths
+        .iter()
+        .map(|path| fmt::path_to_area_name(path))
+        .collect::<Result<BTreeSet<_>, _>>()?;
+
+    let mut seen_calendars = BTreeSet::new();
+    for detail in &metadata.area_details {
+        if !seen_calendars.insert(detail.calendar_name.as_str()) {
+            problems.push(MetadataProblem::DuplicateCalendar {
+                calendar_name: detail.calendar_name.clone(),
+            });
+        }
+    }
+    let described = metadata
+        .area_details
+        .iter()
+        .map(|detail| detail.area_name().to_owned())
+        .collect::<BTreeSet<_>>();
+    for area_name in described.difference(&area_names) {
+        problems.push(MetadataProblem::OrphanMetadata {
+            calendar_name: format!("{area_name}.ics"),
+        });
+    }
+    for area_name in area_names.difference(&described) {
+        problems.push(MetadataProblem::MissingMetadata {
+            area_name: area_name.clone(),
+        });
+    }
+
+    // Every calendar which lists each suburb, grouped by province and municipality
+    let mut suburb_calendars: BTreeMap<(&str, &str), BTreeMap<&str, Vec<&str>>> = BTreeMap::new();
+    // Municipalities are considered the same if they only differ in case and punctuation
+    let mut municipality_spellings: BTreeMap<String, BTreeSet<&str>> = BTreeMap::new();
+    let mut malformed: BTreeSet<(&'static str, &str)> = BTreeSet::new();
+    for detail in &metadata.area_details {
+        if !is_well_formed(&detail.calendar_name) {
+            malformed.insert(("calendar", &detail.calendar_name));
+        }
+
+        let mut missing_province = false;
+        let mut unknown_provinces = BTreeSet::new();
+        for group in &detail.areas {
+            let province = group.province.as_ref().or(detail.province.as_ref());
+            match province {
+                None => missing_province = true,
+                Some(province) if !PROVINCES.contains(&province.as_str()) => {
+                    unknown_provinces.insert(province.as_str());
+                }
+                Some(_) => {}
+            }
+            let province = province.map_or("an unknown province", |p| p.as_str());
+            let municipality = group
+                .municipality
+                .as_ref()
+                .or(detail.municipality.as_ref())
+                .map_or("an unknown municipality", |m| m.as_str());
+            for suburb in group.name.iter() {
+                suburb_calendars
+                    .entry((province, municipality))
+                    .or_default()
+                    .entry(suburb.as_str())
+                    .or_default()
+                    .push(&detail.calendar_name);
+            }
+        }
+        for municipality in detail
+            .municipality
+            .iter()
+            .chain(detail.areas.iter().filter_map(|g| g.municipality.as_ref()))
+        {
+            if !is_well_formed(municipality) {
+                malformed.insert(("municipality", municipality));
+            }
+            municipality_spellings
+                .entry(crate::search::search_key(municipality))
+                .or_default()
+                .insert(municipality);
+        }
+
+        if missing_province {
+            problems.push(MetadataProblem::MissingProvince {
+                calendar_name: detail.calendar_name.clone(),
+            });
+        }
+        for province in unknown_provinces {
+            problems.push(MetadataProblem::UnknownProvince {
+                calendar_name: detail.calendar_name.clone(),
+                province: province.to_owned(),
+            });
+        }
+    }
+    for ((province, municipality), suburbs) in suburb_calendars {
+        for (suburb, mut calendar_names) in suburbs {
+            if calendar_names.len() < 2 {
+                continue;
+            }
+            calendar_names.sort();
+            calendar_names.dedup();
+            problems.push(MetadataProblem::DuplicateSuburb {
+                province: province.to_owned(),
+                municipality: municipality.to_owned(),
+                suburb: suburb.to_owned(),
+                calendar_names: calendar_names.into_iter().map(str::to_owned).collect(),
+            });
+        }
+    }
+    for spellings in municipality_spellings.into_values() {
+        if spellings.len() > 1 {
+            problems.push(MetadataProblem::InconsistentMunicipality {
+                spellings: spellings.into_iter().map(|s| s.to_owned()).collect(),
+            });
+        }
+    }
+    for (kind, name) in malformed {
+        problems.push(MetadataProblem::MalformedName {
+            kind,
+            name: name.to_owned(),
+        });
+    }
+    // A calendar which is described more than once would otherwise report its problems twice
+    problems.sort();
+    problems.dedup();
+    Ok(problems)
+}
+
+/// Checks that `area_metadata.yaml` lines up with the schedules in `generated/`. Every problem is
+/// logged as a warning, but only problems which would break the website return an `Err`.
+pub fn err_if_metadata_inconsistent(
+    metadata: &AreaMetadata,
+    paths: &[PathBuf],
+) -> Result<(), BoxedError> {
+    info!("Checking area metadata for consistency...");
+    let problems = check_metadata(metadata, paths)?;
+    for problem in &problems {
+        warn!("{problem}");
+    }
+    let errors = problems
+        .iter()
+        .filter(|problem| problem.is_error())
+        .map(|problem| problem.to_string())
+        .collect::<Vec<_>>();
+    if !errors.is_empty() {
+        return Err(Box::from(format!(
+            "area_metadata.yaml doesn't line up with generated/:\n{}",
+            errors.join("\n")
+        )));
+    }
+    trace!("  Found {} metadata warnings", problems.len());
+    Ok(())
+}
+
+/// Something wrong with the recurring schedule of one area in `generated/`, which probably means
+/// the municipality's schedule was transcribed incorrectly.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ScheduleProblem {
+    /// The same row appears more than once
+    DuplicateRow { area_name: String, row: String },
+    /// Two different rows of the same stage overlap on the same day
+    OverlappingRows {
+        area_name: String,
+        first: String,
+        second: String,
+    },
+    /// Monthly rows on the 29th, 30th or 31st, which are skipped in months without those dates
+    ShortMonthDates {
+        area_name: String,
+        dates_of_month: Vec<u8>,
+    },
+    /// An outage at one stage isn't also an outage at the next stage up, even though higher
+    /// stages should always include the outages of lower stages
+    NotMonotonic {
+        area_name: String,
+        row: String,
+        stage: u8,
+    },
+}
+
+impl ScheduleProblem {
+    /// Overlaps double up outages, so they fail the check. Duplicates are harmless but suggest a
+    /// copy-paste error, and not every municipality publishes monotonic stages, so those are
+    /// warnings. Almost every monthly schedule has outages on the 31st, so that's only a notice.
+    pub fn level(&self) -> Level {
+        match self {
+            ScheduleProblem::OverlappingRows { .. } => Level::Error,
+            ScheduleProblem::DuplicateRow { .. } | ScheduleProblem::NotMonotonic { .. } => {
+                Level::Warn
+            }
+            ScheduleProblem::ShortMonthDates { .. } => Level::Info,
+        }
+    }
+
+    /// The area whose schedule has this problem.
+    pub fn area_name(&self) -> &str {
+        match self {
+            ScheduleProblem::DuplicateRow { area_name, .. }
+            | ScheduleProblem::OverlappingRows { area_name, .. }
+            | ScheduleProblem::ShortMonthDates { area_name, .. }
+            | ScheduleProblem::NotMonotonic { area_name, .. } => area_name,
+        }
+    }
+}
+
+impl Display for ScheduleProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScheduleProblem::DuplicateRow { area_name, row } => {
+                write!(f, "{area_name} has the row {row} more than once")
+            }
+            ScheduleProblem::OverlappingRows {
+                area_name,
+                first,
+                second,
+            } => write!(f, "{area_name} has overlapping rows {first} and {second}"),
+            ScheduleProblem::ShortMonthDates {
+                area_name,
+                dates_of_month,
+            } => write!(
+                f,
+                "{area_name} has outages on days {}, which are skipped in shorter months",
+                dates_of_month
+                    .iter()
+                    .map(|date| date.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            ScheduleProblem::NotMonotonic {
+                area_name,
+                row,
+                stage,
+            } => write!(f, "{area_name} has the row {row}, but not at stage {stage}"),
+        }
+    }
+}
+
+/// Rows of the same stage may overlap by this many minutes, since most schedules have outages
+/// which are two hours long but officially end at HH:30, like 00:00-02:30 and 02:00-04:30.
+const ALLOWED_OVERLAP_MINUTES: u32 = 30;
+
+/// A human-readable description of a row, like `day 3 16:00-18:30 stage 2` (or
+/// `2023-09-15 16:00-18:30 stage 2` for a dated row).
+fn describe_row(shedding: &RecurringShedding) -> String {
+    let day = match shedding.recurrence {
+        Recurrence::Weekly { day_of_week: day } | Recurrence::Monthly { date_of_month: day } => {
+            format!("day {day}")
+        }
+        Recurrence::Periodic(cycle) => format!("day {}", cycle.day()),
+        Recurrence::Dated { date } => date.format("%Y-%m-%d").to_string(),
+        Recurrence::Rule(rule) => format!("{rule} from {}", rule.start()),
+    };
+    let (start, finsh) = shedding.format_times();
+    format!("{day} {start}-{finsh} stage {}", shedding.stage)
+}
+
+/// The minutes after midnight at which a row starts and finishes. Rows which go over midnight
+/// finish more than 24 hours after midnight.
+fn minutes(shedding: &RecurringShedding) -> (u32, u32) {
+    let start = shedding.start_time.num_seconds_from_midnight() / 60;
+    let duration = u32::try_from(shedding.duration.num_minutes()).unwrap_or(u32::MAX);
+    (start, start.saturating_add(duration))
+}
+
+/// The number of minutes in a day.
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// The day after `day`, or `None` if it isn't clear which day that is (like for recurrence
+/// rules). Monthly rows on the 31st are followed by the 1st.
+fn next_day(day: Recurrence) -> Option<Recurrence> {
+    match day {
+        Recurrence::Weekly { day_of_week } => Some(Recurrence::Weekly {
+            day_of_week: day_of_week % 7 + 1,
+        }),
+        Recurrence::Monthly { date_of_month } => Some(Recurrence::Monthly {
+            date_of_month: date_of_month % 31 + 1,
+        }),
+        Recurrence::Periodic(cycle) => Cycle::new(
+            cycle.start(),
+            cycle.period(),
+            cycle.day() % cycle.period() + 1,
+        )
+        .ok()
+        .map(Recurrence::Periodic),
+        Recurrence::Dated { date } => date.succ_opt().map(|date| Recurrence::Dated { date }),
+        Recurrence::Rule(_) => None,
+    }
+}
+
+/// The part of a row on each day that it covers, as the minutes after midnight of that day. Rows
+/// which go over midnight continue on the next day, so `day 3 22:00-00:30` is `day 3 22:00-24:00`
+/// and `day 4 00:00-00:30`.
+fn minutes_by_day(shedding: &RecurringShedding) -> Vec<(Recurrence, (u32, u32))> {
+    let (mut start, mut finsh) = minutes(shedding);
+    let mut day = shedding.recurrence;
+    let mut parts = vec![];
+    while finsh > MINUTES_PER_DAY {
+        let Some(next) = next_day(day) else {
+            break;
+        };
+        parts.push((day, (start, MINUTES_PER_DAY)));
+        (day, start, finsh) = (next, 0, finsh - MINUTES_PER_DAY);
+    }
+    parts.push((day, (start, finsh)));
+    parts
+}
+
+/// Whether the rows in `covering` (which are all on the same day) cover all of `row`.
+fn is_covered(row: (u32, u32), covering: &[(u32, u32)]) -> bool {
+    let mut covering = covering.to_vec();
+    covering.sort();
+    let mut covered_until = row.0;
+    for (start, finsh) in covering {
+        if start <= covered_until && finsh > covered_until {
+            covered_until = finsh;
+        }
+    }
+    covered_until >= row.1
+}
+
+/// Find all the problems with the schedule of `area_name`, which is made of `sheddings`.
+pub fn check_schedule(area_name: &str, sheddings: &[RecurringShedding]) -> Vec<ScheduleProblem> {
+    let mut problems = vec![];
+    let area_name = area_name.to_owned();
+
+    // Group the rows by day (which is their recurrence) and stage, with the rows which go over
+    // midnight split across both days
+    let mut by_day_and_stage = BTreeMap::<_, Vec<_>>::new();
+    for shedding in sheddings {
+        for (day, minutes) in minutes_by_day(shedding) {
+            by_day_and_stage
+                .entry((day, shedding.stage))
+                .or_default()
+                .push((shedding, minutes));
+        }
+    }
+
+    for rows in by_day_and_stage.values() {
+        for (i, &(first, (start1, finsh1))) in rows.iter().enumerate() {
+            for &(second, (start2, finsh2)) in &rows[i + 1..] {
+                if first == second {
+                    problems.push(ScheduleProblem::DuplicateRow {
+                        area_name: area_name.clone(),
+                        row: describe_row(first),
+                    });
+                } else if finsh1.min(finsh2) > start1.max(start2) + ALLOWED_OVERLAP_MINUTES {
+                    problems.push(ScheduleProblem::OverlappingRows {
+                        area_name: area_name.clone(),
+                        first: describe_row(first),
+                        second: describe_row(second),
+                    });
+                }
+            }
+        }
+    }
+
+    let dates_of_month = sheddings
+        .iter()
+        .filter_map(|s| match s.recurrence {
+            Recurrence::Monthly { date_of_month } if date_of_month > 28 => Some(date_of_month),
+            _ => None,
+        })
+        .collect::<BTreeSet<_>>();
+    if !dates_of_month.is_empty() {
+        problems.push(ScheduleProblem::ShortMonthDates {
+            area_name: area_name.clone(),
+            dates_of_month: dates_of_month.into_iter().collect(),
+        });
+    }
+
+    // Every outage at one stage should also be an outage at the next stage up, but only check
+    // stages which appear somewhere in the schedule, since some schedules stop at stage 4
+    let stages = sheddings.iter().map(|s| s.stage).collect::<BTreeSet<_>>();
+    for ((day, stage), rows) in &by_day_and_stage {
+        if !stages.contains(&(stage + 1)) {
+            continue;
+        }
+        let next_stage = by_day_and_stage
+            .get(&(*day, stage + 1))
+            .map_or(vec![], |rows| {
+                rows.iter().map(|(_, minutes)| *minutes).collect()
+            });
+        for (row, minutes) in rows {
+            if !is_covered(*minutes, &next_stage) {
+                problems.push(ScheduleProblem::NotMonotonic {
+                    area_name: area_name.clone(),
+                    row: describe_row(row),
+                    stage: stage + 1,
+                });
+            }
+        }
+    }
+    problems.sort();
+    problems.dedup();
+    problems
+}
+
+/// Checks the schedules at `paths` for transcription mistakes. Every problem is logged as a
+/// warning, but only problems which would give users wrong outages return an `Err`.
+pub fn err_if_schedules_inconsistent(paths: &[PathBuf]) -> Result<(), BoxedError> {
+    info!("Checking schedules for consistency...");
+    let mut problems = vec![];
+    for path in paths {
+        let area_name = fmt::path_to_area_name(path)?;
+        let schedule = source::read_schedule(path)?;
+        problems.extend(check_schedule(&area_name, &schedule.sheddings));
+    }
+    for problem in &problems {
+        log!(problem.level(), "{problem}");
+    }
+    let errors = problems
+        .iter()
+        .filter(|problem| problem.level() == Level::Error)
+        .map(|problem| problem.to_string())
+        .collect::<Vec<_>>();
+    if !errors.is_empty() {
+        return Err(Box::from(format!(
+            "Some schedules in generated/ are inconsistent:\n{}",
+            errors.join("\n")
+        )));
+    }
+    trace!(
+        "  Found {} schedule warnings in {} areas",
+        problems.len(),
+        problems
+            .iter()
+            .map(|problem| problem.area_name())
+            .collect::<BTreeSet<_>>()
+            .len()
+    );
+    Ok(())
+}
+
+/// Print the areas chosen by the selectors (like `province: western-cape`) of every change or
+/// exemption which has them, so that it's easy to check they choose the areas that were meant.
+pub fn print_selected_areas(manually_specified: &ManuallyInputSchedule) {
+    for change in &manually_specified.changes {
+        if let Some(areas) = &change.area_filter.areas {
+            println!(
+                "Stage {} from {} to {} applies to {} areas selected by {:?}: {}",
+                change.stage,
+                change.start,
+                change.finsh,
+                areas.len(),
+                change.area_filter.selector,
+                areas.iter().cloned().collect::<Vec<_>>().join(", ")
+            );
+        }
+    }
+    for exemption in &manually_specified.exemptions {
+        if let Some(areas) = &exemption.area_filter.areas {
+            println!(
+                "Exemption from {} to {} applies to {} areas selected by {:?}: {}",
+                exemption.start,
+                exemption.finsh,
+                areas.len(),
+                exemption.area_filter.selector,
+                areas.iter().cloned().collect::<Vec<_>>().join(", ")
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::check::{
+        check_metadata, check_schedule, err_if_metadata_inconsistent, MetadataProblem,
+        ScheduleProblem,
+    };
+    use crate::structs::{AreaMetadata, RawMonthlyShedding, RecurringShedding};
+    use std::path::PathBuf;
+
+    fn monthly(date_of_month: u8, start: &str, finsh: &str, stage: u8) -> RecurringShedding {
+        RawMonthlyShedding {
+            start_time: start.to_owned(),
+            finsh_time: finsh.to_owned(),
+            stage,
+            date_of_month,
+        }
+        .try_into()
+        .unwrap()
+    }
+
+    fn metadata(yaml: &str) -> AreaMetadata {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn paths(area_names: &[&str]) -> Vec<PathBuf> {
+        area_names
+            .iter()
+            .map(|name| PathBuf::from(format!("generated/{name}.csv")))
+            .collect()
+    }
+
+    #[test]
+    fn ok_if_consistent() {
+        let metadata = metadata(
+            r#"
+area_details:
+- calendar_name: western-cape-stellenbosch.ics
+  province: western-cape
+  municipality: stellenbosch
+  areas: [{name: stellenbosch}, {name: idas-valley}]
+"#,
+        );
+        let paths = paths(&["western-cape-stellenbosch"]);
+        assert_eq!(check_metadata(&metadata, &paths).unwrap(), vec![]);
+        assert!(err_if_metadata_inconsistent(&metadata, &paths).is_ok());
+    }
+
+    #[test]
+    fn err_if_orphaned_or_missing() {
+        let metadata = metadata(
+            r#"
+area_details:
+- calendar_name: western-cape-stellenbosch.ics
+  province: western-cape
+  areas: [{name: stellenbosch}]
+"#,
+        );
+        let paths = paths(&["western-cape-darling"]);
+        assert_eq!(
+            check_metadata(&metadata, &paths).unwrap(),
+            vec![
+                MetadataProblem::OrphanMetadata {
+                    calendar_name: "western-cape-stellenbosch.ics".to_owned()
+                },
+                MetadataProblem::MissingMetadata {
+                    area_name: "western-cape-darling".to_owned()
+                },
+            ]
+        );
+        assert!(err_if_metadata_inconsistent(&metadata, &paths).is_err());
+    }
+
+    #[test]
+    fn warns_about_duplicates_and_spelling() {
+        let metadata = metadata(
+            r#"
+area_details:
+- calendar_name: eskom-direct-1-even.ics
+  areas:
+  - province: free-state
+    municipality: letsemeng
+    name: ["diamant", "luckhoff", "diamant"]
+  - province: Free State
+    municipality: Letsemeng
+    name: ["luckhoff"]
+  - municipality: kopanong
+    name: ["diamant"]
+- calendar_name: eskom-direct-1-even.ics
+  province: free-state
+  areas: [{name: diamant}]
+"#,
+        );
+        let paths = paths(&["eskom-direct-1-even"]);
+        let problems = check_metadata(&metadata, &paths).unwrap();
+        assert_eq!(
+            problems,
+            vec![
+                MetadataProblem::DuplicateCalendar {
+                    calendar_name: "eskom-direct-1-even.ics".to_owned()
+                },
+                MetadataProblem::DuplicateSuburb {
+                    province: "free-state".to_owned(),
+                    municipality: "letsemeng".to_owned(),
+                    suburb: "diamant".to_owned(),
+                    calendar_names: vec!["eskom-direct-1-even.ics".to_owned()]
+                },
+                MetadataProblem::MissingProvince {
+                    calendar_name: "eskom-direct-1-even.ics".to_owned()
+                },
+                MetadataProblem::UnknownProvince {
+                    calendar_name: "eskom-direct-1-even.ics".to_owned(),
+                    province: "Free State".to_owned()
+                },
+                MetadataProblem::InconsistentMunicipality {
+                    spellings: vec!["Letsemeng".to_owned(), "letsemeng".to_owned()]
+                },
+                MetadataProblem::MalformedName {
+                    kind: "municipality",
+                    name: "Letsemeng".to_owned()
+                },
+            ]
+        );
+        // None of these are errors, so the check still passes
+        assert!(err_if_metadata_inconsistent(&metadata, &paths).is_ok());
+    }
+
+    #[test]
+    fn suburbs_in_several_calendars() {
+        let metadata = metadata(
+            r#"
+area_details:
+- calendar_name: western-cape-stellenbosch.ics
+  province: western-cape
+  municipality: stellenbosch
+  areas: [{name: [idas-valley, cloetesville]}]
+- calendar_name: western-cape-franschhoek.ics
+  province: western-cape
+  municipality: stellenbosch
+  areas: [{name: [franschhoek, idas-valley]}]
+- calendar_name: western-cape-franschhoek.ics
+  province: western-cape
+  municipality: stellenbosch
+  areas: [{name: [franschhoek, idas-valley]}]
+- calendar_name: western-cape-drakenstein.ics
+  province: western-cape
+  municipality: drakenstein
+  areas: [{name: [cloetesville]}]
+"#,
+        );
+        let paths = paths(&[
+            "western-cape-stellenbosch",
+            "western-cape-franschhoek",
+            "western-cape-drakenstein",
+        ]);
+        let duplicate = |suburb: &str, calendar_names: &[&str]| MetadataProblem::DuplicateSuburb {
+            province: "western-cape".to_owned(),
+            municipality: "stellenbosch".to_owned(),
+            suburb: suburb.to_owned(),
+            calendar_names: calendar_names.iter().map(|c| c.to_string()).collect(),
+        };
+        // Describing a calendar twice is only reported once, and suburbs with the same name in
+        // different municipalities are fine
+        assert_eq!(
+            check_metadata(&metadata, &paths).unwrap(),
+            vec![
+                MetadataProblem::DuplicateCalendar {
+                    calendar_name: "western-cape-franschhoek.ics".to_owned()
+                },
+                duplicate("franschhoek", &["western-cape-franschhoek.ics"]),
+                duplicate(
+                    "idas-valley",
+                    &[
+                        "western-cape-franschhoek.ics",
+                        "western-cape-stellenbosch.ics"
+                    ]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_calendar_names() {
+        let metadata = metadata(
+            r#"
+area_details:
+- calendar_name: limpopo-lephalale(ellisras)_town.ics
+  province: limpopo
+  areas: [{name: lephalale}]
+"#,
+        );
+        let paths = paths(&["limpopo-lephalale(ellisras)_town"]);
+        assert_eq!(
+            check_metadata(&metadata, &paths).unwrap(),
+            vec![MetadataProblem::MalformedName {
+                kind: "calendar",
+                name: "limpopo-lephalale(ellisras)_town.ics".to_owned()
+            }]
+        );
+    }
+
+    #[test]
+    fn ok_if_schedule_consistent() {
+        // Outages may overlap by 30 minutes, since they officially end at HH:30
+        let sheddings = vec![
+            monthly(1, "00:00", "02:30", 1),
+            monthly(1, "00:00", "02:30", 2),
+            monthly(1, "02:00", "04:30", 2),
+            monthly(1, "22:00", "00:30", 2),
+        ];
+        assert_eq!(check_schedule("area", &sheddings), vec![]);
+    }
+
+    #[test]
+    fn finds_duplicates_and_overlaps() {
+        let sheddings = vec![
+            monthly(1, "00:00", "02:30", 1),
+            monthly(1, "00:00", "02:30", 1),
+            monthly(2, "22:00", "00:30", 1),
+            monthly(2, "23:00", "01:30", 1),
+        ];
+        assert_eq!(
+            check_schedule("area", &sheddings),
+            vec![
+                ScheduleProblem::DuplicateRow {
+                    area_name: "area".to_owned(),
+                    row: "day 1 00:00-02:30 stage 1".to_owned()
+                },
+                ScheduleProblem::OverlappingRows {
+                    area_name: "area".to_owned(),
+                    first: "day 2 22:00-00:30 stage 1".to_owned(),
+                    second: "day 2 23:00-01:30 stage 1".to_owned()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rows_continue_past_midnight() {
+        // The stage 1 row is covered by stage 2 on both days
+        let sheddings = vec![
+            monthly(1, "22:00", "00:30", 1),
+            monthly(1, "20:00", "00:00", 2),
+            monthly(2, "00:00", "02:30", 2),
+        ];
+        assert_eq!(check_schedule("area", &sheddings), vec![]);
+
+        let sheddings = vec![
+            monthly(1, "22:00", "02:00", 2),
+            monthly(2, "00:00", "02:30", 2),
+        ];
+        assert_eq!(
+            check_schedule("area", &sheddings),
+            vec![ScheduleProblem::OverlappingRows {
+                area_name: "area".to_owned(),
+                first: "day 1 22:00-02:00 stage 2".to_owned(),
+                second: "day 2 00:00-02:30 stage 2".to_owned()
+            }]
+        );
+    }
+
+    #[test]
+    fn finds_non_monotonic_stages_and_short_months() {
+        let sheddings = vec![
+            monthly(1, "00:00", "02:30", 1),
+            // Covered by two outages at stage 2
+            monthly(1, "08:00", "12:30", 1),
+            monthly(1, "08:00", "10:30", 2),
+            monthly(1, "10:00", "12:30", 2),
+            monthly(31, "00:00", "02:30", 2),
+        ];
+        assert_eq!(
+            check_schedule("area", &sheddings),
+            vec![
+                ScheduleProblem::ShortMonthDates {
+                    area_name: "area".to_owned(),
+                    dates_of_month: vec![31]
+                },
+                ScheduleProblem::NotMonotonic {
+                    area_name: "area".to_owned(),
+                    row: "day 1 00:00-02:30 stage 1".to_owned(),
+                    stage: 2
+                },
+            ]
+        );
+    }
+}
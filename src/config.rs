@@ -0,0 +1,298 @@
+//! Where the inputs are read from, where the outputs are written to, and which outputs are written.
+//! These can be set in a TOML config file, and each setting can be overridden on the command line.
+use crate::structs::{ExportFormat, GenerateArgs, PathArgs};
+use crate::BoxedError;
+use log::info;
+use serde::Deserialize;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+/// The config file which is read if `--config` isn't given. It's fine if it doesn't exist.
+pub const DEFAULT_CONFIG_PATH: &str = "eskom-calendar.toml";
+
+/// The configuration of one run. Every field has a default which matches the layout of this
+/// repository, so a config file only needs to mention the settings it changes. For example:
+///
+/// ```toml
+/// generated_dir = "../my-fork/schedules"
+/// output_dir = "/tmp/calendars"
+///
+/// [outputs]
+/// parquet_files = true
+///
+/// [policies]
+/// min_event_minutes = 0
+/// ```
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The directory containing the CSV schedules of every area
+    pub generated_dir: PathBuf,
+    /// The YAML file containing the national loadshedding changes
+    pub manually_specified: PathBuf,
+    /// The YAML file describing which suburbs are in which area
+    pub area_metadata: PathBuf,
+    /// The directory containing the GeoJSON boundaries of the areas
+    pub boundaries_dir: PathBuf,
+    /// The directory which all the outputs are written to
+    pub output_dir: PathBuf,
+    /// The name of the machine-friendly CSV file containing every outage, inside `output_dir`
+    pub csv_file_name: String,
+    /// Which outputs are written
+    pub outputs: Outputs,
+    /// How the outputs are written
+    pub policies: Policies,
+}
+
+/// Which outputs are written.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Outputs {
+    /// One human-friendly ICS calendar per area
+    pub ics_files: bool,
+    /// One machine-friendly CSV file containing every outage
+    pub csv_file: bool,
+    /// Analytics-friendly Parquet files, partitioned by month
+    pub parquet_files: bool,
+    /// A JSON search index of all the suburbs for the website
+    pub search_index: bool,
+    /// A GeoJSON map of the area boundaries, coloured by current stage
+    pub geojson_file: bool,
+    /// The national stage over time (and each provider's, where it differs) as ICS, CSV and JSON
+    pub timeline_files: bool,
+}
+
+/// How the outputs are written.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policies {
+    /// Skip rewriting ICS files whose inputs haven't changed since the last run
+    pub incremental: bool,
+    /// Outages this many minutes long or shorter are left out of the ICS files (but not the CSV
+    /// file), unless they're right next to another outage
+    pub min_event_minutes: i64,
+    /// Areas which also get a CSV file of their own, for devices which can't download the full one
+    pub individual_csv_areas: Vec<String>,
+    /// Outages which were published by a previous run but have since been withdrawn are kept as
+    /// cancelled outages until this many hours after they would have finished
+    pub cancelled_outage_hours: i64,
+    /// Changes which continue until further notice are projected this many days after the
+    /// calendars are compiled, as tentative outages
+    pub tentative_horizon_days: i64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            generated_dir: PathBuf::from("generated"),
+            manually_specified: PathBuf::from("manually_specified.yaml"),
+            area_metadata: PathBuf::from("area_metadata.yaml"),
+            boundaries_dir: PathBuf::from("boundaries"),
+            output_dir: PathBuf::from("calendars"),
+            csv_file_name: "machine_friendly.csv".to_owned(),
+            outputs: Outputs::default(),
+            policies: Policies::default(),
+        }
+    }
+}
+
+impl Default for Outputs {
+    fn default() -> Self {
+        Outputs {
+            ics_files: true,
+            csv_file: true,
+            parquet_files: false,
+            search_index: false,
+            geojson_file: false,
+            timeline_files: false,
+        }
+    }
+}
+
+impl Default for Policies {
+    fn default() -> Self {
+        Policies {
+            incremental: true,
+            min_event_minutes: 30,
+            // NOTE: Hacky solution for embedded platforms.
+            // https://github.com/beyarkay/eskom-calendar/issues/341
+            //
+            // Microcontrollers don't have the memory/storage to handle a 1.42 MB file, so for now
+            // (until an API can be set up), write out just cpt-9 and cpt-15 as their own CSV files
+            // so that they can be downloaded individually.
+            individual_csv_areas: vec![
+                "city-of-cape-town-area-9".to_owned(),
+                "city-of-cape-town-area-15".to_owned(),
+            ],
+            cancelled_outage_hours: 24,
+            tentative_horizon_days: 7,
+        }
+    }
+}
+
+impl Config {
+    /// Read the config file at `path`, or at `eskom-calendar.toml` if no path is given. It's an
+    /// error if an explicitly given file doesn't exist, but the default file is optional.
+    pub fn read(path: Option<&Path>) -> Result<Config, BoxedError> {
+        let contents = match path {
+            Some(path) => read_to_string(path)
+                .map_err(|e| format!("Couldn't read config file {path:?}: {e}"))?,
+            None => match read_to_string(DEFAULT_CONFIG_PATH) {
+                Ok(contents) => contents,
+                Err(_) => {
+                    info!("No config file at {DEFAULT_CONFIG_PATH:?}, using the defaults");
+                    return Ok(Config::default());
+                }
+            },
+        };
+        Ok(toml::from_str(&contents)
+            .map_err(|e| format!("Couldn't parse config file {path:?}: {e}"))?)
+    }
+
+    /// Override the paths in this config with any which were given on the command line.
+    pub fn with_overrides(mut self, args: &PathArgs) -> Config {
+        let override_path = |setting: &mut PathBuf, arg: &Option<PathBuf>| {
+            if let Some(arg) = arg {
+                *setting = arg.clone();
+            }
+        };
+        override_path(&mut self.generated_dir, &args.generated_dir);
+        override_path(&mut self.manually_specified, &args.manually_specified);
+        override_path(&mut self.area_metadata, &args.area_metadata);
+        override_path(&mut self.boundaries_dir, &args.boundaries_dir);
+        override_path(&mut self.output_dir, &args.output_dir);
+        self
+    }
+
+    /// Override the outputs in this config with any which were given to `generate`.
+    pub fn with_generate_args(mut self, args: &GenerateArgs) -> Config {
+        let outputs = &mut self.outputs;
+        outputs.ics_files = args.output_ics_files.unwrap_or(outputs.ics_files);
+        outputs.csv_file = args.output_csv_file.unwrap_or(outputs.csv_file);
+        outputs.parquet_files = args.output_parquet_files.unwrap_or(outputs.parquet_files);
+        outputs.search_index = args.output_search_index.unwrap_or(outputs.search_index);
+        outputs.geojson_file = args.output_geojson_file.unwrap_or(outputs.geojson_file);
+        outputs.timeline_files = args.output_timeline_files.unwrap_or(outputs.timeline_files);
+        self.policies.incremental = args.incremental.unwrap_or(self.policies.incremental);
+        self
+    }
+
+    /// Only write the given formats, which were asked for with `export`.
+    pub fn with_export_formats(mut self, formats: &[ExportFormat]) -> Config {
+        self.outputs = Outputs {
+            ics_files: false,
+            csv_file: formats.contains(&ExportFormat::Csv),
+            parquet_files: formats.contains(&ExportFormat::Parquet),
+            search_index: formats.contains(&ExportFormat::SearchIndex),
+            geojson_file: formats.contains(&ExportFormat::Geojson),
+            timeline_files: formats.contains(&ExportFormat::Timeline),
+        };
+        self
+    }
+
+    /// The path of `fname` inside the output directory.
+    pub fn output_path(&self, fname: &str) -> PathBuf {
+        self.output_dir.join(fname)
+    }
+
+    /// The path of the ICS calendar for `area_name`.
+    pub fn ics_path(&self, area_name: &str) -> PathBuf {
+        self.output_path(&format!("{}.ics", area_name.replace("&nbsp;", "")))
+    }
+
+    /// Where the hashes of each calendar's inputs are kept between runs. It starts with a `.` so
+    /// that it doesn't get published alongside the calendars.
+    pub fn cache_path(&self) -> PathBuf {
+        self.output_path(".cache.json")
+    }
+
+    /// Where the manifest of all the output files is written.
+    pub fn manifest_path(&self) -> PathBuf {
+        self.output_path("manifest.json")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::structs::{Args, ExportFormat};
+    use clap::Parser;
+    use std::fs::{remove_file, write};
+    use std::path::PathBuf;
+
+    #[test]
+    fn missing_settings_use_defaults() {
+        let config: Config = toml::from_str(
+            r#"
+output_dir = "/tmp/calendars"
+
+[outputs]
+parquet_files = true
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.output_dir, PathBuf::from("/tmp/calendars"));
+        assert!(config.outputs.parquet_files);
+        assert!(config.outputs.ics_files);
+        assert_eq!(config.generated_dir, PathBuf::from("generated"));
+        assert_eq!(config.policies, Config::default().policies);
+    }
+
+    #[test]
+    fn err_if_unknown_setting() {
+        assert!(toml::from_str::<Config>("output_directory = \"calendars\"").is_err());
+    }
+
+    #[test]
+    fn err_if_explicit_file_missing() {
+        assert!(Config::read(Some(&PathBuf::from("does-not-exist.toml"))).is_err());
+    }
+
+    #[test]
+    fn reads_explicit_file() {
+        let path = std::env::temp_dir().join("eskom-calendar-test-config.toml");
+        write(&path, "generated_dir = \"schedules\"\n").unwrap();
+        let config = Config::read(Some(&path)).unwrap();
+        remove_file(&path).unwrap();
+        assert_eq!(config.generated_dir, PathBuf::from("schedules"));
+    }
+
+    #[test]
+    fn command_line_overrides_config() {
+        let config: Config = toml::from_str(
+            r#"
+output_dir = "from-config"
+
+[outputs]
+csv_file = false
+search_index = false
+"#,
+        )
+        .unwrap();
+        let args = Args::parse_from([
+            "eskom-calendar",
+            "--output-dir",
+            "from-cli",
+            "--output-csv-file=true",
+        ]);
+        let config = config
+            .with_overrides(&args.paths)
+            .with_generate_args(&args.generate);
+        assert_eq!(config.output_dir, PathBuf::from("from-cli"));
+        assert!(config.outputs.csv_file);
+        assert!(!config.outputs.search_index);
+        assert_eq!(
+            config.ics_path("city-of-cape-town-area-1"),
+            PathBuf::from("from-cli/city-of-cape-town-area-1.ics")
+        );
+    }
+
+    #[test]
+    fn export_only_writes_given_formats() {
+        let config = Config::default().with_export_formats(&[ExportFormat::Parquet]);
+        assert!(config.outputs.parquet_files);
+        assert!(!config.outputs.ics_files);
+        assert!(!config.outputs.csv_file);
+        assert!(!config.outputs.search_index);
+    }
+}
@@ -0,0 +1,313 @@
+//! Compares the outages calculated from two versions of the inputs, so that reviewers can see the
+//! effect of a change to `manually_specified.yaml` (or to the schedules in `generated/`) before
+//! it's merged.
+use crate::config::Config;
+use crate::structs::{ManuallyInputSchedule, PowerOutage};
+use crate::{calculate_power_outages, fmt, read, source, BoxedError};
+use log::{error, info};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+/// At most this many areas have their outages listed in full, so that the summary fits in a PR
+/// comment. The rest are still counted in the table.
+const MAX_DETAILED_AREAS: usize = 20;
+
+/// How the outages of one area differ between the old and the new inputs.
+#[derive(Debug, Default, PartialEq)]
+pub struct AreaDiff {
+    pub area_name: String,
+    /// Outages which only exist in the new inputs
+    pub added: Vec<PowerOutage>,
+    /// Outages which only exist in the old inputs
+    pub removed: Vec<PowerOutage>,
+    /// Outages which start at the same time but differ in some other way, as (old, new) pairs
+    pub modified: Vec<(PowerOutage, PowerOutage)>,
+}
+
+impl AreaDiff {
+    fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// Read the schedules and changes from `path`, which is either a YAML file (in which case the
+/// configured schedules are used) or a directory laid out like this repository, such as another git
+/// revision checked out with `git worktree add`.
+fn read_inputs(
+    path: &Path,
+    config: &Config,
+) -> Result<(Vec<PathBuf>, ManuallyInputSchedule), BoxedError> {
+    let (yaml, generated, area_metadata) = if path.is_dir() {
+        (
+            path.join(&config.manually_specified),
+            path.join(&config.generated_dir),
+            path.join(&config.area_metadata),
+        )
+    } else {
+        (
+            path.to_path_buf(),
+            config.generated_dir.clone(),
+            config.area_metadata.clone(),
+        )
+    };
+    info!("Reading inputs from {yaml:?} and {generated:?}");
+    let paths = read::get_schedule_paths(&generated)?;
+    let metadata = read::read_area_metadata(&area_metadata)?;
+    let manually_specified = read::read_manually_specified(&yaml, &metadata)?;
+    Ok((paths, manually_specified))
+}
+
+/// Calculate the outages of every area, sorted by their start time.
+fn outages_by_area(
+    paths: &[PathBuf],
+    manually_specified: &ManuallyInputSchedule,
+) -> BTreeMap<String, Vec<PowerOutage>> {
+    paths
+        .par_iter()
+        .filter_map(|path| {
+            let area_name = fmt::path_to_area_name(path).ok()?;
+            let outages = source::read_schedule(path).and_then(|schedule| {
+                calculate_power_outages(&area_name, schedule.sheddings, manually_specified)
+            });
+            match outages {
+                Ok((mut outages, _last_finsh)) => {
+                    outages.sort();
+                    Some((area_name, outages))
+                }
+                Err(e) => {
+                    error!("Error while calculating power outages for {area_name}: {e}");
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Compare the outages of one area. Identical outages are ignored, outages which start at the
+/// same time are paired up as modifications, and everything else was either added or removed.
+pub fn diff_area(area_name: &str, old: &[PowerOutage], new: &[PowerOutage]) -> AreaDiff {
+    let mut removed = old
+        .iter()
+        .filter(|outage| !new.contains(outage))
+        .cloned()
+        .collect::<Vec<_>>();
+    let mut added = new
+        .iter()
+        .filter(|outage| !old.contains(outage))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let mut modified = vec![];
+    removed.retain(|old_outage| {
+        match added
+            .iter()
+            .position(|new_outage| new_outage.start == old_outage.start)
+        {
+            Some(i) => {
+                modified.push((old_outage.clone(), added.remove(i)));
+                false
+            }
+            None => true,
+        }
+    });
+
+    AreaDiff {
+        area_name: area_name.to_owned(),
+        added,
+        removed,
+        modified,
+    }
+}
+
+/// Compare every area's outages, only returning the areas which changed.
+pub fn diff_outages(
+    old: &BTreeMap<String, Vec<PowerOutage>>,
+    new: &BTreeMap<String, Vec<PowerOutage>>,
+) -> Vec<AreaDiff> {
+    let area_names = old.keys().chain(new.keys()).collect::<BTreeSet<_>>();
+    area_names
+        .into_iter()
+        .map(|area_name| {
+            diff_area(
+                area_name,
+                old.get(area_name).map_or(&[], Vec::as_slice),
+                new.get(area_name).map_or(&[], Vec::as_slice),
+            )
+        })
+        .filter(|diff| !diff.is_empty())
+        .collect()
+}
+
+/// Calculate the outages from the `old` and `new` inputs, and print a Markdown summary of how they
+/// differ which can be posted as a PR comment.
+pub fn print_diff(old: &Path, new: &Path, config: &Config) -> Result<(), BoxedError> {
+    let (old_paths, old_schedule) = read_inputs(old, config)?;
+    let (new_paths, new_schedule) = read_inputs(new, config)?;
+    let diffs = diff_outages(
+        &outages_by_area(&old_paths, &old_schedule),
+        &outages_by_area(&new_paths, &new_schedule),
+    );
+    println!("{}", DiffSummary(&diffs));
+    Ok(())
+}
+
+/// A Markdown summary of some area diffs.
+pub struct DiffSummary<'a>(pub &'a [AreaDiff]);
+
+impl Display for DiffSummary<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let diffs = self.0;
+        writeln!(f, "### Loadshedding changes")?;
+        writeln!(f)?;
+        if diffs.is_empty() {
+            return write!(f, "No outages were added, removed or modified.");
+        }
+        let count = |n: fn(&AreaDiff) -> usize| diffs.iter().map(n).sum::<usize>();
+        writeln!(
+            f,
+            "{} areas affected: {} outages added, {} removed and {} modified.",
+            diffs.len(),
+            count(|d| d.added.len()),
+            count(|d| d.removed.len()),
+            count(|d| d.modified.len()),
+        )?;
+        writeln!(f)?;
+        writeln!(f, "| Area | Added | Removed | Modified |")?;
+        writeln!(f, "|------|------:|--------:|---------:|")?;
+        for diff in diffs {
+            writeln!(
+                f,
+                "| {} | {} | {} | {} |",
+                diff.area_name,
+                diff.added.len(),
+                diff.removed.len(),
+                diff.modified.len()
+            )?;
+        }
+
+        for diff in diffs.iter().take(MAX_DETAILED_AREAS) {
+            writeln!(f)?;
+            writeln!(f, "<details><summary>{}</summary>", diff.area_name)?;
+            writeln!(f)?;
+            for outage in &diff.added {
+                writeln!(f, "- Added: {}", outage_line(outage))?;
+            }
+            for outage in &diff.removed {
+                writeln!(f, "- Removed: {}", outage_line(outage))?;
+            }
+            for (old, new) in &diff.modified {
+                writeln!(
+                    f,
+                    "- Modified: {} is now {}",
+                    outage_line(old),
+                    outage_line(new)
+                )?;
+            }
+            writeln!(f)?;
+            writeln!(f, "</details>")?;
+        }
+        if diffs.len() > MAX_DETAILED_AREAS {
+            writeln!(f)?;
+            writeln!(f, "...and {} more areas.", diffs.len() - MAX_DETAILED_AREAS)?;
+        }
+        Ok(())
+    }
+}
+
+/// A one-line summary of an outage, for use in a Markdown list.
+fn outage_line(outage: &PowerOutage) -> String {
+    format!(
+        "stage {} on {} to {}",
+        outage.stage,
+        outage.start.format("%a %d %h %Y %H:%M"),
+        outage.finsh.format("%H:%M"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff::{diff_area, diff_outages, DiffSummary};
+    use crate::structs::PowerOutage;
+    use chrono::DateTime;
+    use std::collections::BTreeMap;
+
+    fn outage(stage: u8, start: &str, finsh: &str) -> PowerOutage {
+        PowerOutage {
+            area_name: "test-area".to_owned(),
+            stage,
+            start: DateTime::parse_from_rfc3339(start).unwrap(),
+            finsh: DateTime::parse_from_rfc3339(finsh).unwrap(),
+            source: "test source".to_owned(),
+            cancelled: false,
+            tentative: false,
+        }
+    }
+
+    #[test]
+    fn identical_outages_are_ignored() {
+        let outages = vec![outage(
+            2,
+            "2023-05-29T10:00:00+02:00",
+            "2023-05-29T12:00:00+02:00",
+        )];
+        let diff = diff_area("test-area", &outages, &outages);
+        assert!(diff.added.is_empty() && diff.removed.is_empty() && diff.modified.is_empty());
+    }
+
+    #[test]
+    fn same_start_is_modified() {
+        let old = vec![
+            outage(2, "2023-05-29T10:00:00+02:00", "2023-05-29T12:00:00+02:00"),
+            outage(2, "2023-05-29T18:00:00+02:00", "2023-05-29T20:00:00+02:00"),
+        ];
+        let new = vec![
+            outage(4, "2023-05-29T10:00:00+02:00", "2023-05-29T12:30:00+02:00"),
+            outage(4, "2023-05-30T02:00:00+02:00", "2023-05-30T04:30:00+02:00"),
+        ];
+        let diff = diff_area("test-area", &old, &new);
+        assert_eq!(diff.modified, vec![(old[0].clone(), new[0].clone())]);
+        assert_eq!(diff.removed, vec![old[1].clone()]);
+        assert_eq!(diff.added, vec![new[1].clone()]);
+    }
+
+    #[test]
+    fn only_changed_areas_are_returned() {
+        let a = outage(2, "2023-05-29T10:00:00+02:00", "2023-05-29T12:00:00+02:00");
+        let b = outage(4, "2023-05-29T18:00:00+02:00", "2023-05-29T20:00:00+02:00");
+        let old = BTreeMap::from([
+            ("unchanged".to_owned(), vec![a.clone()]),
+            ("changed".to_owned(), vec![a.clone()]),
+        ]);
+        let new = BTreeMap::from([
+            ("unchanged".to_owned(), vec![a.clone()]),
+            ("changed".to_owned(), vec![a]),
+            ("new-area".to_owned(), vec![b.clone()]),
+        ]);
+        let diffs = diff_outages(&old, &new);
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].area_name, "new-area");
+        assert_eq!(diffs[0].added, vec![b]);
+    }
+
+    #[test]
+    fn summary_counts_outages() {
+        let old = vec![outage(
+            2,
+            "2023-05-29T10:00:00+02:00",
+            "2023-05-29T12:00:00+02:00",
+        )];
+        let summary = DiffSummary(&[diff_area("test-area", &old, &[])]).to_string();
+        assert!(summary.contains("1 areas affected: 0 outages added, 1 removed and 0 modified."));
+        assert!(summary.contains("| test-area | 0 | 1 | 0 |"));
+        assert!(summary.contains("- Removed: stage 2 on Mon 29 May 2023 10:00 to 12:00"));
+    }
+
+    #[test]
+    fn summary_of_nothing() {
+        assert!(DiffSummary(&[])
+            .to_string()
+            .contains("No outages were added, removed or modified."));
+    }
+}
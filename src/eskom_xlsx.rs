@@ -0,0 +1,536 @@
+//! Imports Eskom's `*_LS.xlsx` loadshedding spreadsheets, which are the source of the
+//! `eskom-direct-*` schedules. Each spreadsheet has a `Schedule` sheet (which blocks are shed at
+//! which times on each day of the month) and an `SP_List` sheet (which suburbs are in which
+//! block). The schedules are written to `generated/` and the suburbs to `area_metadata.yaml`.
+use crate::config::Config;
+use crate::manifest::write_atomically;
+use crate::BoxedError;
+use calamine::{open_workbook_auto, Data, Range, Reader};
+use chrono::{Duration, NaiveTime, Timelike};
+use log::{info, warn};
+use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::fs::read_to_string;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Where the spreadsheets can be downloaded from, which is recorded in `area_metadata.yaml`.
+const SOURCE: &str =
+    "https://www.eskom.co.za/distribution/wp-content/uploads/2022/09/WesternCape_LS.xlsx";
+const SOURCE_INFO: &str = "https://www.eskom.co.za/distribution/customer-service/outages/downloadable-loadshedding-spreadsheets-for-eskom-customers/";
+
+/// The highest stage in the spreadsheets. Each stage includes all the outages of the stages below
+/// it.
+const MAX_STAGE: u8 = 8;
+
+/// Maps the spreadsheets' file names (without the `_LS.xlsx`) to the province names used in
+/// `area_metadata.yaml`.
+const PROVINCES: &[(&str, &str)] = &[
+    ("EasternCape", "eastern-cape"),
+    ("FreeState", "free-state"),
+    ("Gauteng", "gauteng"),
+    ("KwaZulu-Natal", "kwazulu-natal"),
+    ("Limpopo", "limpopo"),
+    ("Mpumalanga", "mpumalanga"),
+    ("NorthernCape", "northern-cape"),
+    ("NorthWest", "north-west"),
+    ("WesternCape", "western-cape"),
+];
+
+/// One outage of one block, as a row of a `generated/` CSV. The fields are in the same order as
+/// the rows are sorted in canonical CSVs (see `normalise.rs`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Slot {
+    pub date_of_month: u8,
+    pub start: NaiveTime,
+    pub finsh: NaiveTime,
+    pub stage: u8,
+}
+
+impl Display for Slot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{},{},{},{}",
+            self.date_of_month,
+            self.start.format("%H:%M"),
+            self.finsh.format("%H:%M"),
+            self.stage
+        )
+    }
+}
+
+/// One suburb from the `SP_List` sheet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Suburb {
+    pub province: String,
+    pub municipality: String,
+    pub name: String,
+    pub block: u32,
+    /// Eskom marks blocks whose outages start on odd hours (01:00, 03:00, etc) with a `U`
+    pub odd: bool,
+}
+
+/// The name of the `generated/` CSV (without the extension) of a block.
+fn area_name(block: u32, odd: bool) -> String {
+    format!("eskom-direct-{block}-{}", if odd { "odd" } else { "even" })
+}
+
+/// The province which the spreadsheet at `path` (like `WesternCape_LS.xlsx`) covers.
+fn province_of(path: &Path) -> Result<&'static str, BoxedError> {
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default();
+    let stem = stem.strip_suffix("_LS").unwrap_or(stem);
+    PROVINCES
+        .iter()
+        .find(|(prefix, _province)| *prefix == stem)
+        .map(|(_prefix, province)| *province)
+        .ok_or_else(|| format!("Don't know which province {path:?} covers").into())
+}
+
+/// A cell as a number, if it is one.
+fn cell_number(cell: &Data) -> Option<f64> {
+    match cell {
+        Data::Int(i) => Some(*i as f64),
+        Data::Float(f) => Some(*f),
+        Data::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// A cell as a time of day. Excel stores times as a fraction of a day, and sometimes midnight is
+/// stored as 1900-01-01 00:00 (which is the number 1), so only the fraction is used.
+fn cell_time(cell: &Data) -> Option<NaiveTime> {
+    let fraction = match cell {
+        Data::DateTime(dt) => dt.as_f64(),
+        Data::String(s) | Data::DateTimeIso(s) => {
+            let s = s.trim();
+            let s = s.rsplit_once('T').map_or(s, |(_date, time)| time);
+            return NaiveTime::parse_from_str(s, "%H:%M:%S")
+                .or_else(|_| NaiveTime::parse_from_str(s, "%H:%M"))
+                .ok();
+        }
+        cell => cell_number(cell)?,
+    };
+    let minutes = (fraction.fract() * 24.0 * 60.0).round() as u32 % (24 * 60);
+    NaiveTime::from_hms_opt(minutes / 60, minutes % 60, 0)
+}
+
+/// Read the `Schedule` sheet into the slots of each block. The sheet has a row for each time slot
+/// and stage, with the start and finish times in the first two columns (only filled in on the
+/// first row of each time slot), the stage in the third column, and the block which is shed on
+/// each day of the month in the next 31 columns. Rows which don't look like that (such as the
+/// titles and legend) are skipped.
+pub fn read_schedule_sheet(rows: &[Vec<Data>]) -> BTreeMap<u32, Vec<Slot>> {
+    let mut blocks: BTreeMap<u32, Vec<Slot>> = BTreeMap::new();
+    let (mut start, mut finsh) = (None, None);
+    for row in rows {
+        start = row.first().and_then(cell_time).or(start);
+        finsh = row.get(1).and_then(cell_time).or(finsh);
+        let stage = row.get(2).and_then(cell_number);
+        let days = row
+            .iter()
+            .skip(3)
+            .take(31)
+            .map(cell_number)
+            .collect::<Option<Vec<_>>>();
+        let (Some(start), Some(finsh), Some(stage), Some(days)) = (start, finsh, stage, days)
+        else {
+            continue;
+        };
+        if days.len() != 31 || !(1.0..=MAX_STAGE as f64).contains(&stage) {
+            continue;
+        }
+        // Loadshedding officially only ends at HH:30, not at HH:00
+        let finsh = finsh.with_minute(30).unwrap();
+        for (day, block) in days.into_iter().enumerate() {
+            blocks.entry(block as u32).or_default().push(Slot {
+                date_of_month: day as u8 + 1,
+                stage: stage as u8,
+                start,
+                finsh,
+            });
+        }
+    }
+    blocks
+}
+
+/// Each stage includes all the outages of the stages below it, but the spreadsheet only lists the
+/// outages which are added at each stage.
+pub fn cumulative_stages(slots: &[Slot]) -> Vec<Slot> {
+    slots
+        .iter()
+        .flat_map(|slot| (slot.stage..=MAX_STAGE).map(|stage| Slot { stage, ..*slot }))
+        .collect()
+}
+
+/// Combine outages like 02:00-04:30 and 04:00-06:30 on the same day and stage into one outage
+/// like 02:00-06:30, and sort them like canonical `generated/` CSVs.
+pub fn merge_overlapping(slots: &[Slot]) -> Vec<Slot> {
+    let mut sorted = slots.to_vec();
+    sorted.sort_by_key(|slot| (slot.stage, slot.date_of_month, slot.start));
+    let mut merged: Vec<Slot> = vec![];
+    for slot in sorted {
+        match merged.last_mut() {
+            Some(prev)
+                if prev.stage == slot.stage
+                    && prev.date_of_month == slot.date_of_month
+                    && prev.start <= slot.finsh
+                    && prev.finsh >= slot.start =>
+            {
+                prev.finsh = prev.finsh.max(slot.finsh);
+            }
+            _ => merged.push(slot),
+        }
+    }
+    merged.sort();
+    merged
+}
+
+/// The schedule of an odd block, which is the same as the even block but an hour later.
+fn odd_slots(slots: &[Slot]) -> Vec<Slot> {
+    let hour = Duration::hours(1);
+    let mut odd = slots
+        .iter()
+        .map(|slot| Slot {
+            start: slot.start + hour,
+            finsh: slot.finsh + hour,
+            ..*slot
+        })
+        .collect::<Vec<_>>();
+    odd.sort();
+    odd
+}
+
+/// The contents of the `generated/` CSV for some slots.
+pub fn schedule_csv(slots: &[Slot]) -> String {
+    let mut csv = "date_of_month,start_time,finsh_time,stage\n".to_owned();
+    for slot in slots {
+        csv.push_str(&format!("{slot}\n"));
+    }
+    csv
+}
+
+/// The contents of the `generated/` CSVs of every block, both even and odd, by area name.
+pub fn schedule_csvs(blocks: &BTreeMap<u32, Vec<Slot>>) -> BTreeMap<String, String> {
+    blocks
+        .iter()
+        .flat_map(|(block, slots)| {
+            let even = merge_overlapping(&cumulative_stages(slots));
+            let odd = odd_slots(&even);
+            [
+                (area_name(*block, false), schedule_csv(&even)),
+                (area_name(*block, true), schedule_csv(&odd)),
+            ]
+        })
+        .collect()
+}
+
+/// Read the `SP_List` sheet, which has a header row with (among others) the columns `MP_NAME`
+/// (the municipality), `SP_NAME` (the suburb), `BLOCK` and `TYPE`.
+pub fn read_suburb_sheet(rows: &[Vec<Data>], province: &str) -> Result<Vec<Suburb>, BoxedError> {
+    let header = rows.first().ok_or("The SP_List sheet is empty")?;
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|cell| cell.to_string().trim() == name)
+            .ok_or_else(|| format!("The SP_List sheet has no {name} column"))
+    };
+    let (municipality, name, block, kind) = (
+        column("MP_NAME")?,
+        column("SP_NAME")?,
+        column("BLOCK")?,
+        column("TYPE")?,
+    );
+    // Eskom suffixes suburbs with the block they're in, like "Aardoff (2)"
+    let block_suffix = Regex::new(r"\s\(\d+\)").unwrap();
+    let slug = |s: &str| s.trim().to_lowercase().replace(' ', "-");
+
+    Ok(rows
+        .iter()
+        .skip(1)
+        .filter_map(|row| {
+            Some(Suburb {
+                province: province.to_owned(),
+                municipality: slug(&row.get(municipality)?.to_string()),
+                name: slug(&block_suffix.replace_all(&row.get(name)?.to_string(), "")),
+                block: cell_number(row.get(block)?)? as u32,
+                odd: row.get(kind)?.to_string().trim().ends_with('U'),
+            })
+        })
+        .filter(|suburb| !suburb.name.is_empty())
+        .collect())
+}
+
+/// The `area_metadata.yaml` entry of every block, by calendar name. These are formatted by hand
+/// (rather than with serde) to match the rest of `area_metadata.yaml`, which keeps the long lists
+/// of suburbs on one line.
+pub fn metadata_entries(suburbs: &[Suburb]) -> BTreeMap<String, String> {
+    let mut blocks: BTreeMap<String, BTreeMap<(&str, &str), BTreeSet<&str>>> = BTreeMap::new();
+    for suburb in suburbs {
+        blocks
+            .entry(format!("{}.ics", area_name(suburb.block, suburb.odd)))
+            .or_default()
+            .entry((&suburb.province, &suburb.municipality))
+            .or_default()
+            .insert(&suburb.name);
+    }
+    blocks
+        .into_iter()
+        .map(|(calendar_name, groups)| {
+            let mut entry = format!(
+                "- calendar_name: {calendar_name}\n  provider: eskom\n  source: {SOURCE}\n  source_info: {SOURCE_INFO}\n  areas:\n"
+            );
+            for ((province, municipality), names) in groups {
+                let names = names
+                    .iter()
+                    .map(|name| format!("\"{name}\""))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                entry.push_str(&format!(
+                    "  - province: {province}\n    municipality: \"{municipality}\"\n    name: [{names}]\n"
+                ));
+            }
+            (calendar_name, entry)
+        })
+        .collect()
+}
+
+/// Replace the entries in `yaml` (the contents of `area_metadata.yaml`) which have the same
+/// calendar names as `entries`, leaving every other entry as it was. Entries which aren't in
+/// `yaml` yet are added to the end.
+pub fn replace_entries(yaml: &str, entries: &BTreeMap<String, String>) -> String {
+    let mut remaining = entries.clone();
+    let mut result = String::new();
+    let mut skipping = false;
+    for line in yaml.lines() {
+        if let Some(calendar_name) = line.strip_prefix("- calendar_name: ") {
+            skipping = false;
+            if let Some(entry) = remaining.remove(calendar_name.trim()) {
+                result.push_str(&entry);
+                skipping = true;
+            }
+        } else if !line.starts_with(' ') && !line.starts_with('-') {
+            // The end of the list of entries
+            skipping = false;
+        }
+        if !skipping {
+            result.push_str(line);
+            result.push('\n');
+        }
+    }
+    for entry in remaining.values() {
+        result.push_str(entry);
+    }
+    result
+}
+
+/// Read the rows of the sheet called `name` in the spreadsheet at `path`.
+fn read_sheet(path: &Path, name: &str) -> Result<Vec<Vec<Data>>, BoxedError> {
+    let mut workbook = open_workbook_auto(path)?;
+    let range: Range<Data> = workbook
+        .worksheet_range(name)
+        .map_err(|e| format!("Couldn't read the {name} sheet of {path:?}: {e}"))?;
+    Ok(range.rows().map(|row| row.to_vec()).collect())
+}
+
+/// Write `contents` to `path`, or if `verify` is set, only check that `path` already contains
+/// them. Returns whether `path` is (now) up to date.
+fn write_or_verify(path: &Path, contents: &str, verify: bool) -> Result<bool, BoxedError> {
+    let existing = read_to_string(path).unwrap_or_default();
+    if existing == contents {
+        return Ok(true);
+    }
+    if !verify {
+        info!("Writing {path:?}");
+        write_atomically(path, |file| Ok(file.write_all(contents.as_bytes())?))?;
+        return Ok(true);
+    }
+    println!("{path:?} differs from the spreadsheets:");
+    let (old, new) = (
+        existing.lines().collect::<BTreeSet<_>>(),
+        contents.lines().collect::<BTreeSet<_>>(),
+    );
+    for line in old.difference(&new) {
+        println!("- {line}");
+    }
+    for line in new.difference(&old) {
+        println!("+ {line}");
+    }
+    Ok(false)
+}
+
+/// Import the given spreadsheets into `generated/` and `area_metadata.yaml`. Every province's
+/// spreadsheet is needed to get all the suburbs of each block. If `verify` is set, nothing is
+/// written and it's an error if the existing files don't match the spreadsheets.
+pub fn import(spreadsheets: &[PathBuf], verify: bool, config: &Config) -> Result<(), BoxedError> {
+    let mut blocks = None;
+    let mut suburbs = vec![];
+    for path in spreadsheets {
+        info!("Reading {path:?}");
+        let province = province_of(path)?;
+        let schedule = read_schedule_sheet(&read_sheet(path, "Schedule")?);
+        if schedule.is_empty() {
+            return Err(format!("Couldn't find any schedules in {path:?}").into());
+        }
+        // Every province uses the same schedule, so only the first one is used
+        match &blocks {
+            None => blocks = Some(schedule),
+            Some(blocks) if *blocks != schedule => {
+                warn!("The schedule in {path:?} differs from the other spreadsheets, ignoring it")
+            }
+            Some(_) => {}
+        }
+        suburbs.extend(read_suburb_sheet(&read_sheet(path, "SP_List")?, province)?);
+    }
+    let blocks = blocks.ok_or("No spreadsheets were given")?;
+
+    let mut up_to_date = true;
+    for (area_name, csv) in schedule_csvs(&blocks) {
+        let path = config.generated_dir.join(format!("{area_name}.csv"));
+        up_to_date &= write_or_verify(&path, &csv, verify)?;
+    }
+    let yaml = read_to_string(&config.area_metadata)?;
+    let yaml = replace_entries(&yaml, &metadata_entries(&suburbs));
+    up_to_date &= write_or_verify(&config.area_metadata, &yaml, verify)?;
+
+    if !up_to_date {
+        return Err("The existing schedules don't match the spreadsheets".into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eskom_xlsx::{
+        cumulative_stages, merge_overlapping, metadata_entries, read_schedule_sheet,
+        read_suburb_sheet, replace_entries, schedule_csvs, Slot,
+    };
+    use calamine::Data;
+    use chrono::NaiveTime;
+    use std::collections::BTreeMap;
+
+    fn time(s: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(s, "%H:%M").unwrap()
+    }
+
+    fn slot(date_of_month: u8, stage: u8, start: &str, finsh: &str) -> Slot {
+        Slot {
+            date_of_month,
+            stage,
+            start: time(start),
+            finsh: time(finsh),
+        }
+    }
+
+    /// A row of the `Schedule` sheet, where every day has the same block.
+    fn row(start: Data, finsh: Data, stage: f64, block: f64) -> Vec<Data> {
+        let mut row = vec![start, finsh, Data::Float(stage)];
+        row.extend((1..=31).map(|_| Data::Float(block)));
+        row
+    }
+
+    #[test]
+    fn reads_schedule_rows() {
+        let rows = vec![
+            vec![Data::String("Loadshedding schedule".to_owned())],
+            // 00:00 to 02:00, with the times stored as fractions of a day
+            row(Data::Float(0.0), Data::Float(2.0 / 24.0), 1.0, 1.0),
+            // The times are only given on the first row of each slot
+            row(Data::Empty, Data::Empty, 2.0, 2.0),
+            // Midnight is sometimes stored as 1900-01-01 00:00
+            row(Data::Float(22.0 / 24.0), Data::Float(1.0), 1.0, 3.0),
+        ];
+        let blocks = read_schedule_sheet(&rows);
+        assert_eq!(blocks.keys().collect::<Vec<_>>(), vec![&1, &2, &3]);
+        assert_eq!(blocks[&1][0], slot(1, 1, "00:00", "02:30"));
+        assert_eq!(blocks[&2][30], slot(31, 2, "00:00", "02:30"));
+        assert_eq!(blocks[&3][0], slot(1, 1, "22:00", "00:30"));
+    }
+
+    #[test]
+    fn higher_stages_include_lower_stages() {
+        let slots = cumulative_stages(&[slot(1, 7, "00:00", "02:30")]);
+        assert_eq!(
+            slots,
+            vec![slot(1, 7, "00:00", "02:30"), slot(1, 8, "00:00", "02:30")]
+        );
+    }
+
+    #[test]
+    fn merges_overlapping_slots() {
+        let merged = merge_overlapping(&[
+            slot(1, 5, "04:00", "06:30"),
+            slot(1, 5, "02:00", "04:30"),
+            slot(1, 5, "08:00", "10:30"),
+            slot(1, 6, "06:00", "08:30"),
+            slot(2, 5, "06:00", "08:30"),
+        ]);
+        assert_eq!(
+            merged,
+            vec![
+                slot(1, 5, "02:00", "06:30"),
+                slot(1, 6, "06:00", "08:30"),
+                slot(1, 5, "08:00", "10:30"),
+                slot(2, 5, "06:00", "08:30"),
+            ]
+        );
+    }
+
+    #[test]
+    fn odd_blocks_are_an_hour_later() {
+        let blocks = BTreeMap::from([(4, vec![slot(1, 8, "22:00", "00:30")])]);
+        let csvs = schedule_csvs(&blocks);
+        assert_eq!(
+            csvs["eskom-direct-4-even"],
+            "date_of_month,start_time,finsh_time,stage\n1,22:00,00:30,8\n"
+        );
+        assert_eq!(
+            csvs["eskom-direct-4-odd"],
+            "date_of_month,start_time,finsh_time,stage\n1,23:00,01:30,8\n"
+        );
+    }
+
+    #[test]
+    fn reads_suburbs_into_metadata() {
+        let s = |s: &str| Data::String(s.to_owned());
+        let rows = vec![
+            vec![s("MP_NAME"), s("SP_NAME"), s("BLOCK"), s("TYPE")],
+            vec![s("Kopanong"), s("Aandenking (1)"), Data::Float(1.0), s("E")],
+            vec![s("Kopanong"), s("Allep"), Data::Float(1.0), s("E")],
+            vec![s("Mangaung"), s("Bloubospan A"), Data::Float(1.0), s("U")],
+        ];
+        let suburbs = read_suburb_sheet(&rows, "free-state").unwrap();
+        assert_eq!(suburbs[0].name, "aandenking");
+        let entries = metadata_entries(&suburbs);
+        assert_eq!(
+            entries.keys().collect::<Vec<_>>(),
+            vec!["eskom-direct-1-even.ics", "eskom-direct-1-odd.ics"]
+        );
+        assert!(entries["eskom-direct-1-even.ics"].ends_with(
+            "  - province: free-state\n    municipality: \"kopanong\"\n    name: [\"aandenking\",\"allep\"]\n"
+        ));
+    }
+
+    #[test]
+    fn only_replaces_matching_entries() {
+        let yaml = "---\narea_details:\n- calendar_name: eskom-direct-1-even.ics\n  provider: eskom\n  areas:\n  - province: old\n- calendar_name: other.ics\n  areas: []\n";
+        let entries = BTreeMap::from([
+            (
+                "eskom-direct-1-even.ics".to_owned(),
+                "- calendar_name: eskom-direct-1-even.ics\n  areas: []\n".to_owned(),
+            ),
+            (
+                "eskom-direct-2-even.ics".to_owned(),
+                "- calendar_name: eskom-direct-2-even.ics\n  areas: []\n".to_owned(),
+            ),
+        ]);
+        assert_eq!(
+            replace_entries(yaml, &entries),
+            "---\narea_details:\n- calendar_name: eskom-direct-1-even.ics\n  areas: []\n- calendar_name: other.ics\n  areas: []\n- calendar_name: eskom-direct-2-even.ics\n  areas: []\n"
+        );
+    }
+}
@@ -0,0 +1,282 @@
+//! Exports of the calculated power outages in formats better suited to analytics than the
+//! `machine_friendly.csv` file.
+use crate::manifest::write_atomically;
+use crate::structs::{AreaDetail, AreaMetadata, PowerOutage};
+use crate::BoxedError;
+use arrow::array::{
+    ArrayRef, BooleanArray, Int64Array, StringArray, TimestampSecondArray, UInt8Array,
+};
+use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use arrow::record_batch::RecordBatch;
+use log::info;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::create_dir_all;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// All outage times are in South African Standard Time.
+const TIMEZONE_SAST: &str = "+02:00";
+
+/// The arrow schema used for every Parquet file.
+pub fn power_outage_schema() -> Schema {
+    let timestamp = DataType::Timestamp(TimeUnit::Second, Some(TIMEZONE_SAST.into()));
+    Schema::new(vec![
+        Field::new("area_name", DataType::Utf8, false),
+        Field::new("province", DataType::Utf8, true),
+        Field::new("municipality", DataType::Utf8, true),
+        Field::new("stage", DataType::UInt8, false),
+        Field::new("start", timestamp.clone(), false),
+        Field::new("finsh", timestamp, false),
+        Field::new("duration_minutes", DataType::Int64, false),
+        Field::new("source", DataType::Utf8, false),
+        Field::new("cancelled", DataType::Boolean, false),
+        Field::new("tentative", DataType::Boolean, false),
+    ])
+}
+
+/// Convert some power outages into a single arrow `RecordBatch`, looking up the province and
+/// municipality of each outage's area in `metadata`. Calendars which span multiple provinces or
+/// municipalities have them joined with a `;`.
+pub fn power_outages_to_record_batch(
+    power_outages: &[PowerOutage],
+    metadata: &AreaMetadata,
+) -> Result<RecordBatch, BoxedError> {
+    let details: HashMap<&str, &AreaDetail> = metadata
+        .area_details
+        .iter()
+        .map(|detail| (detail.area_name(), detail))
+        .collect();
+    let lookup = |area_name: &str, f: fn(&AreaDetail) -> Vec<String>| {
+        details
+            .get(area_name)
+            .map(|detail| f(detail))
+            .filter(|items| !items.is_empty())
+            .map(|items| items.join(";"))
+    };
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from_iter_values(
+            power_outages.iter().map(|o| o.area_name.as_str()),
+        )),
+        Arc::new(StringArray::from_iter(
+            power_outages
+                .iter()
+                .map(|o| lookup(&o.area_name, AreaDetail::provinces)),
+        )),
+        Arc::new(StringArray::from_iter(
+            power_outages
+                .iter()
+                .map(|o| lookup(&o.area_name, AreaDetail::municipalities)),
+        )),
+        Arc::new(UInt8Array::from_iter_values(
+            power_outages.iter().map(|o| o.stage),
+        )),
+        Arc::new(
+            TimestampSecondArray::from_iter_values(
+                power_outages.iter().map(|o| o.start.timestamp()),
+            )
+            .with_timezone(TIMEZONE_SAST),
+        ),
+        Arc::new(
+            TimestampSecondArray::from_iter_values(
+                power_outages.iter().map(|o| o.finsh.timestamp()),
+            )
+            .with_timezone(TIMEZONE_SAST),
+        ),
+        Arc::new(Int64Array::from_iter_values(
+            power_outages
+                .iter()
+                .map(|o| (o.finsh - o.start).num_minutes()),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            power_outages.iter().map(|o| o.source.as_str()),
+        )),
+        Arc::new(BooleanArray::from_iter(
+            power_outages.iter().map(|o| Some(o.cancelled)),
+        )),
+        Arc::new(BooleanArray::from_iter(
+            power_outages.iter().map(|o| Some(o.tentative)),
+        )),
+    ];
+    Ok(RecordBatch::try_new(
+        Arc::new(power_outage_schema()),
+        columns,
+    )?)
+}
+
+/// Write the power outages to `dir` as Parquet files, partitioned by the month in which each outage
+/// starts. The files are laid out like `dir/month=2023-05/outages.parquet` so they can be read as a
+/// hive-partitioned dataset. Returns the paths of the files which were written.
+pub fn write_outages_to_parquet(
+    power_outages: &[PowerOutage],
+    metadata: &AreaMetadata,
+    dir: &Path,
+) -> Result<Vec<PathBuf>, BoxedError> {
+    let mut by_month: BTreeMap<String, Vec<PowerOutage>> = BTreeMap::new();
+    for outage in power_outages {
+        by_month
+            .entry(outage.start.format("%Y-%m").to_string())
+            .or_default()
+            .push(outage.clone());
+    }
+
+    let props = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+
+    let mut paths = vec![];
+    for (month, mut outages) in by_month {
+        // Sort the outages so we have some kind of consistency of the output
+        outages.sort();
+        let partition = dir.join(format!("month={month}"));
+        create_dir_all(&partition)?;
+        let path = partition.join("outages.parquet");
+        info!("Writing {} outages to {path:?}", outages.len());
+
+        let batch = power_outages_to_record_batch(&outages, metadata)?;
+        write_atomically(&path, |file| {
+            let mut writer = ArrowWriter::try_new(file, batch.schema(), Some(props.clone()))?;
+            writer.write(&batch)?;
+            writer.close()?;
+            Ok(())
+        })?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::export::{power_outages_to_record_batch, write_outages_to_parquet};
+    use crate::structs::{AreaMetadata, PowerOutage};
+    use arrow::array::{Array, Int64Array, StringArray};
+    use chrono::DateTime;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use std::fs::{remove_dir_all, File};
+
+    fn outage(area_name: &str, start: &str, finsh: &str) -> PowerOutage {
+        PowerOutage {
+            area_name: area_name.to_owned(),
+            stage: 2,
+            start: DateTime::parse_from_rfc3339(start).unwrap(),
+            finsh: DateTime::parse_from_rfc3339(finsh).unwrap(),
+            source: "test source".to_owned(),
+            cancelled: false,
+            tentative: false,
+        }
+    }
+
+    fn metadata() -> AreaMetadata {
+        serde_yaml::from_str(
+            r#"
+area_details:
+- calendar_name: western-cape-stellenbosch.ics
+  province: western-cape
+  municipality: unknown
+  provider: eskom
+  source: https://example.com
+  source_info: https://example.com
+  areas: [{name: stellenbosch}]
+- calendar_name: eskom-direct-1-even.ics
+  provider: eskom
+  source: https://example.com
+  source_info: https://example.com
+  areas:
+  - province: gauteng
+    municipality: "emfuleni"
+    name: ["boipatong"]
+  - province: free-state
+    municipality: "kopanong"
+    name: ["aandenking","alicedale"]
+"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn looks_up_province_and_municipality() {
+        let outages = vec![
+            outage(
+                "western-cape-stellenbosch",
+                "2023-05-29T10:00:00+02:00",
+                "2023-05-29T12:30:00+02:00",
+            ),
+            outage(
+                "eskom-direct-1-even",
+                "2023-05-29T10:00:00+02:00",
+                "2023-05-29T12:00:00+02:00",
+            ),
+            outage(
+                "not-in-metadata",
+                "2023-05-29T10:00:00+02:00",
+                "2023-05-29T12:00:00+02:00",
+            ),
+        ];
+        let batch = power_outages_to_record_batch(&outages, &metadata()).unwrap();
+        let provinces = batch
+            .column_by_name("province")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(provinces.value(0), "western-cape");
+        assert_eq!(provinces.value(1), "free-state;gauteng");
+        assert!(provinces.is_null(2));
+
+        let durations = batch
+            .column_by_name("duration_minutes")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        assert_eq!(durations.values(), &[150, 120, 120]);
+    }
+
+    #[test]
+    fn partitions_by_month() {
+        let dir = std::env::temp_dir().join("eskom-calendar-test-parquet");
+        let _ = remove_dir_all(&dir);
+        let outages = vec![
+            outage(
+                "western-cape-stellenbosch",
+                "2023-05-31T22:00:00+02:00",
+                "2023-06-01T00:30:00+02:00",
+            ),
+            outage(
+                "western-cape-stellenbosch",
+                "2023-06-01T10:00:00+02:00",
+                "2023-06-01T12:30:00+02:00",
+            ),
+            outage(
+                "western-cape-stellenbosch",
+                "2023-06-02T10:00:00+02:00",
+                "2023-06-02T12:30:00+02:00",
+            ),
+        ];
+        let paths = write_outages_to_parquet(&outages, &metadata(), &dir).unwrap();
+        assert_eq!(
+            paths,
+            vec![
+                dir.join("month=2023-05/outages.parquet"),
+                dir.join("month=2023-06/outages.parquet"),
+            ]
+        );
+
+        let rows: Vec<usize> = paths
+            .iter()
+            .map(|path| {
+                ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap())
+                    .unwrap()
+                    .build()
+                    .unwrap()
+                    .map(|batch| batch.unwrap().num_rows())
+                    .sum()
+            })
+            .collect();
+        assert_eq!(rows, vec![1, 2]);
+        let _ = remove_dir_all(&dir);
+    }
+}
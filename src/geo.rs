@@ -0,0 +1,274 @@
+//! Optional geographic boundaries for each area, so that a GPS position can be resolved to an area
+//! without relying on (often ambiguous) suburb names.
+//!
+//! Boundaries are read from `boundaries/<area_name>.geojson`, where `<area_name>` lines up with the
+//! `generated/` CSV file names. Each file can contain a Geometry, a Feature or a FeatureCollection,
+//! and every Polygon or MultiPolygon in it is considered part of the area.
+use crate::manifest::write_atomically;
+use crate::query::AreaStatus;
+use crate::{fmt, BoxedError};
+use geojson::{Feature, FeatureCollection, GeoJson, Geometry, PolygonType, Value};
+use log::{info, warn};
+use std::fs::{read_dir, read_to_string};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// The polygons making up one area.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AreaBoundary {
+    pub area_name: String,
+    /// Each polygon is a list of linear rings of `[longitude, latitude]` positions. The first ring
+    /// is the exterior, and any others are holes.
+    pub polygons: Vec<PolygonType>,
+}
+
+impl AreaBoundary {
+    /// Create a boundary from some GeoJSON, ignoring everything that isn't a (Multi)Polygon.
+    pub fn from_geojson(area_name: &str, geojson: GeoJson) -> Self {
+        let geometries: Vec<Geometry> = match geojson {
+            GeoJson::Geometry(geometry) => vec![geometry],
+            GeoJson::Feature(feature) => feature.geometry.into_iter().collect(),
+            GeoJson::FeatureCollection(collection) => collection
+                .features
+                .into_iter()
+                .filter_map(|feature| feature.geometry)
+                .collect(),
+        };
+        let mut polygons = vec![];
+        for geometry in geometries {
+            collect_polygons(geometry.value, &mut polygons);
+        }
+        AreaBoundary {
+            area_name: area_name.to_owned(),
+            polygons,
+        }
+    }
+
+    /// Whether or not the point is inside this area. Points inside a hole are not in the area.
+    pub fn contains(&self, longitude: f64, latitude: f64) -> bool {
+        self.polygons.iter().any(|polygon| {
+            let mut rings = polygon.iter();
+            rings
+                .next()
+                .is_some_and(|exterior| ring_contains(exterior, longitude, latitude))
+                && !rings.any(|hole| ring_contains(hole, longitude, latitude))
+        })
+    }
+}
+
+fn collect_polygons(value: Value, polygons: &mut Vec<PolygonType>) {
+    match value {
+        Value::Polygon(polygon) => polygons.push(polygon),
+        Value::MultiPolygon(multi) => polygons.extend(multi),
+        Value::GeometryCollection(geometries) => {
+            for geometry in geometries {
+                collect_polygons(geometry.value, polygons);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Ray-casting point-in-polygon test for a single linear ring.
+fn ring_contains(ring: &[Vec<f64>], x: f64, y: f64) -> bool {
+    let mut inside = false;
+    for (a, b) in ring.iter().zip(ring.iter().cycle().skip(1)) {
+        let ((xa, ya), (xb, yb)) = ((a[0], a[1]), (b[0], b[1]));
+        if (ya > y) != (yb > y) && x < (xb - xa) * (y - ya) / (yb - ya) + xa {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+/// Read all the area boundaries in `dir`. Boundaries are optional, so if `dir` doesn't exist then
+/// no boundaries are returned.
+pub fn read_boundaries(dir: &Path) -> Result<Vec<AreaBoundary>, BoxedError> {
+    if !dir.is_dir() {
+        info!("No boundaries directory at {dir:?}");
+        return Ok(vec![]);
+    }
+    let mut boundaries = vec![];
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "geojson") {
+            continue;
+        }
+        let area_name = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or("Boundary path is not valid unicode")?;
+        let geojson = read_to_string(&path)?
+            .parse::<GeoJson>()
+            .map_err(|e| format!("Couldn't parse {path:?} as GeoJSON: {e}"))?;
+        let boundary = AreaBoundary::from_geojson(area_name, geojson);
+        if boundary.polygons.is_empty() {
+            warn!("{path:?} doesn't contain any polygons");
+        }
+        boundaries.push(boundary);
+    }
+    boundaries.sort_by(|a, b| a.area_name.cmp(&b.area_name));
+    info!("Read {} area boundaries from {dir:?}", boundaries.len());
+    Ok(boundaries)
+}
+
+/// Find the names of all the areas containing the given point. Areas sometimes overlap at their
+/// edges, so there can be more than one.
+pub fn locate(boundaries: &[AreaBoundary], latitude: f64, longitude: f64) -> Vec<&str> {
+    boundaries
+        .iter()
+        .filter(|boundary| boundary.contains(longitude, latitude))
+        .map(|boundary| boundary.area_name.as_str())
+        .collect()
+}
+
+/// A fill colour for each stage, going from green (no loadshedding) to dark red (stage 8).
+fn stage_colour(stage: u8) -> &'static str {
+    let colours = [
+        "#2ecc71", "#f1c40f", "#f39c12", "#e67e22", "#e74c3c", "#c0392b", "#a93226", "#922b21",
+        "#641e16",
+    ];
+    colours
+        .get(stage as usize)
+        .unwrap_or(&colours[colours.len() - 1])
+}
+
+/// Convert the boundaries into a FeatureCollection, with each area coloured by its current stage
+/// using the simplestyle `fill` and `stroke` properties. Areas without a status are left out.
+pub fn boundaries_to_stage_geojson(
+    boundaries: &[AreaBoundary],
+    statuses: &[AreaStatus],
+) -> FeatureCollection {
+    let features = boundaries
+        .iter()
+        .filter_map(|boundary| {
+            let status = statuses
+                .iter()
+                .find(|status| status.area_name == boundary.area_name)?;
+            let mut feature = Feature {
+                geometry: Some(Geometry::new(Value::MultiPolygon(
+                    boundary.polygons.clone(),
+                ))),
+                ..Default::default()
+            };
+            feature.set_property("area_name", boundary.area_name.clone());
+            feature.set_property("pretty_name", fmt::prettify_area_name(&boundary.area_name));
+            feature.set_property("stage", status.stage);
+            feature.set_property("power_off", status.current_outage.is_some());
+            feature.set_property(
+                "power_returns",
+                status.power_returns.map(|dt| dt.to_rfc3339()),
+            );
+            feature.set_property("fill", stage_colour(status.stage));
+            feature.set_property("fill-opacity", 0.5);
+            feature.set_property("stroke", stage_colour(status.stage));
+            Some(feature)
+        })
+        .collect();
+    FeatureCollection {
+        bbox: None,
+        features,
+        foreign_members: None,
+    }
+}
+
+/// Write the boundaries, coloured by their current stage, to `path`.
+pub fn write_stage_geojson(
+    boundaries: &[AreaBoundary],
+    statuses: &[AreaStatus],
+    path: &Path,
+) -> Result<(), BoxedError> {
+    let collection = boundaries_to_stage_geojson(boundaries, statuses);
+    info!(
+        "Writing {} area boundaries to {path:?}",
+        collection.features.len()
+    );
+    write_atomically(path, |file| {
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, &collection)?;
+        Ok(writer.flush()?)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geo::{boundaries_to_stage_geojson, locate, AreaBoundary};
+    use crate::query::AreaStatus;
+    use geojson::GeoJson;
+
+    /// A square from (18, -34) to (19, -33) with a hole from (18.4, -33.6) to (18.6, -33.4), and
+    /// a second square from (20, -34) to (21, -33).
+    fn boundary() -> AreaBoundary {
+        let geojson = r#"{
+            "type": "FeatureCollection",
+            "features": [{
+                "type": "Feature",
+                "properties": {},
+                "geometry": {
+                    "type": "Polygon",
+                    "coordinates": [
+                        [[18, -34], [19, -34], [19, -33], [18, -33], [18, -34]],
+                        [[18.4, -33.6], [18.6, -33.6], [18.6, -33.4], [18.4, -33.4], [18.4, -33.6]]
+                    ]
+                }
+            }, {
+                "type": "Feature",
+                "properties": {},
+                "geometry": {
+                    "type": "MultiPolygon",
+                    "coordinates": [[[[20, -34], [21, -34], [21, -33], [20, -33], [20, -34]]]]
+                }
+            }]
+        }"#;
+        AreaBoundary::from_geojson("test-area", geojson.parse::<GeoJson>().unwrap())
+    }
+
+    #[test]
+    fn reads_polygons_and_multipolygons() {
+        assert_eq!(boundary().polygons.len(), 2);
+    }
+
+    #[test]
+    fn contains_points_inside() {
+        let boundary = boundary();
+        assert!(boundary.contains(18.2, -33.8));
+        assert!(boundary.contains(20.5, -33.5));
+    }
+
+    #[test]
+    fn excludes_points_outside_and_in_holes() {
+        let boundary = boundary();
+        assert!(!boundary.contains(19.5, -33.5));
+        assert!(!boundary.contains(18.5, -33.5));
+        assert!(!boundary.contains(18.5, -32.5));
+    }
+
+    #[test]
+    fn locates_by_latitude_then_longitude() {
+        let boundaries = vec![boundary()];
+        assert_eq!(locate(&boundaries, -33.8, 18.2), vec!["test-area"]);
+        assert!(locate(&boundaries, 18.2, -33.8).is_empty());
+    }
+
+    #[test]
+    fn colours_by_stage() {
+        let statuses = vec![AreaStatus {
+            area_name: "test-area".to_owned(),
+            stage: 4,
+            current_outage: None,
+            power_returns: None,
+            next_outage: None,
+        }];
+        let collection = boundaries_to_stage_geojson(&[boundary()], &statuses);
+        assert_eq!(collection.features.len(), 1);
+        let feature = &collection.features[0];
+        assert_eq!(feature.property("stage").unwrap(), 4);
+        assert_eq!(feature.property("fill").unwrap(), "#e74c3c");
+        assert_eq!(feature.property("power_off").unwrap(), false);
+
+        // Areas without a status are left out
+        assert!(boundaries_to_stage_geojson(&[boundary()], &[])
+            .features
+            .is_empty());
+    }
+}
@@ -1,21 +1,42 @@
-use chrono::{DateTime, Datelike, Duration, NaiveTime, Timelike};
+use chrono::{DateTime, Duration, NaiveTime};
 use chrono::{Days, FixedOffset};
-use icalendar::{Calendar, EventLike};
+use config::Config;
+use icalendar::Calendar;
+use manifest::{Manifest, ManifestEntry};
 use rayon::prelude::*;
 use regex::Regex;
+use sink::{AreaOutages, SinkContext, WrittenFile};
 use std::error::Error;
-use std::fs::File;
-use std::io::Write;
+use std::io::{BufWriter, Write};
 use std::ops::Sub;
 use std::path::{Path, PathBuf};
-use std::process::Command;
-use structs::{Args, Change, ManuallyInputSchedule, PowerOutage, Recurrence, RecurringShedding};
+use structs::{
+    Args, BuildContext, Change, Command, Exemption, ManuallyInputSchedule, PowerOutage, Recurrence,
+    RecurringShedding,
+};
 
 extern crate pretty_env_logger;
 use log::{error, info, trace, warn};
 
 use clap::Parser;
+mod cache;
+mod cancelled;
+mod check;
+mod config;
+mod diff;
+mod eskom_xlsx;
+mod export;
+mod geo;
+mod manifest;
+mod normalise;
+mod query;
+mod rrule;
+mod search;
+mod serve;
+mod sink;
+mod source;
 mod structs;
+mod timeline;
 
 type BoxedError = Box<dyn Error + Sync + Send>;
 
@@ -23,6 +44,66 @@ type BoxedError = Box<dyn Error + Sync + Send>;
 fn main() -> Result<(), BoxedError> {
     pretty_env_logger::init();
 
+    // Parse the command-line arguments, which override the config file
+    let args = Args::parse();
+    let config = Config::read(args.paths.config.as_deref())?.with_overrides(&args.paths);
+
+    match args.command().unwrap_or_else(|e| e.exit()) {
+        Command::Generate(generate_args) => {
+            let config = config.with_generate_args(&generate_args);
+            generate(&config, generate_args.include_regex)
+        }
+        Command::Check => check(&config),
+        Command::Query(query_command) => {
+            // Answer questions about a single area without writing any calendars
+            let paths = read::get_schedule_paths(&config.generated_dir)?;
+            let metadata = read::read_area_metadata(&config.area_metadata)?;
+            let mut manually_specified =
+                read::read_manually_specified(&config.manually_specified, &metadata)?;
+            manually_specified.project_until(
+                query::now_sast() + Duration::days(config.policies.tentative_horizon_days),
+            );
+            query::run(
+                &query_command,
+                &config,
+                &paths,
+                &metadata,
+                &manually_specified,
+            )
+        }
+        Command::Export {
+            formats,
+            include_regex,
+        } => generate(&config.with_export_formats(&formats), include_regex),
+        Command::Diff { old, new } => diff::print_diff(&old, &new, &config),
+        Command::Serve { port } => serve::serve(&config.output_dir, port),
+        Command::FmtSchedules { check } => {
+            let paths = read::get_schedule_paths(&config.generated_dir)?;
+            normalise::fmt_schedules(&paths, check)
+        }
+        Command::ImportEskom {
+            spreadsheets,
+            verify,
+        } => eskom_xlsx::import(&spreadsheets, verify, &config),
+    }
+}
+
+/// Ensure that none of the manually_specified areas conflict with one another, and that
+/// area_metadata.yaml lines up with the schedules in generated/
+fn check(config: &Config) -> Result<(), BoxedError> {
+    let paths = read::get_schedule_paths(&config.generated_dir)?;
+    let metadata = read::read_area_metadata(&config.area_metadata)?;
+    let manually_specified = read::read_manually_specified(&config.manually_specified, &metadata)?;
+    check::print_selected_areas(&manually_specified);
+    err_if_overlaps(&manually_specified, &paths)?;
+    check::err_if_metadata_inconsistent(&metadata, &paths)?;
+    check::err_if_schedules_inconsistent(&paths)?;
+    Ok(())
+}
+
+/// Calculate the power outages of every area whose CSV path matches `include_regex`, and write
+/// them out in each of the formats enabled in `config`.
+fn generate(config: &Config, include_regex: Option<Regex>) -> Result<(), BoxedError> {
     // Some of the areas have expired and been replaced with outers. For now, it's just some
     // eThekwini suburbs so they can be dealt with in a bit of a hacky manner, although proper
     // methods will have to be implemented when more and more municipalities start updating their
@@ -47,30 +128,131 @@ fn main() -> Result<(), BoxedError> {
     ];
     let expired_at = DateTime::parse_from_rfc3339("2023-05-25T00:00:00.000000+02:00").unwrap();
 
-    // Parse the command-line arguments
-    let args = Args::parse();
-
     // Read in the CSV paths
-    let paths = read::get_csv_paths("generated/")?;
+    let paths = read::get_schedule_paths(&config.generated_dir)?;
 
     // Read in the manually_specified YAML file
-    let manually_specified = read::read_manually_specified("manually_specified.yaml")?;
+    let metadata = read::read_area_metadata(&config.area_metadata)?;
+    let manually_specified = read::read_manually_specified(&config.manually_specified, &metadata)?;
 
     // Ensure that none of the manually_specified areas conflict with one another
-    err_if_overlaps(&manually_specified.changes, &paths)?;
-    if args.only_check_for_overlaps {
-        return Ok(());
-    }
+    err_if_overlaps(&manually_specified, &paths)?;
+
+    // Compute the details of this build once, since they're included in every event
+    let build = build_context()?;
+
+    // Changes which continue until further notice are projected over the next few days
+    let mut manually_specified = manually_specified;
+    manually_specified
+        .project_until(build.compiled_at + Duration::days(config.policies.tentative_horizon_days));
+
+    // Remember what the previous run published, so that withdrawn outages can be cancelled
+    let published = if config.outputs.csv_file {
+        cancelled::read_published(&config.output_path(&config.csv_file_name))?
+    } else {
+        Default::default()
+    };
+    let keep_cancelled_for = Duration::hours(config.policies.cancelled_outage_hours);
 
     // Only include those CSV paths permitted by the --include-regex CLI argument
-    let mut filtered_paths = filter_paths_by_regex(args.include_regex, paths);
+    let mut filtered_paths = filter_paths_by_regex(include_regex, paths);
     filtered_paths.sort();
 
-    // Convert the paths into lines which can be written to a CSV
-    let paths_and_outages: Vec<(&PathBuf, Vec<PowerOutage>)> = filtered_paths
+    // The stage timelines only depend on the changes, so they're calculated once up front
+    let timelines = if config.outputs.timeline_files {
+        timeline::timelines(&manually_specified, &metadata)
+    } else {
+        vec![]
+    };
+
+    // Each enabled output is written by its own sink
+    let sinks = sink::sinks_for(&config.outputs);
+    let ctx = SinkContext {
+        config,
+        build: &build,
+        manually_specified: &manually_specified,
+        timelines: &timelines,
+        expired: &expired,
+        expired_at,
+    };
+    info!(
+        "Writing outputs: {}",
+        sinks
+            .iter()
+            .map(|s| s.name())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    // Areas whose inputs haven't changed since the last run don't need their files rewritten. If
+    // none of the sinks need every area's outages, they don't even need to be recomputed.
+    let cache = if config.policies.incremental {
+        cache::Cache::read(&config.cache_path())
+    } else {
+        cache::Cache::default()
+    };
+    // Withdrawn outages can only be found by calculating an area's outages, so if there are any
+    // previously published outages then every area has to be recalculated
+    let every_area_needed = sinks.iter().any(|s| s.needs_every_area()) || !published.is_empty();
+    // Files which aren't rewritten keep their entries from the previous manifest
+    let mut manifest = Manifest::read(&config.manifest_path());
+
+    // Hash the inputs of an area (including its cancelled outages), and check if its files need to
+    // be rewritten
+    let hash_inputs = |path: &PathBuf, cancelled: &[PowerOutage]| {
+        let area_name = fmt::path_to_area_name(path).unwrap();
+        let expired_on = expired
+            .contains(&area_name.as_str())
+            .then(|| build.compiled_at.date_naive());
+        let input_hash = match cache::area_input_hash(
+            path,
+            &area_name,
+            &manually_specified.changes_for_area(&area_name),
+            &manually_specified.exemptions_for_area(&area_name),
+            cancelled,
+            &config.policies,
+            expired_on,
+        ) {
+            Ok(input_hash) => input_hash,
+            Err(e) => {
+                error!("Error while hashing CSV {:?}: {}", path, e);
+                return None;
+            }
+        };
+        let area_paths = sinks
+            .iter()
+            .flat_map(|s| s.area_paths(&area_name, &ctx))
+            .collect::<Vec<_>>();
+        let changed = area_paths.is_empty()
+            || area_paths.iter().any(|area_path| {
+                cache.is_stale(&area_name, &input_hash, area_path) || !manifest.contains(area_path)
+            });
+        Some((input_hash, changed))
+    };
+
+    // Calculate the outages of each area, and write the outputs of each area as we go
+    #[allow(clippy::type_complexity)]
+    let areas: Vec<(String, Vec<PowerOutage>, Option<String>, Vec<WrittenFile>)> = filtered_paths
         .par_iter()
+        // Skip areas whose inputs haven't changed, unless every area's outages are needed
+        .filter(|path| {
+            if every_area_needed {
+                return true;
+            }
+            // Nothing was published, so there aren't any cancelled outages
+            let Some((_input_hash, changed)) = hash_inputs(path, &[]) else {
+                return false;
+            };
+            if !changed {
+                trace!("Inputs for {path:?} are unchanged, not recomputing its outages");
+            }
+            changed
+        })
         // Convert the paths to (path, shedding) tuples
-        .map(|path| (path, read::read_sheddings_from_csv_path(path)))
+        .map(|path| {
+            let sheddings = source::read_schedule(path).map(|schedule| schedule.sheddings);
+            (path, sheddings)
+        })
         // Exclude all sheddings which failed
         .filter_map(|(path, sheddings)| match sheddings {
             Ok(sheddings) => Some((path, sheddings)),
@@ -107,57 +289,93 @@ fn main() -> Result<(), BoxedError> {
             }).collect();
             (path, new_outages, last_finsh)
         })
-        // Write the individual sheddings to ICS files
-        .map(|(path, mut outages, last_finsh)| {
-            if args.output_ics_files {
-                write_sheddings_to_ics(path, &mut outages, last_finsh, expired.clone(), expired_at).unwrap();
-            }
-            (path, outages)
+        // Outages which were published before but have since been withdrawn are kept as cancelled,
+        // and the area's files are rewritten whenever its cancelled outages change
+        .filter_map(|(path, mut outages, last_finsh)| {
+            let area_name = fmt::path_to_area_name(path).unwrap();
+            let cancelled = published
+                .get(&area_name)
+                .map(|published| {
+                    cancelled::cancelled_outages(
+                        published,
+                        &outages,
+                        build.compiled_at,
+                        keep_cancelled_for,
+                    )
+                })
+                .unwrap_or_default();
+            let (input_hash, changed) = hash_inputs(path, &cancelled)?;
+            outages.extend(cancelled);
+            Some((path, outages, last_finsh, changed.then_some(input_hash)))
+        })
+        // Write the outputs of each area, which only rewrite files if their inputs have changed
+        .map(|(path, outages, last_finsh, input_hash)| {
+            let mut area = AreaOutages {
+                csv_path: path,
+                area_name: fmt::path_to_area_name(path).unwrap(),
+                outages,
+                last_finsh,
+                changed: input_hash.is_some(),
+            };
+            let written = sinks
+                .iter()
+                .flat_map(|s| s.write_area(&mut area, &ctx).unwrap())
+                .collect();
+            (area.area_name, area.outages, input_hash, written)
         })
         .collect();
 
-    let mut csv_lines = paths_and_outages
+    // Remember the inputs of every area whose files were rewritten, and report which they were
+    let mut cache = cache;
+    let mut rewritten = 0;
+    for (area_name, _outages, input_hash, written) in &areas {
+        for (path, entry) in written {
+            info!("Rewrote {path:?}");
+            manifest.insert(path, entry.clone());
+        }
+        if let (Some(input_hash), false) = (input_hash, written.is_empty()) {
+            cache.areas.insert(area_name.clone(), input_hash.clone());
+            rewritten += 1;
+        }
+    }
+    if rewritten > 0 {
+        info!(
+            "Rewrote the files of {} of {} areas, the rest were unchanged",
+            rewritten,
+            filtered_paths.len()
+        );
+        cache.write(&config.cache_path())?;
+    }
+
+    let outages: Vec<PowerOutage> = areas
         .into_par_iter()
-        .flat_map(|(_p, o)| o)
+        .flat_map(|(_a, o, _h, _w)| o)
         .collect();
 
-    if args.output_csv_file {
-        // Write the lines to a CSV
-        overwrite_lines_to_csv(&mut csv_lines, "machine_friendly.csv".to_owned())?;
-        // NOTE: Hacky solution for embedded platforms.
-        // https://github.com/beyarkay/eskom-calendar/issues/341
-        //
-        // Microcontrollers don't have the memory/storage to handle a 1.42 MB file, so for now
-        // (until an API can be set up), write out just cpt-9 and cpt-15 as their own CSV files so
-        // that they can be downloaded individually.
-        let custom_areas = vec!["city-of-cape-town-area-9", "city-of-cape-town-area-15"];
-        for area in custom_areas {
-            overwrite_lines_to_csv(
-                &mut csv_lines
-                    .iter()
-                    .cloned()
-                    .filter(|l| l.area_name == area)
-                    .collect(),
-                format!("{}.csv", area),
-            )?;
+    // Write the outputs which combine every area
+    for sink in &sinks {
+        for (path, entry) in sink.finalize(&outages, &ctx)? {
+            manifest.insert(&path, entry);
         }
     }
+
+    // Describe every output file, so the website and mirrors can check their integrity
+    manifest.write(&config.manifest_path(), build.compiled_at)?;
     Ok(())
 }
 
 /// Checks the provided changes for illegal overlaps.
 /// For example, specifying Stage 2 from 14h to 16h as well as Stage 3 from 13h to 16h is not
 /// allowed.
-fn err_if_overlaps(changes: &[Change], paths: &[PathBuf]) -> Result<(), BoxedError> {
+fn err_if_overlaps(
+    manually_specified: &ManuallyInputSchedule,
+    paths: &[PathBuf],
+) -> Result<(), BoxedError> {
     info!("Checking for overlaps...");
     for path in paths {
         let area_name = fmt::path_to_area_name(path)?;
-        let regexed_changes = changes
-            .par_iter()
-            .filter(|c| {
-                !c.exclude_regex.is_match(&area_name) && c.include_regex.is_match(&area_name)
-            })
-            .collect::<Vec<_>>();
+        // Stage rules can move or split the national changes, so check them after they're applied
+        let regexed_changes = manually_specified.changes_for_area(&area_name);
         for change1 in &regexed_changes {
             for change2 in &regexed_changes {
                 let not_same_item = change1 != change2;
@@ -189,7 +407,7 @@ fn filter_paths_by_regex(regex: Option<Regex>, paths: Vec<PathBuf>) -> Vec<PathB
         .filter(|path| {
             regex
                 .as_ref()
-                .map_or(true, |re| re.is_match(path.to_str().unwrap()))
+                .is_none_or(|re| re.is_match(path.to_str().unwrap()))
         })
         .collect::<Vec<_>>();
     trace!("  Resulted with {} paths", filtered_paths.len());
@@ -203,12 +421,7 @@ fn calculate_power_outages(
     monthly_sheddings: Vec<RecurringShedding>,
     manually_specified: &ManuallyInputSchedule,
 ) -> Result<(Vec<PowerOutage>, Option<DateTime<FixedOffset>>), BoxedError> {
-    let national_changes: Vec<Change> = manually_specified
-        .changes
-        .clone()
-        .into_par_iter()
-        .filter(|c| !c.exclude_regex.is_match(area_name) && c.include_regex.is_match(area_name))
-        .collect();
+    let national_changes = manually_specified.changes_for_area(area_name);
     let combos = make_combinations_from_sheddings(&monthly_sheddings, &national_changes);
     if combos.is_empty() {
         warn!("No combinations for possible load shedding in {area_name}\nmonthly sheddings: {}, national changes: {}", monthly_sheddings.len(), national_changes.len());
@@ -226,15 +439,16 @@ fn calculate_power_outages(
         let datetimes = gen_datetimes(
             natnl.start,
             natnl.finsh,
-            local.day_of_recurrence,
             local.recurrence,
-            local.start_time.time(),
-            local.finsh_time.time(),
+            local.start_time,
+            local.duration,
         );
         for dt in datetimes {
             // Keep track of the last finished event, so that we can add one more event immediately
-            // after it
-            last_finsh = last_finsh.map_or(Some(dt.1), |le| Some(le.max(dt.1)));
+            // after it. Tentative events aren't known, so the schedule ends before them.
+            if !natnl.tentative {
+                last_finsh = last_finsh.map_or(Some(dt.1), |le| Some(le.max(dt.1)));
+            }
             // Also create a power outage struct and push it onto the vector
             outages.push(PowerOutage {
                 area_name: area_name.to_owned(),
@@ -242,28 +456,31 @@ fn calculate_power_outages(
                 start: dt.0,
                 finsh: dt.1,
                 source: natnl.source.clone(),
+                cancelled: false,
+                tentative: natnl.tentative,
             })
         }
     }
+    // Loadshedding is sometimes suspended, like during elections
+    let outages = manually_specified.without_exemptions(outages);
     Ok((outages, last_finsh))
 }
 
-/// Attempt to write some PowerOutages to the specified path as a ICS file.
+/// Attempt to write some PowerOutages to the specified path as a ICS file, along with an all-day
+/// event for each of the `exemptions` which applies to the area.
+#[allow(clippy::too_many_arguments)]
 fn write_sheddings_to_ics(
     path: &Path,
     power_outages: &mut [PowerOutage],
     last_finsh: Option<DateTime<FixedOffset>>,
+    exemptions: &[&Exemption],
     expired: Vec<&str>,
     expired_at: DateTime<FixedOffset>,
+    build: &BuildContext,
+    config: &Config,
 ) -> Result<Calendar, BoxedError> {
     // Get the correct filename
-    let fname = path
-        .to_str()
-        .ok_or("Couldn't convert path to string")?
-        .replace("csv", "ics")
-        .replace("generated", "calendars")
-        .replace(|c: char| !c.is_ascii(), "")
-        .replace("&nbsp;", "");
+    let fname = config.ics_path(&fmt::path_to_area_name(path)?);
 
     info!("Writing {} events to {:?}", power_outages.len(), fname);
 
@@ -274,27 +491,32 @@ fn write_sheddings_to_ics(
     // loadshedding outage is <=30m long, however this is the widely observed truth. So for the
     // user-facing ICS files, we remove the 30m loadshedding item, but for the machine-facing CSV
     // file, we keep it in.
-    let min_duration = Duration::minutes(30);
+    let min_duration = Duration::minutes(config.policies.min_event_minutes);
 
     let mut calendar = Calendar::new();
 
     power_outages.sort_by_key(|outage| outage.start);
 
+    // Cancelled outages aren't going to happen, so they mustn't decide whether a live outage is
+    // long enough or collides with another
+    let (cancelled, live): (Vec<&PowerOutage>, Vec<&PowerOutage>) =
+        power_outages.iter().partition(|outage| outage.cancelled);
+
     // Filter out all the outages which are under 30 minutes long
-    let long_enough_outages = power_outages.iter().enumerate().filter(|(i, outage)| {
+    let long_enough_outages = live.iter().enumerate().filter(|(i, outage)| {
         let curr_event_long_enough = outage.finsh - outage.start > min_duration;
 
         // If i == 0, then there's no previous event so they can't collide
         let prev_event_collides = i != &0
-            && power_outages
+            && live
                 .get(i - 1)
-                .map_or(false, |o| outage.start.sub(o.finsh) <= Duration::minutes(1));
+                .is_some_and(|o| outage.start.sub(o.finsh) <= Duration::minutes(1));
 
-        // If i+1 == power_outages.len, then there's no next event so they can't collide
-        let next_event_does_collide = i + 1 != power_outages.len()
-            && power_outages
+        // If i+1 == live.len, then there's no next event so they can't collide
+        let next_event_does_collide = i + 1 != live.len()
+            && live
                 .get(i + 1)
-                .map_or(false, |o| o.start.sub(outage.finsh) <= Duration::minutes(1));
+                .is_some_and(|o| o.start.sub(outage.finsh) <= Duration::minutes(1));
 
         // trace!("{curr_event_long_enough} || ({prev_event_collides} || {next_event_does_collide})");
         (curr_event_long_enough) || ((prev_event_collides) || (next_event_does_collide))
@@ -303,25 +525,40 @@ fn write_sheddings_to_ics(
     // Add all the long enough events to the calendar
     for (_i, outage) in long_enough_outages {
         // Convert the outage to an event, and add it to the calendar
-        calendar.push(fmt::power_outage_to_event(outage)?);
+        calendar.push(fmt::power_outage_to_event(outage, build)?);
+    }
+
+    // Every cancelled outage is added, even short ones, since it might have been shown before
+    for outage in cancelled {
+        calendar.push(fmt::power_outage_to_event(outage, build)?);
     }
 
     let mut is_expired_ics = false;
     if let Ok(area_name) = fmt::path_to_area_name(path) {
+        for exemption in exemptions {
+            calendar.push(fmt::exemption_event(exemption, &area_name, build)?);
+        }
         if expired.contains(&area_name.as_str()) {
-            calendar.push(fmt::expired_schedule_event(&area_name, expired_at)?);
+            calendar.push(fmt::expired_schedule_event(
+                &area_name,
+                expired_at,
+                expired_at.date_naive(),
+                build,
+            )?);
             is_expired_ics = true;
             // If the calendar has expired in the past, it's possible a new user might not see the
             // warning. So add another warning on the same day as compilation, to make sure.
-            if expired_at.checked_add_days(Days::new(1)).unwrap() < chrono::offset::Local::now() {
+            if expired_at.checked_add_days(Days::new(1)).unwrap() < build.compiled_at {
                 info!(
                     "Writing expired event because {area_name} in expired && {expired_at:?} < {:?}",
-                    chrono::offset::Local::now()
+                    build.compiled_at
                 );
-                let event = fmt::expired_schedule_event(&area_name, expired_at)?
-                    .all_day(chrono::offset::Local::now().date_naive())
-                    .done();
-                calendar.push(event);
+                calendar.push(fmt::expired_schedule_event(
+                    &area_name,
+                    expired_at,
+                    build.compiled_at.date_naive(),
+                    build,
+                )?);
             }
         }
     }
@@ -333,33 +570,46 @@ fn write_sheddings_to_ics(
             calendar.push(fmt::end_of_schedule_event(
                 last_finsh,
                 fmt::path_to_area_name(path).ok(),
+                build,
             )?);
         }
     }
 
-    // Write all the data to disk
-    let mut file = File::create(fname.as_str())?;
-    writeln!(&mut file, "{}", calendar)?;
+    // Write all the data to disk, atomically so that a crash never leaves a truncated calendar
+    manifest::write_atomically(&fname, |file| {
+        writeln!(file, "{}", calendar)?;
+        Ok(())
+    })?;
 
     Ok(calendar)
 }
 
-/// Given a list of power outages, convert them to a single CSV file for machine consumption.
+/// Given a list of power outages, convert them to a single CSV file for machine consumption. Returns
+/// the file's entry for the manifest.
 fn overwrite_lines_to_csv(
-    power_outages: &mut Vec<PowerOutage>,
-    path: String,
-) -> Result<(), BoxedError> {
-    // Create the file (overwriting if it exists)
-    let mut file = File::create(format!("calendars/{path}"))?;
-    info!("Writing {}+1 lines to {file:?}", power_outages.len());
-    // Write the header line of the csv file
-    writeln!(&mut file, "{}", PowerOutage::csv_header())?;
+    power_outages: &mut [PowerOutage],
+    path: &Path,
+    build: &BuildContext,
+) -> Result<ManifestEntry, BoxedError> {
+    info!("Writing {}+1 lines to {path:?}", power_outages.len());
     // Sort the lines so we have some kind of consistency of the output
     power_outages.sort();
-    for line in power_outages {
-        writeln!(&mut file, "{}", line)?;
-    }
-    Ok(())
+    // Overwrite the file if it exists, atomically so that a crash never leaves a truncated CSV
+    manifest::write_atomically(path, |file| {
+        let mut writer = BufWriter::new(file);
+        // Write the header line of the csv file
+        writeln!(writer, "{}", PowerOutage::csv_header())?;
+        for line in power_outages.iter() {
+            writeln!(writer, "{}", line)?;
+        }
+        Ok(writer.flush()?)
+    })?;
+    ManifestEntry::new(
+        path,
+        Some(power_outages.len()),
+        power_outages,
+        build.compiled_at,
+    )
 }
 
 /// Returns the git hash of the current repository.
@@ -370,11 +620,52 @@ fn overwrite_lines_to_csv(
 /// - The current directory is not a valid git repository.
 fn get_git_hash() -> Result<String, BoxedError> {
     Ok(String::from_utf8(
-        Command::new("git")
+        std::process::Command::new("git")
             .args(["rev-parse", "HEAD"])
             .output()?
             .stdout,
-    )?)
+    )?
+    .trim()
+    .to_owned())
+}
+
+/// Gather the details about this build which are included in every calendar. This spawns `git`
+/// and reads the environment, so it should only be called once.
+///
+/// If `SOURCE_DATE_EPOCH` is set (see https://reproducible-builds.org/specs/source-date-epoch/),
+/// it's used as the compilation time so that the same inputs always produce the same calendars.
+fn build_context() -> Result<BuildContext, BoxedError> {
+    let timezone_sast = FixedOffset::east_opt(2 * 60 * 60).unwrap();
+    let compiled_at = match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => parse_source_date_epoch(&epoch)?,
+        Err(_) => chrono::Utc::now(),
+    }
+    .with_timezone(&timezone_sast);
+
+    // Get a nice URL link to the exact run which created this calendar (if the run even exists)
+    let run_url = std::env::var("GITHUB_RUN_ID").ok().map(|run_id| {
+        // And infer the repo name from the ENV variables, because sometimes this code is run on
+        // the development repository `beyarkay/eskom-calendar-dev`
+        let owner_repo = std::env::var("GITHUB_REPOSITORY")
+            .unwrap_or_else(|_| "beyarkay/eskom-calendar".to_owned());
+        format!("https://github.com/{owner_repo}/actions/runs/{run_id}")
+    });
+
+    Ok(BuildContext {
+        git_hash: get_git_hash()?,
+        run_url,
+        compiled_at,
+    })
+}
+
+/// Parse a `SOURCE_DATE_EPOCH`, which is the number of seconds since the unix epoch.
+fn parse_source_date_epoch(epoch: &str) -> Result<DateTime<chrono::Utc>, BoxedError> {
+    let seconds = epoch
+        .trim()
+        .parse::<i64>()
+        .map_err(|e| format!("SOURCE_DATE_EPOCH {epoch:?} is not an integer: {e}"))?;
+    DateTime::from_timestamp(seconds, 0)
+        .ok_or_else(|| Box::from(format!("SOURCE_DATE_EPOCH {epoch:?} is out of range")))
 }
 
 /// Given some monthly shedding data and some national shedding data, calculate all the ways they
@@ -401,84 +692,44 @@ fn make_combinations_from_sheddings(
 /// Specifically, the returned (start, finish) datetimes are:
 /// 1. Not before `natnl_start`
 /// 2. Not after `natnl_finsh`
-/// 3. On no other day of the month other than `local_dom`
-/// 4. Never before `local_start` on any particular day.
-/// 5. Never after `local_finsh` on any particular day.
+/// 3. Only on days that `lcl_recurrence` occurs on
+/// 4. Never before `lcl_start_t` on the day they start.
+/// 5. Never longer than `lcl_duration`.
 ///
-/// Note that if local_start is 23:30 and local_finsh is 00:30, then the (start, finsh) tuple will
-/// cross over midnight.
+/// Note that if lcl_start_t is 23:30 and lcl_duration is an hour, then the (start, finsh) tuple
+/// will cross over midnight.
 fn gen_datetimes(
     nat_start_dt: DateTime<FixedOffset>,
     nat_finsh_dt: DateTime<FixedOffset>,
-    lcl_dor: u8,
     lcl_recurrence: Recurrence,
     lcl_start_t: NaiveTime,
-    lcl_finsh_t: NaiveTime,
+    lcl_duration: Duration,
 ) -> Vec<(DateTime<FixedOffset>, DateTime<FixedOffset>)> {
-    // info!("Checking LS from {nat_start_dt} to {nat_finsh_dt} where cycle day is {lcl_dor}");
+    // Local ranges which started up to this many days before nat_start_dt could still be going
+    let days_before = 1 + u64::try_from(lcl_duration.num_days()).unwrap_or(0);
     nat_start_dt
-        // subtract a day to ensure we handle the midnight boundary condition properly
-        .checked_sub_days(chrono::Days::new(1))
+        // subtract the days to ensure we handle the midnight boundary condition properly
+        .checked_sub_days(chrono::Days::new(days_before))
         .unwrap()
         // Convert datetime to date to make things simpler. We'll add back the time later.
         .date_naive()
-        // create an unbounded iterator, starting from one day before nat_start_dt
+        // create an unbounded iterator, starting from a few days before nat_start_dt
         .iter_days()
         .take_while(|d| d <= &nat_finsh_dt.date_naive())
-        // Get all possible datetime ranges with start and finsh times specified by `lcl_start_t`
-        // and `lcl_finsh_t`
+        // Get all possible datetime ranges which start at `lcl_start_t` and last for
+        // `lcl_duration`
         .map(|d| {
-            // Create the starting datetime
-            let start_dt = d
-                .and_hms_opt(
-                    lcl_start_t.hour(),
-                    lcl_start_t.minute(),
-                    lcl_start_t.second(),
-                )
-                .unwrap();
-            // Create the finishing datetime
-            let mut finsh_dt = d
-                .and_hms_opt(
-                    lcl_finsh_t.hour(),
-                    lcl_finsh_t.minute(),
-                    lcl_finsh_t.second(),
-                )
-                .unwrap();
-            // it's possible that finsh_t is 00:30 and start_t is 22:00, in which case make sure
-            // finsh_dt is on the next day.
-            if finsh_dt < start_dt {
-                finsh_dt = finsh_dt.checked_add_days(chrono::Days::new(1)).unwrap();
-            }
+            let start_dt = d.and_time(lcl_start_t);
+            let finsh_dt = start_dt + lcl_duration;
 
             let lcl_range: (DateTime<FixedOffset>, DateTime<FixedOffset>) = (
-                chrono::DateTime::from_local(start_dt, *nat_start_dt.offset()),
-                chrono::DateTime::from_local(finsh_dt, *nat_finsh_dt.offset()),
+                start_dt.and_local_timezone(*nat_start_dt.offset()).unwrap(),
+                finsh_dt.and_local_timezone(*nat_finsh_dt.offset()).unwrap(),
             );
             lcl_range
         })
-        // Ensure each range starts on the correct date of the recurrence
-        .filter(|(start, _finsh)| match lcl_recurrence {
-            Recurrence::Weekly => start.weekday().number_from_monday() == lcl_dor as u32,
-            Recurrence::Monthly => start.day() == lcl_dor as u32,
-            Recurrence::Periodic { offset, period } => {
-                // FIXME make this invalid state unrepresentable
-                assert!(lcl_dor <= period);
-                // Figure out the cycle day, using the period of the recurrence and the difference
-                // between the loadsheddings start and the recurrence's offset
-                let mut cycle_day = start.date_naive().sub(offset).num_days() % period as i64;
-                // It's valid for the offset to be in the future, in which case the cycle day could
-                // be negative (`-6 % 5 == -1`). Take care of this by repeatedly adding the period
-                // until the cycle_day is non-negative
-                while cycle_day < 0 {
-                    cycle_day += period as i64;
-                }
-                // The cycle_day is actually one-indexed, so add 1 to compensate for this.
-                cycle_day += 1;
-                // If the calculated cycle day is the same as the recurrence cycle day, then emit
-                // the loadshedding event
-                cycle_day == lcl_dor as i64
-            }
-        })
+        // Ensure each range starts on a day of the recurrence
+        .filter(|(start, _finsh)| lcl_recurrence.occurs_on(start.date_naive()))
         // Truncate each local range so that it's actually within the specified national range
         .map(|(start, finsh)| (nat_start_dt.max(start), nat_finsh_dt.min(finsh)))
         // Ensure each range is before the finish
@@ -491,48 +742,49 @@ fn gen_datetimes(
 
 /// Contains some formatting functions, including some event-creation functions.
 mod fmt {
-    use crate::get_git_hash;
     use crate::BoxedError;
     use chrono::Duration;
     use chrono::FixedOffset;
-    use chrono::{DateTime, Utc};
+    use chrono::{DateTime, Days, Utc};
     use icalendar::Alarm;
     use icalendar::EventLike;
-    use icalendar::{Component, Event};
+    use icalendar::{Component, Event, EventStatus};
     use std::path::Path;
 
-    use crate::structs::PowerOutage;
+    use crate::structs::{BuildContext, Exemption, PowerOutage};
+    use crate::timeline::{self, StageBlock};
 
-    /// Format a path as an area name: remove the extension and the `generated/` directory. This fails
-    /// if the path isn't valid.
+    /// Format a path as an area name: remove the extension and the directory. This fails if the
+    /// path isn't valid.
     pub fn path_to_area_name(path: &Path) -> Result<String, BoxedError> {
         Ok(path
-            .to_str()
+            .file_stem()
+            .and_then(|fname| fname.to_str())
             .ok_or("Path is not valid unicode")?
-            .replace("generated/", "")
             .replace(".csv", "")
             .replace(|c: char| !c.is_ascii(), ""))
     }
 
+    /// A unique ID for an event, which is the same every time the calendars are built from the
+    /// same inputs. This means calendar apps can tell when an event is unchanged.
+    fn event_uid(kind: &str, area_name: &str, when: &str) -> String {
+        format!("{area_name}-{kind}-{when}@eskomcalendar.co.za")
+    }
+
     /// Convert a power outage to a ICS calendar event, with a nicely formatted description.
-    pub fn power_outage_to_event(power_outage: &PowerOutage) -> Result<Event, BoxedError> {
+    pub fn power_outage_to_event(
+        power_outage: &PowerOutage,
+        build: &BuildContext,
+    ) -> Result<Event, BoxedError> {
         let timezone_sast = FixedOffset::east_opt(2 * 60 * 60).unwrap();
-        let now = DateTime::<FixedOffset>::from_local(
-            chrono::offset::Local::now().naive_local(),
-            timezone_sast,
-        );
+        let compiled_at = build.compiled_at.with_timezone(&timezone_sast);
 
         // Get a nice URL link to the exact run which created this calendar (if the run even
         // exists)
-        let github_run_url = if let Ok(run_id) = std::env::var("GITHUB_RUN_ID") {
-            // And infer the repo name from the ENV variables, because sometimes this code is run
-            // on the development repository `beyarkay/eskom-calendar-dev`
-            let owner_repo = std::env::var("GITHUB_REPOSITORY")
-                .unwrap_or_else(|_| "beyarkay/eskom-calendar".to_owned());
-            format!(" by run https://github.com/{owner_repo}/actions/runs/{run_id}")
-        } else {
-            "".to_string()
-        };
+        let github_run_url = build
+            .run_url
+            .as_ref()
+            .map_or(String::new(), |url| format!(" by run {url}"));
 
         // TODO can a default alarm be added to this?
         let description = format!(
@@ -563,9 +815,9 @@ mod fmt {
             power_outage.area_name,
             power_outage.area_name,
             power_outage.source,
-            now.format("on %A %d %h %Y at %H:%M:%S (UTC+02:00)"),
+            compiled_at.format("on %A %d %h %Y at %H:%M:%S (UTC+02:00)"),
             github_run_url,
-            get_git_hash()?,
+            build.git_hash,
         );
         // These emojis are for stages:
         //                  0     1     2    3     4     5     6     7     8
@@ -576,23 +828,59 @@ mod fmt {
             stage = power_outage.stage,
             emoji = emojis.get(power_outage.stage as usize).unwrap_or(&"🫠"),
         );
-        let evt = Event::new()
-            .summary(summary.as_str())
-            .description(description.as_str())
-            .starts(power_outage.start.with_timezone(&Utc))
+        let uid = event_uid(
+            &format!("stage{}", power_outage.stage),
+            &power_outage.area_name,
+            &format!(
+                "{}-{}",
+                power_outage.start.format("%Y%m%dT%H%M%S"),
+                power_outage.finsh.format("%Y%m%dT%H%M%S")
+            ),
+        );
+        let mut evt = Event::new();
+        evt.starts(power_outage.start.with_timezone(&Utc))
             .ends(power_outage.finsh.with_timezone(&Utc))
-            .alarm(Alarm::display(
-                &format!("In 1 hour: {}", summary),
-                -Duration::hours(1),
-            ))
-            .done();
-        Ok(evt)
+            .timestamp(build.compiled_at.with_timezone(&Utc))
+            .uid(&uid);
+        // Cancelled and tentative outages keep the same UID, so that calendar apps update the
+        // existing event instead of adding a new one
+        if power_outage.cancelled {
+            evt.summary(&format!("❌ Cancelled: {summary}"))
+                .description(&format!(
+                    "This loadshedding has been cancelled, and the power is no longer expected \
+                    to go off at this time. It was withdrawn by a later update to the schedule.\n\
+                    \n\
+                    {description}"
+                ))
+                .status(EventStatus::Cancelled);
+        } else if power_outage.tentative {
+            evt.summary(&format!("❓ Tentative: {summary}"))
+                .description(&format!(
+                    "This loadshedding is tentative. The stage was announced to continue until \
+                    further notice, so this event shows when the power would go off if it does. \
+                    It will be updated once the next stage is announced.\n\
+                    \n\
+                    {description}"
+                ))
+                .status(EventStatus::Tentative);
+        } else {
+            let alarm = Alarm::display(&format!("In 1 hour: {}", summary), -Duration::hours(1))
+                .timestamp(build.compiled_at.with_timezone(&Utc))
+                .uid(&format!("alarm-{uid}"))
+                .done();
+            evt.summary(summary.as_str())
+                .description(description.as_str())
+                .alarm(alarm);
+        }
+        Ok(evt.done())
     }
 
-    /// Create an event that signals that the schedule has expired
+    /// Create an all-day event on `date` that signals that the schedule has expired
     pub fn expired_schedule_event(
         area_name: &str,
         expired_at: DateTime<FixedOffset>,
+        date: chrono::NaiveDate,
+        build: &BuildContext,
     ) -> Result<Event, BoxedError> {
         let description = format!(
             "The schedule for {area_name} has expired, and is not valid after {fmt_date}.\n\
@@ -615,22 +903,140 @@ mod fmt {
             Generated by Boyd Kane's eskom-calendar: https://github.com/beyarkay/eskom-calendar/tree/{git_hash} \n\
             Calendar compiled at {compiletime:?}",
             fmt_date=expired_at.format("%-d %B %Y"),
-            git_hash=get_git_hash()?,
-            compiletime=chrono::offset::Local::now(),
+            git_hash=build.git_hash,
+            compiletime=build.compiled_at,
         );
 
         Ok(Event::new()
-            .all_day(expired_at.date_naive())
+            .all_day(date)
             .summary("❌ Schedule expired")
             .description(&description)
+            .timestamp(build.compiled_at.with_timezone(&Utc))
+            .uid(&event_uid(
+                "expired",
+                area_name,
+                &date.format("%Y%m%d").to_string(),
+            ))
+            .done())
+    }
+
+    /// Create an informational all-day event covering every day of `exemption`, to explain why
+    /// there isn't any loadshedding in `area_name` then.
+    pub fn exemption_event(
+        exemption: &Exemption,
+        area_name: &str,
+        build: &BuildContext,
+    ) -> Result<Event, BoxedError> {
+        let reason = exemption
+            .reason
+            .as_deref()
+            .unwrap_or("Loadshedding suspended");
+        let description = format!(
+            "Loadshedding has been suspended for {pretty_area_name} from {start} to {finsh}, so \
+            there won't be any loadshedding in this area then.\n\
+            \n\
+            Reason: {reason}\n\
+            \n\
+            Source: {source}\n\
+            \n\
+            --- \n\
+            Generated by Boyd Kane's eskom-calendar: https://github.com/beyarkay/eskom-calendar/tree/{git_hash} \n\
+            Calendar compiled at {compiletime:?}",
+            pretty_area_name = prettify_area_name(area_name),
+            start = exemption.start.format("%A %-d %B at %H:%M"),
+            finsh = exemption.finsh.format("%A %-d %B at %H:%M"),
+            source = exemption.source,
+            git_hash = build.git_hash,
+            compiletime = build.compiled_at,
+        );
+        // All-day events end on the day after their last day, so an exemption which ends exactly
+        // at midnight doesn't cover the next day
+        let first_day = exemption.start.date_naive();
+        let last_day = (exemption.finsh - Duration::seconds(1)).date_naive();
+        Ok(Event::new()
+            .starts(first_day)
+            .ends(last_day + Days::new(1))
+            .summary(&format!("✅ {reason}"))
+            .description(&description)
+            .timestamp(build.compiled_at.with_timezone(&Utc))
+            .uid(&event_uid(
+                "exemption",
+                area_name,
+                &format!(
+                    "{}-{}",
+                    exemption.start.format("%Y%m%dT%H%M%S"),
+                    exemption.finsh.format("%Y%m%dT%H%M%S")
+                ),
+            ))
             .done())
     }
 
+    /// Create an event for one block of a stage timeline, where `timeline` is `national` or the
+    /// name of a provider.
+    pub fn stage_block_event(
+        block: &StageBlock,
+        timeline: &str,
+        build: &BuildContext,
+    ) -> Result<Event, BoxedError> {
+        let who = if timeline == timeline::NATIONAL {
+            String::new()
+        } else {
+            format!("{} ", to_title_case(timeline.replace('-', " ")))
+        };
+        let description = format!(
+            "{who}Stage {stage} from {start} to {finsh}{tentative}.\n\
+            \n\
+            Source: {source}\n\
+            \n\
+            --- \n\
+            Generated by Boyd Kane's eskom-calendar: https://github.com/beyarkay/eskom-calendar/tree/{git_hash} \n\
+            Calendar compiled at {compiletime:?}",
+            stage = block.stage,
+            start = block.start.format("%A %-d %B at %H:%M"),
+            finsh = block.finsh.format("%A %-d %B at %H:%M"),
+            tentative = if block.tentative {
+                ", tentatively (the stage was announced to continue until further notice)"
+            } else {
+                ""
+            },
+            source = block.source,
+            git_hash = build.git_hash,
+            compiletime = build.compiled_at,
+        );
+        let mut evt = Event::new();
+        evt.summary(&format!("⚡{who}Stage {}", block.stage))
+            .description(&description)
+            .starts(block.start.with_timezone(&Utc))
+            .ends(block.finsh.with_timezone(&Utc))
+            .timestamp(build.compiled_at.with_timezone(&Utc))
+            .uid(&event_uid(
+                &format!("stage{}", block.stage),
+                &format!("timeline-{timeline}"),
+                &format!(
+                    "{}-{}",
+                    block.start.format("%Y%m%dT%H%M%S"),
+                    block.finsh.format("%Y%m%dT%H%M%S")
+                ),
+            ));
+        if block.tentative {
+            evt.status(EventStatus::Tentative);
+        }
+        Ok(evt.done())
+    }
+
     /// Create an event that signals the end of known loadshedding data
     pub fn end_of_schedule_event(
         last_finsh: DateTime<FixedOffset>,
         area_name: Option<String>,
+        build: &BuildContext,
     ) -> Result<Event, BoxedError> {
+        // The UID doesn't include the time, so that calendar apps move the existing event when
+        // the schedule is extended instead of adding a new one.
+        let uid = event_uid(
+            "end-of-schedule",
+            area_name.as_deref().unwrap_or("unknown"),
+            "latest",
+        );
         let website_link = if let Some(area_name) = area_name {
             format!("To check for the most up-to-date schedule, go to the website: https://eskomcalendar.co.za/ec?calendar={area_name}.ics. ")
         } else {
@@ -650,8 +1056,8 @@ mod fmt {
             --- \n\
             Generated by Boyd Kane's eskom-calendar: https://github.com/beyarkay/eskom-calendar/tree/{git_hash} \n\
             Calendar compiled at {compiletime:?}",
-            git_hash=get_git_hash()?,
-            compiletime=chrono::offset::Local::now(),
+            git_hash=build.git_hash,
+            compiletime=build.compiled_at,
         );
 
         let start = last_finsh.with_timezone(&Utc);
@@ -665,6 +1071,8 @@ mod fmt {
             .description(&description)
             .starts(start)
             .ends(end)
+            .timestamp(build.compiled_at.with_timezone(&Utc))
+            .uid(&uid)
             .done())
     }
 
@@ -721,93 +1129,52 @@ mod fmt {
     }
 }
 
-/// Contains some read-based functions, such as `get_csv_paths` and `read_manually_specified`.
+/// Contains some read-based functions, such as `get_schedule_paths` and `read_manually_specified`.
 mod read {
-    use crate::structs::{
-        ManuallyInputSchedule, RawManuallyInputSchedule, RawMonthlyShedding, RawPeriodicShedding,
-        RawWeeklyShedding, RecurringShedding,
-    };
+    use crate::source::source_for;
+    use crate::structs::{AreaMetadata, ManuallyInputSchedule, RawManuallyInputSchedule};
     use crate::BoxedError;
     use std::fs::read_to_string;
-    use std::path::PathBuf;
+    use std::path::{Path, PathBuf};
 
     extern crate pretty_env_logger;
 
-    use csv::{ReaderBuilder, Trim};
     use log::{info, trace};
 
-    /// Get the paths of all CSVs in `dir`.
-    pub fn get_csv_paths(dir: &str) -> Result<Vec<PathBuf>, BoxedError> {
-        info!("Looking for CSV paths in {}", dir);
+    /// Get the paths of all the schedules in `dir` which some `ScheduleSource` can read.
+    pub fn get_schedule_paths(dir: &Path) -> Result<Vec<PathBuf>, BoxedError> {
+        info!("Looking for schedule paths in {:?}", dir);
         let paths = std::fs::read_dir(dir)?
             // Filter out all those directory entries which couldn't be read
             .filter_map(|res| res.ok())
             // Map the directory entries to paths
             .map(|dir_entry| dir_entry.path())
-            // Filter out all paths with extensions other than `csv`
-            .filter_map(|path| {
-                if path.extension().map_or(false, |ext| ext == "csv") {
-                    Some(path)
-                } else {
-                    None
-                }
-            })
+            // Filter out all paths which aren't in a known schedule format
+            .filter(|path| source_for(path).is_some())
             .collect::<Vec<_>>();
-        trace!("  Found {} CSVs in {dir}", paths.len());
+        trace!("  Found {} schedules in {dir:?}", paths.len());
         Ok(paths)
     }
 
-    /// Read in `manually_specified.yaml` from YAML to an in-memory struct
-    pub fn read_manually_specified(path: &str) -> Result<ManuallyInputSchedule, BoxedError> {
-        Ok(
+    /// Read in `manually_specified.yaml` from YAML to an in-memory struct, resolving the changes'
+    /// selectors with `metadata`
+    pub fn read_manually_specified(
+        path: &Path,
+        metadata: &AreaMetadata,
+    ) -> Result<ManuallyInputSchedule, BoxedError> {
+        let mut manually_specified: ManuallyInputSchedule =
             serde_yaml::from_str::<RawManuallyInputSchedule>(read_to_string(path)?.as_str())?
-                .into(),
-        )
+                .try_into()
+                .map_err(|e| format!("Invalid {path:?}: {e}"))?;
+        manually_specified.resolve_selectors(metadata)?;
+        Ok(manually_specified)
     }
 
-    /// Read in the load shedding information from the provided path.
-    pub fn read_sheddings_from_csv_path(
-        path: &PathBuf,
-    ) -> Result<Vec<RecurringShedding>, BoxedError> {
-        let mut reader = ReaderBuilder::new()
-            .trim(Trim::All) // Remove leading/trailing whitespace
-            .comment(Some(b'#')) // Allow comment lines if they start with #
-            .from_path(path)?;
-
-        let headers = reader.headers()?;
-        let raw: Vec<RecurringShedding>;
-
-        // Parse the CSV file in a manner that depends on the headers
-        if headers.iter().any(|h| h == "date_of_month") {
-            info!("Parsing {path:?} as a Monthly recurrence");
-            raw = reader
-                .deserialize::<RawMonthlyShedding>()
-                .map(|res| Into::<RecurringShedding>::into(res.unwrap()))
-                .collect::<Vec<_>>();
-        } else if headers.iter().any(|h| h == "day_of_week") {
-            info!("Parsing {path:?} as a Weekly recurrence");
-            // Monday is day 1
-            raw = reader
-                .deserialize::<RawWeeklyShedding>()
-                .map(|res| Into::<RecurringShedding>::into(res.unwrap()))
-                .collect::<Vec<_>>();
-        } else if headers.iter().any(|h| h == "day_of_cycle") {
-            info!("Parsing {path:?} as a Periodic recurrence");
-            raw = reader
-                .deserialize::<RawPeriodicShedding>()
-                .map(|res| Into::<RecurringShedding>::into(res.unwrap()))
-                .collect::<Vec<_>>();
-        } else {
-            return Err(Box::from(format!(
-                "Could not parse headers from {path:?}: {headers:?}"
-            )));
-        }
-
-        let local_shedding = raw
-            .into_iter()
-            .filter(|shedding| shedding.stage != 0)
-            .collect::<Vec<RecurringShedding>>();
-        Ok(local_shedding)
+    /// Read in `area_metadata.yaml` from YAML to an in-memory struct
+    pub fn read_area_metadata(path: &Path) -> Result<AreaMetadata, BoxedError> {
+        Ok(serde_yaml::from_str::<AreaMetadata>(
+            read_to_string(path)?.as_str(),
+        )?)
     }
 }
 
@@ -819,21 +1186,38 @@ mod tests {
         DateTime::parse_from_rfc3339(s).unwrap()
     }
 
+    fn build() -> crate::structs::BuildContext {
+        crate::structs::BuildContext {
+            git_hash: "0123456789abcdef".to_owned(),
+            run_url: None,
+            compiled_at: rfc3339("2022-01-01T00:00:00+02:00"),
+        }
+    }
+
     mod power_outage_to_event {
         use chrono::Utc;
         use icalendar::Component;
 
-        use crate::{fmt::power_outage_to_event, structs::PowerOutage, tests::rfc3339};
+        use crate::{
+            fmt::power_outage_to_event,
+            structs::PowerOutage,
+            tests::{build, rfc3339},
+        };
 
         #[test]
         fn description_contains() {
-            let e = power_outage_to_event(&PowerOutage {
-                area_name: "test-name".to_owned(),
-                stage: 2,
-                start: rfc3339("2022-01-02T13:00:00+02:00"),
-                finsh: rfc3339("2022-01-02T15:00:00+02:00"),
-                source: "test-source".to_owned(),
-            })
+            let e = power_outage_to_event(
+                &PowerOutage {
+                    area_name: "test-name".to_owned(),
+                    stage: 2,
+                    start: rfc3339("2022-01-02T13:00:00+02:00"),
+                    finsh: rfc3339("2022-01-02T15:00:00+02:00"),
+                    source: "test-source".to_owned(),
+                    cancelled: false,
+                    tentative: false,
+                },
+                &build(),
+            )
             .unwrap();
             let desc = e.get_description().unwrap();
 
@@ -855,25 +1239,130 @@ mod tests {
         fn start_and_finsh_correct() {
             let start = rfc3339("2022-01-02T13:00:00+02:00");
             let finsh = rfc3339("2022-01-02T15:00:00+02:00");
-            let e = power_outage_to_event(&PowerOutage {
+            let e = power_outage_to_event(
+                &PowerOutage {
+                    area_name: "test-name".to_owned(),
+                    stage: 2,
+                    start,
+                    finsh,
+                    source: "test-source".to_owned(),
+                    cancelled: false,
+                    tentative: false,
+                },
+                &build(),
+            )
+            .unwrap();
+            assert_eq!(e.get_start().unwrap(), start.with_timezone(&Utc).into());
+            assert_eq!(e.get_end().unwrap(), finsh.with_timezone(&Utc).into());
+        }
+
+        #[test]
+        fn same_inputs_give_same_event() {
+            let outage = PowerOutage {
                 area_name: "test-name".to_owned(),
                 stage: 2,
-                start,
-                finsh,
+                start: rfc3339("2022-01-02T13:00:00+02:00"),
+                finsh: rfc3339("2022-01-02T15:00:00+02:00"),
                 source: "test-source".to_owned(),
-            })
+                cancelled: false,
+                tentative: false,
+            };
+            let first = power_outage_to_event(&outage, &build()).unwrap();
+            let second = power_outage_to_event(&outage, &build()).unwrap();
+            assert_eq!(first.to_string(), second.to_string());
+            assert!(first
+                .get_description()
+                .unwrap()
+                .contains("tree/0123456789abcdef"));
+        }
+    }
+
+    mod exemption_event {
+        use icalendar::Component;
+
+        use crate::{
+            fmt::exemption_event,
+            structs::{Exemption, RawExemption},
+            tests::build,
+        };
+
+        #[test]
+        fn description_reads_as_a_sentence() {
+            let exemption: Exemption = serde_yaml::from_str::<RawExemption>(
+                "
+start: 2024-05-29T00:00:00
+finsh: 2024-05-30T00:00:00
+reason: General elections
+source: https://example.com
+",
+            )
+            .unwrap()
+            .try_into()
             .unwrap();
-            assert_eq!(e.get_start().unwrap(), start.with_timezone(&Utc).into());
-            assert_eq!(e.get_end().unwrap(), finsh.with_timezone(&Utc).into());
+            let e = exemption_event(&exemption, "western-cape-stellenbosch", &build()).unwrap();
+            let desc = e.get_description().unwrap();
+            let should_contain_all = vec![
+                "Loadshedding has been suspended for Stellenbosch (WC) from Wednesday 29 May at \
+                00:00 to Thursday 30 May at 00:00, so there won't be any loadshedding in this area \
+                then.",
+                "Reason: General elections",
+                "Source: https://example.com",
+            ];
+            for should_contain in should_contain_all {
+                assert!(
+                    desc.contains(should_contain),
+                    "Description should contain:\n\"{should_contain}\"\nbut is:\n{desc}"
+                );
+            }
+            assert!(
+                !desc.contains("  "),
+                "Description has a run of spaces:\n{desc}"
+            );
+            assert_eq!(e.get_summary(), Some("✅ General elections"));
+        }
+    }
+
+    mod parse_source_date_epoch {
+        use crate::parse_source_date_epoch;
+
+        #[test]
+        fn parses_seconds() {
+            let parsed = parse_source_date_epoch("1640995200\n").unwrap();
+            assert_eq!(parsed.to_rfc3339(), "2022-01-01T00:00:00+00:00");
+        }
+
+        #[test]
+        fn err_if_not_integer() {
+            assert!(parse_source_date_epoch("yesterday").is_err());
         }
     }
 
     mod check_for_overlaps {
+        use crate::structs::{Change, ManuallyInputSchedule, StageRule};
+
+        pub fn schedule(changes: Vec<Change>) -> ManuallyInputSchedule {
+            with_rules(changes, vec![])
+        }
+
+        pub fn with_rules(
+            changes: Vec<Change>,
+            stage_rules: Vec<StageRule>,
+        ) -> ManuallyInputSchedule {
+            ManuallyInputSchedule {
+                changes,
+                historical_changes: vec![],
+                stage_rules,
+                exemptions: vec![],
+                projected_until: None,
+            }
+        }
+
         mod err_if {
             use std::path::PathBuf;
             use std::str::FromStr;
 
-            use crate::structs::RawChange;
+            use super::{schedule, with_rules};
+            use crate::structs::{RawChange, RawStageRule};
             use crate::{err_if_overlaps, structs::Change};
 
             #[test]
@@ -888,8 +1377,11 @@ mod tests {
                         exclude_regex: None,
                         include: Some("coct".to_string()),
                         exclude: None,
+                        selector: Default::default(),
+                        until_further_notice: false,
                     }
-                    .into(),
+                    .try_into()
+                    .unwrap(),
                     RawChange {
                         start: "2022-01-01T11:00:00".to_string(),
                         finsh: "2022-01-01T12:00:00".to_string(),
@@ -899,8 +1391,11 @@ mod tests {
                         exclude_regex: None,
                         include: Some("coct".to_string()),
                         exclude: None,
+                        selector: Default::default(),
+                        until_further_notice: false,
                     }
-                    .into(),
+                    .try_into()
+                    .unwrap(),
                 ];
                 let paths = vec![
                     PathBuf::from_str("generated/city-of-cape-town-area-1.csv").unwrap(),
@@ -908,7 +1403,7 @@ mod tests {
                     PathBuf::from_str("generated/western-cape-stellenbosch.csv").unwrap(),
                     PathBuf::from_str("generated/western-cape-darling.csv.csv").unwrap(),
                 ];
-                assert!(err_if_overlaps(&changes, &paths).is_err())
+                assert!(err_if_overlaps(&schedule(changes), &paths).is_err())
             }
 
             #[test]
@@ -923,8 +1418,11 @@ mod tests {
                         exclude_regex: None,
                         include: Some("coct".to_string()),
                         exclude: None,
+                        selector: Default::default(),
+                        until_further_notice: false,
                     }
-                    .into(),
+                    .try_into()
+                    .unwrap(),
                     RawChange {
                         start: "2022-01-01T11:00:00".to_string(),
                         finsh: "2022-01-01T13:00:00".to_string(),
@@ -934,8 +1432,11 @@ mod tests {
                         exclude_regex: None,
                         include: Some("coct".to_string()),
                         exclude: None,
+                        selector: Default::default(),
+                        until_further_notice: false,
                     }
-                    .into(),
+                    .try_into()
+                    .unwrap(),
                 ];
                 let paths = vec![
                     PathBuf::from_str("generated/city-of-cape-town-area-1.csv").unwrap(),
@@ -943,7 +1444,46 @@ mod tests {
                     PathBuf::from_str("generated/western-cape-stellenbosch.csv").unwrap(),
                     PathBuf::from_str("generated/western-cape-darling.csv.csv").unwrap(),
                 ];
-                assert!(err_if_overlaps(&changes, &paths).is_err())
+                assert!(err_if_overlaps(&schedule(changes), &paths).is_err())
+            }
+
+            #[test]
+            fn delayed_national_change_overlaps_local_change() {
+                let change = |start: &str, finsh: &str, include: Option<&str>| -> Change {
+                    RawChange {
+                        start: start.to_string(),
+                        finsh: finsh.to_string(),
+                        stage: 1,
+                        source: "test_source".to_string(),
+                        include_regex: None,
+                        exclude_regex: None,
+                        include: include.map(str::to_string),
+                        exclude: None,
+                        selector: Default::default(),
+                        until_further_notice: false,
+                    }
+                    .try_into()
+                    .unwrap()
+                };
+                let changes = vec![
+                    change("2022-01-01T10:00:00", "2022-01-01T12:00:00", None),
+                    change("2022-01-01T12:00:00", "2022-01-01T14:00:00", Some("coct")),
+                ];
+                let paths =
+                    vec![PathBuf::from_str("generated/city-of-cape-town-area-1.csv").unwrap()];
+                // The changes don't overlap as written, but they do once the national change has
+                // been delayed for Cape Town
+                assert!(err_if_overlaps(&schedule(changes.clone()), &paths).is_ok());
+                let delay = RawStageRule {
+                    include: Some("coct".to_string()),
+                    include_regex: None,
+                    offset: 0,
+                    during: None,
+                    delay: Some("01:00".to_string()),
+                }
+                .try_into()
+                .unwrap();
+                assert!(err_if_overlaps(&with_rules(changes, vec![delay]), &paths).is_err());
             }
         }
 
@@ -951,6 +1491,7 @@ mod tests {
             use std::path::PathBuf;
             use std::str::FromStr;
 
+            use super::schedule;
             use crate::structs::RawChange;
             use crate::{err_if_overlaps, structs::Change};
             #[test]
@@ -965,8 +1506,11 @@ mod tests {
                         exclude_regex: None,
                         include: Some("coct".to_string()),
                         exclude: None,
+                        selector: Default::default(),
+                        until_further_notice: false,
                     }
-                    .into(),
+                    .try_into()
+                    .unwrap(),
                     RawChange {
                         start: "2022-01-01T11:00:00".to_string(),
                         finsh: "2022-01-01T13:00:00".to_string(),
@@ -976,8 +1520,11 @@ mod tests {
                         exclude_regex: None,
                         include: None,
                         exclude: Some("coct".to_string()),
+                        selector: Default::default(),
+                        until_further_notice: false,
                     }
-                    .into(),
+                    .try_into()
+                    .unwrap(),
                 ];
                 let paths = vec![
                     PathBuf::from_str("generated/city-of-cape-town-area-1.csv").unwrap(),
@@ -985,7 +1532,7 @@ mod tests {
                     PathBuf::from_str("generated/western-cape-stellenbosch.csv").unwrap(),
                     PathBuf::from_str("generated/western-cape-darling.csv.csv").unwrap(),
                 ];
-                assert!(err_if_overlaps(&changes, &paths).is_ok())
+                assert!(err_if_overlaps(&schedule(changes), &paths).is_ok())
             }
             #[test]
             fn ok_if_start_eq_finsh() {
@@ -999,8 +1546,11 @@ mod tests {
                         exclude_regex: None,
                         include: Some("coct".to_string()),
                         exclude: None,
+                        selector: Default::default(),
+                        until_further_notice: false,
                     }
-                    .into(),
+                    .try_into()
+                    .unwrap(),
                     RawChange {
                         start: "2022-01-01T12:30:00".to_string(),
                         finsh: "2022-01-01T14:30:00".to_string(),
@@ -1010,8 +1560,11 @@ mod tests {
                         exclude_regex: None,
                         exclude: Some("coct".to_string()),
                         include: None,
+                        selector: Default::default(),
+                        until_further_notice: false,
                     }
-                    .into(),
+                    .try_into()
+                    .unwrap(),
                 ];
                 let paths = vec![
                     PathBuf::from_str("generated/city-of-cape-town-area-1.csv").unwrap(),
@@ -1019,7 +1572,7 @@ mod tests {
                     PathBuf::from_str("generated/western-cape-stellenbosch.csv").unwrap(),
                     PathBuf::from_str("generated/western-cape-darling.csv.csv").unwrap(),
                 ];
-                assert!(err_if_overlaps(&changes, &paths).is_ok())
+                assert!(err_if_overlaps(&schedule(changes), &paths).is_ok())
             }
 
             #[test]
@@ -1034,8 +1587,11 @@ mod tests {
                         exclude_regex: None,
                         include: Some("coct".to_string()),
                         exclude: None,
+                        selector: Default::default(),
+                        until_further_notice: false,
                     }
-                    .into(),
+                    .try_into()
+                    .unwrap(),
                     RawChange {
                         start: "2022-01-01T10:00:00".to_string(),
                         finsh: "2022-01-01T12:30:00".to_string(),
@@ -1045,8 +1601,11 @@ mod tests {
                         exclude_regex: None,
                         exclude: Some("coct".to_string()),
                         include: None,
+                        selector: Default::default(),
+                        until_further_notice: false,
                     }
-                    .into(),
+                    .try_into()
+                    .unwrap(),
                 ];
                 let paths = vec![
                     PathBuf::from_str("generated/city-of-cape-town-area-1.csv").unwrap(),
@@ -1054,27 +1613,34 @@ mod tests {
                     PathBuf::from_str("generated/western-cape-stellenbosch.csv").unwrap(),
                     PathBuf::from_str("generated/western-cape-darling.csv.csv").unwrap(),
                 ];
-                assert!(err_if_overlaps(&changes, &paths).is_ok())
+                assert!(err_if_overlaps(&schedule(changes), &paths).is_ok())
             }
 
             #[test]
             fn ok_if_empty_vecs() {
-                let changes = vec![];
+                let changes: Vec<Change> = vec![];
                 let paths = vec![];
-                assert!(err_if_overlaps(&changes, &paths).is_ok())
+                assert!(err_if_overlaps(&schedule(changes), &paths).is_ok())
             }
         }
     }
 
     mod write_sheddings_to_ics {
-        use chrono::{DateTime, Duration, Utc};
+        use chrono::Duration;
         use icalendar::{CalendarDateTime, Component, DatePerhapsTime};
 
-        use crate::tests::rfc3339;
-        use crate::{structs::PowerOutage, write_sheddings_to_ics};
-        use std::fs::remove_file;
+        use crate::tests::{build, rfc3339};
+        use crate::{config::Config, structs::PowerOutage, write_sheddings_to_ics};
+        use std::fs::{create_dir_all, remove_file};
         use std::path::PathBuf;
 
+        /// Each test writes to its own directory, so they don't clash when run in parallel.
+        fn output_dir(name: &str) -> PathBuf {
+            let dir = std::env::temp_dir().join(format!("eskom-calendar-test-ics-{name}"));
+            create_dir_all(&dir).unwrap();
+            dir
+        }
+
         #[test]
         fn doesnt_remove_sequential_events() {
             let mut power_outages = vec![
@@ -1085,6 +1651,8 @@ mod tests {
                     start: rfc3339("2023-05-29T18:00:00+02:00"),
                     finsh: rfc3339("2023-05-29T20:00:00+02:00"),
                     source: "test source".to_string(),
+                    cancelled: false,
+                    tentative: false,
                 },
                 PowerOutage {
                     area_name: "test_area".to_string(),
@@ -1092,6 +1660,8 @@ mod tests {
                     start: rfc3339("2023-05-29T20:00:00+02:00"),
                     finsh: rfc3339("2023-05-29T20:30:00+02:00"),
                     source: "test source".to_string(),
+                    cancelled: false,
+                    tentative: false,
                 },
                 // First the 30 minute event, then the long event
                 PowerOutage {
@@ -1100,6 +1670,8 @@ mod tests {
                     start: rfc3339("2023-05-29T10:00:00+02:00"),
                     finsh: rfc3339("2023-05-29T10:30:00+02:00"),
                     source: "test source".to_string(),
+                    cancelled: false,
+                    tentative: false,
                 },
                 PowerOutage {
                     area_name: "test_area".to_string(),
@@ -1107,6 +1679,8 @@ mod tests {
                     start: rfc3339("2023-05-29T10:30:00+02:00"),
                     finsh: rfc3339("2023-05-29T12:00:00+02:00"),
                     source: "test source".to_string(),
+                    cancelled: false,
+                    tentative: false,
                 },
             ];
 
@@ -1115,16 +1689,23 @@ mod tests {
                 .max_by_key(|outage| outage.finsh)
                 .map(|outage| outage.finsh);
 
+            let config = Config {
+                output_dir: output_dir("sequential"),
+                ..Default::default()
+            };
             let calendar = write_sheddings_to_ics(
                 &PathBuf::from("test.csv"),
                 &mut power_outages,
                 last_finsh,
+                &[],
                 vec![],
                 rfc3339("2099-01-01T00:00:00+02:00"),
+                &build(),
+                &config,
             )
             .unwrap();
 
-            let _ = remove_file("test.ics");
+            let _ = remove_file(config.ics_path("test"));
 
             let events: Vec<_> = calendar
                 .components
@@ -1132,7 +1713,7 @@ mod tests {
                 .filter_map(|c| c.as_event())
                 // Filter out all the non-loadshedding events
                 .filter(|e| {
-                    e.get_summary().map_or(false, |s| {
+                    e.get_summary().is_some_and(|s| {
                         !s.contains("End of schedule") && !s.contains("Schedule expired")
                     })
                 })
@@ -1141,6 +1722,69 @@ mod tests {
             assert!(events.len() == power_outages.len());
         }
 
+        #[test]
+        fn cancelled_outages_dont_collide_with_live_ones() {
+            let outage = |stage, start, finsh, cancelled| PowerOutage {
+                area_name: "test_area".to_string(),
+                stage,
+                start: rfc3339(start),
+                finsh: rfc3339(finsh),
+                source: "test source".to_string(),
+                cancelled,
+                tentative: false,
+            };
+            let mut power_outages = vec![
+                // A stale cancelled outage right before a short live one
+                outage(
+                    2,
+                    "2023-05-29T18:00:00+02:00",
+                    "2023-05-29T20:00:00+02:00",
+                    true,
+                ),
+                outage(
+                    4,
+                    "2023-05-29T20:00:00+02:00",
+                    "2023-05-29T20:30:00+02:00",
+                    false,
+                ),
+                // A short cancelled outage
+                outage(
+                    4,
+                    "2023-05-30T10:00:00+02:00",
+                    "2023-05-30T10:30:00+02:00",
+                    true,
+                ),
+            ];
+            let config = Config {
+                output_dir: output_dir("cancelled"),
+                ..Default::default()
+            };
+            let calendar = write_sheddings_to_ics(
+                &PathBuf::from("test.csv"),
+                &mut power_outages,
+                None,
+                &[],
+                vec![],
+                rfc3339("2099-01-01T00:00:00+02:00"),
+                &build(),
+                &config,
+            )
+            .unwrap();
+            let _ = remove_file(config.ics_path("test"));
+
+            let summaries: Vec<_> = calendar
+                .components
+                .iter()
+                .filter_map(|c| c.as_event())
+                .map(|e| e.get_summary().unwrap().to_owned())
+                .collect();
+            assert_eq!(summaries.len(), 2, "{summaries:?}");
+            assert!(
+                summaries.iter().all(|s| s.starts_with("❌")),
+                "{summaries:?}"
+            );
+        }
+
         #[test]
         fn removes_events_le_30_minutes() {
             let mut power_outages = vec![
@@ -1151,6 +1795,8 @@ mod tests {
                     start: rfc3339("2023-05-29T10:00:00+02:00"),
                     finsh: rfc3339("2023-05-29T10:30:00+02:00"),
                     source: "test source".to_string(),
+                    cancelled: false,
+                    tentative: false,
                 },
                 // Should be removed (<30m)
                 PowerOutage {
@@ -1159,6 +1805,8 @@ mod tests {
                     start: rfc3339("2023-05-29T18:00:00+02:00"),
                     finsh: rfc3339("2023-05-29T18:10:00+02:00"),
                     source: "test source".to_string(),
+                    cancelled: false,
+                    tentative: false,
                 },
                 // Should be kept (>30m)
                 PowerOutage {
@@ -1167,6 +1815,8 @@ mod tests {
                     start: rfc3339("2023-05-29T20:00:00+02:00"),
                     finsh: rfc3339("2023-05-29T20:31:00+02:00"),
                     source: "test source".to_string(),
+                    cancelled: false,
+                    tentative: false,
                 },
             ];
 
@@ -1175,15 +1825,22 @@ mod tests {
                 .max_by_key(|outage| outage.finsh)
                 .map(|outage| outage.finsh);
 
+            let config = Config {
+                output_dir: output_dir("le-30-minutes"),
+                ..Default::default()
+            };
             let calendar = write_sheddings_to_ics(
                 &PathBuf::from("test.csv"),
                 &mut power_outages,
                 last_finsh,
+                &[],
                 vec![],
                 rfc3339("2099-01-01T00:00:00+02:00"),
+                &build(),
+                &config,
             )
             .unwrap();
-            let _ = remove_file("test.ics");
+            let _ = remove_file(config.ics_path("test"));
 
             let events: Vec<_> = calendar
                 .components
@@ -1222,9 +1879,97 @@ mod tests {
         }
     }
 
+    mod generate {
+        use chrono::Duration;
+        use std::fs::{create_dir_all, read_to_string, remove_dir_all, write, OpenOptions};
+        use std::io::Write;
+
+        use crate::config::{Config, Outputs};
+        use crate::structs::PowerOutage;
+        use crate::{generate, query};
+
+        #[test]
+        fn withdrawn_outage_rewrites_unchanged_area() {
+            let dir = std::env::temp_dir().join("eskom-calendar-test-generate-withdrawn");
+            let _ = remove_dir_all(&dir);
+            create_dir_all(dir.join("generated")).unwrap();
+            let schedule = (1..=31)
+                .map(|day| format!("{day},10:00,12:30,4\n"))
+                .collect::<String>();
+            write(
+                dir.join("generated/test-area.csv"),
+                format!("date_of_month,start_time,finsh_time,stage\n{schedule}"),
+            )
+            .unwrap();
+            let today = query::now_sast().date_naive();
+            write(
+                dir.join("manually_specified.yaml"),
+                format!(
+                    "
+changes:
+- stage: 4
+  start: {today}T00:00:00
+  finsh: {}T00:00:00
+  source: test source
+historical_changes: []
+",
+                    today + Duration::days(3)
+                ),
+            )
+            .unwrap();
+            write(dir.join("area_metadata.yaml"), "area_details: []\n").unwrap();
+            let config = Config {
+                generated_dir: dir.join("generated"),
+                manually_specified: dir.join("manually_specified.yaml"),
+                area_metadata: dir.join("area_metadata.yaml"),
+                output_dir: dir.join("calendars"),
+                outputs: Outputs {
+                    ics_files: true,
+                    csv_file: true,
+                    parquet_files: false,
+                    search_index: false,
+                    geojson_file: false,
+                    timeline_files: false,
+                },
+                ..Default::default()
+            };
+            create_dir_all(&config.output_dir).unwrap();
+            generate(&config, None).unwrap();
+            let ics = config.ics_path("test-area");
+            assert!(!read_to_string(&ics).unwrap().contains("STATUS:CANCELLED"));
+
+            // Pretend that the previous run published an outage which has since been withdrawn,
+            // without changing any of the area's inputs
+            let withdrawn = PowerOutage {
+                area_name: "test-area".to_owned(),
+                stage: 4,
+                start: query::now_sast() + Duration::hours(1),
+                finsh: query::now_sast() + Duration::hours(3),
+                source: "withdrawn source".to_owned(),
+                cancelled: false,
+                tentative: false,
+            };
+            let mut csv = OpenOptions::new()
+                .append(true)
+                .open(config.output_path(&config.csv_file_name))
+                .unwrap();
+            writeln!(csv, "{withdrawn}").unwrap();
+            generate(&config, None).unwrap();
+            let calendar = read_to_string(&ics).unwrap();
+            remove_dir_all(&dir).unwrap();
+            assert!(
+                calendar.contains("STATUS:CANCELLED"),
+                "The withdrawn outage should be cancelled in:\n{calendar}"
+            );
+        }
+    }
+
     mod gen_datetimes {
-        use crate::{structs::Recurrence, tests::rfc3339};
-        use chrono::{Datelike, NaiveTime};
+        use crate::{
+            structs::{Cycle, Recurrence},
+            tests::rfc3339,
+        };
+        use chrono::{Datelike, Duration, NaiveDate, NaiveTime};
 
         use crate::gen_datetimes;
 
@@ -1233,11 +1978,10 @@ mod tests {
             let start_dt = rfc3339("2022-01-02T00:00:00+02:00");
             let finsh_dt = rfc3339("2022-01-02T01:00:00+02:00");
             let dor = 1;
-            let recurrence = Recurrence::Monthly;
+            let recurrence = Recurrence::Monthly { date_of_month: dor };
             let start_time = NaiveTime::from_hms_opt(23, 30, 0).unwrap();
-            let finsh_time = NaiveTime::from_hms_opt(0, 30, 0).unwrap();
-            let datetimes =
-                gen_datetimes(start_dt, finsh_dt, dor, recurrence, start_time, finsh_time);
+            let duration = Duration::hours(1);
+            let datetimes = gen_datetimes(start_dt, finsh_dt, recurrence, start_time, duration);
             assert_eq!(
                 datetimes,
                 vec![(
@@ -1252,11 +1996,10 @@ mod tests {
             let start_dt = rfc3339("2022-01-01T10:00:00+02:00");
             let finsh_dt = rfc3339("2022-01-01T20:00:00+02:00");
             let dor = 1;
-            let recurrence = Recurrence::Monthly;
+            let recurrence = Recurrence::Monthly { date_of_month: dor };
             let start_time = NaiveTime::from_hms_opt(19, 0, 0).unwrap();
-            let finsh_time = NaiveTime::from_hms_opt(21, 0, 0).unwrap();
-            let datetimes =
-                gen_datetimes(start_dt, finsh_dt, dor, recurrence, start_time, finsh_time);
+            let duration = Duration::hours(2);
+            let datetimes = gen_datetimes(start_dt, finsh_dt, recurrence, start_time, duration);
             assert_eq!(
                 datetimes,
                 vec![(
@@ -1271,11 +2014,10 @@ mod tests {
             let start_dt = rfc3339("2022-01-01T10:00:00+02:00");
             let finsh_dt = rfc3339("2022-01-01T20:00:00+02:00");
             let dor = 1;
-            let recurrence = Recurrence::Monthly;
+            let recurrence = Recurrence::Monthly { date_of_month: dor };
             let start_time = NaiveTime::from_hms_opt(9, 0, 0).unwrap();
-            let finsh_time = NaiveTime::from_hms_opt(11, 0, 0).unwrap();
-            let datetimes =
-                gen_datetimes(start_dt, finsh_dt, dor, recurrence, start_time, finsh_time);
+            let duration = Duration::hours(2);
+            let datetimes = gen_datetimes(start_dt, finsh_dt, recurrence, start_time, duration);
             assert_eq!(
                 datetimes,
                 vec![(
@@ -1285,37 +2027,19 @@ mod tests {
             );
         }
 
-        /// The date of recurrence isn't allowed to be greater than the period length
-        #[should_panic]
-        #[test]
-        fn dor_greater_than_period() {
-            let _ = gen_datetimes(
-                rfc3339("2023-02-18T00:00:00+02:00"),
-                rfc3339("2023-06-11T00:00:00+02:00"),
-                2,
-                Recurrence::Periodic {
-                    offset: rfc3339("2023-02-18T00:00:00+02:00").date_naive(),
-                    period: 1,
-                },
-                NaiveTime::from_hms_opt(12, 0, 0).unwrap(),
-                NaiveTime::from_hms_opt(14, 0, 0).unwrap(),
-            );
-        }
-
         #[test]
         fn nelson_mandela_bay_end() {
             // Example taken from https://nelsonmandelabay.gov.za/page/loadshedding
             let start_dt = rfc3339("2023-02-18T00:00:00+02:00");
             let finsh_dt = rfc3339("2023-06-11T00:00:00+02:00");
             let dor = 19;
-            let recurrence = Recurrence::Periodic {
-                offset: rfc3339("2023-02-18T00:00:00+02:00").date_naive(),
-                period: 19,
-            };
+            let recurrence = Recurrence::Periodic(
+                Cycle::new(rfc3339("2023-02-18T00:00:00+02:00").date_naive(), 19, dor).unwrap(),
+            );
             let start_time = NaiveTime::from_hms_opt(12, 0, 0).unwrap();
-            let finsh_time = NaiveTime::from_hms_opt(14, 0, 0).unwrap();
-            let actual = gen_datetimes(start_dt, finsh_dt, dor, recurrence, start_time, finsh_time);
-            let expected = vec![
+            let duration = Duration::hours(2);
+            let actual = gen_datetimes(start_dt, finsh_dt, recurrence, start_time, duration);
+            let expected = [
                 (
                     rfc3339("2023-03-08T12:00:00+02:00"),
                     rfc3339("2023-03-08T14:00:00+02:00"),
@@ -1353,14 +2077,12 @@ mod tests {
             let start_dt = rfc3339("2023-02-18T00:00:00+02:00");
             let finsh_dt = rfc3339("2023-06-11T00:00:00+02:00");
             let dor = 1;
-            let recurrence = Recurrence::Periodic {
-                offset: start_dt.date_naive(),
-                period: 19,
-            };
+            let recurrence =
+                Recurrence::Periodic(Cycle::new(start_dt.date_naive(), 19, dor).unwrap());
             let start_time = NaiveTime::from_hms_opt(12, 0, 0).unwrap();
-            let finsh_time = NaiveTime::from_hms_opt(14, 0, 0).unwrap();
-            let actual = gen_datetimes(start_dt, finsh_dt, dor, recurrence, start_time, finsh_time);
-            let expected = vec![
+            let duration = Duration::hours(2);
+            let actual = gen_datetimes(start_dt, finsh_dt, recurrence, start_time, duration);
+            let expected = [
                 (
                     rfc3339("2023-02-18T12:00:00+02:00"),
                     rfc3339("2023-02-18T14:00:00+02:00"),
@@ -1398,11 +2120,10 @@ mod tests {
             let start_dt = rfc3339("2022-01-01T00:00:00+02:00");
             let finsh_dt = rfc3339("2022-02-01T00:00:00+02:00");
             let dor = 1; // Sunday
-            let recurrence = Recurrence::Weekly;
+            let recurrence = Recurrence::Weekly { day_of_week: dor };
             let start_time = NaiveTime::from_hms_opt(12, 0, 0).unwrap();
-            let finsh_time = NaiveTime::from_hms_opt(14, 0, 0).unwrap();
-            let datetimes =
-                gen_datetimes(start_dt, finsh_dt, dor, recurrence, start_time, finsh_time);
+            let duration = Duration::hours(2);
+            let datetimes = gen_datetimes(start_dt, finsh_dt, recurrence, start_time, duration);
             // Assert that the dates are all on the correct day of the recurrence
             for (s, f) in &datetimes {
                 assert_eq!(s.weekday().number_from_monday(), dor as u32);
@@ -1441,11 +2162,10 @@ mod tests {
             let start_dt = rfc3339("2022-01-01T00:00:00+02:00");
             let finsh_dt = rfc3339("2022-02-01T00:00:00+02:00");
             let dor = 7; // Sunday
-            let recurrence = Recurrence::Weekly;
+            let recurrence = Recurrence::Weekly { day_of_week: dor };
             let start_time = NaiveTime::from_hms_opt(12, 0, 0).unwrap();
-            let finsh_time = NaiveTime::from_hms_opt(14, 0, 0).unwrap();
-            let datetimes =
-                gen_datetimes(start_dt, finsh_dt, dor, recurrence, start_time, finsh_time);
+            let duration = Duration::hours(2);
+            let datetimes = gen_datetimes(start_dt, finsh_dt, recurrence, start_time, duration);
             // Assert that the dates are all on the correct day of the recurrence
             for (s, f) in &datetimes {
                 assert_eq!(s.weekday().number_from_monday(), dor as u32);
@@ -1486,11 +2206,10 @@ mod tests {
             let start_dt = rfc3339("2022-01-01T00:00:00+02:00");
             let finsh_dt = rfc3339("2023-01-01T00:00:00+02:00");
             let dor = 31;
-            let recurrence = Recurrence::Monthly;
+            let recurrence = Recurrence::Monthly { date_of_month: dor };
             let start_time = NaiveTime::from_hms_opt(12, 0, 0).unwrap();
-            let finsh_time = NaiveTime::from_hms_opt(14, 0, 0).unwrap();
-            let datetimes =
-                gen_datetimes(start_dt, finsh_dt, dor, recurrence, start_time, finsh_time);
+            let duration = Duration::hours(2);
+            let datetimes = gen_datetimes(start_dt, finsh_dt, recurrence, start_time, duration);
             assert_eq!(
                 datetimes,
                 vec![
@@ -1531,11 +2250,10 @@ mod tests {
             let start_dt = rfc3339("2022-01-01T00:00:00+02:00");
             let finsh_dt = rfc3339("2023-01-01T00:00:00+02:00");
             let dor = 1;
-            let recurrence = Recurrence::Monthly;
+            let recurrence = Recurrence::Monthly { date_of_month: dor };
             let start_time = NaiveTime::from_hms_opt(12, 0, 0).unwrap();
-            let finsh_time = NaiveTime::from_hms_opt(14, 0, 0).unwrap();
-            let datetimes =
-                gen_datetimes(start_dt, finsh_dt, dor, recurrence, start_time, finsh_time);
+            let duration = Duration::hours(2);
+            let datetimes = gen_datetimes(start_dt, finsh_dt, recurrence, start_time, duration);
             assert_eq!(
                 datetimes,
                 vec![
@@ -1596,11 +2314,10 @@ mod tests {
             let start_dt = rfc3339("2022-12-31T00:00:00+02:00");
             let finsh_dt = rfc3339("2023-01-02T00:00:00+02:00");
             let dor = 1;
-            let recurrence = Recurrence::Monthly;
+            let recurrence = Recurrence::Monthly { date_of_month: dor };
             let start_time = NaiveTime::from_hms_opt(12, 0, 0).unwrap();
-            let finsh_time = NaiveTime::from_hms_opt(14, 0, 0).unwrap();
-            let datetimes =
-                gen_datetimes(start_dt, finsh_dt, dor, recurrence, start_time, finsh_time);
+            let duration = Duration::hours(2);
+            let datetimes = gen_datetimes(start_dt, finsh_dt, recurrence, start_time, duration);
             assert_eq!(
                 datetimes,
                 vec![(
@@ -1615,11 +2332,10 @@ mod tests {
             let start_dt = rfc3339("2022-01-31T00:00:00+02:00");
             let finsh_dt = rfc3339("2022-02-02T00:00:00+02:00");
             let dor = 1;
-            let recurrence = Recurrence::Monthly;
+            let recurrence = Recurrence::Monthly { date_of_month: dor };
             let start_time = NaiveTime::from_hms_opt(12, 0, 0).unwrap();
-            let finsh_time = NaiveTime::from_hms_opt(14, 0, 0).unwrap();
-            let datetimes =
-                gen_datetimes(start_dt, finsh_dt, dor, recurrence, start_time, finsh_time);
+            let duration = Duration::hours(2);
+            let datetimes = gen_datetimes(start_dt, finsh_dt, recurrence, start_time, duration);
             assert_eq!(
                 datetimes,
                 vec![(
@@ -1634,11 +2350,10 @@ mod tests {
             let start_dt = rfc3339("2022-01-01T00:00:00+02:00");
             let finsh_dt = rfc3339("2022-04-01T00:00:00+02:00");
             let dor = 1;
-            let recurrence = Recurrence::Monthly;
+            let recurrence = Recurrence::Monthly { date_of_month: dor };
             let start_time = NaiveTime::from_hms_opt(22, 0, 0).unwrap();
-            let finsh_time = NaiveTime::from_hms_opt(0, 30, 0).unwrap();
-            let datetimes =
-                gen_datetimes(start_dt, finsh_dt, dor, recurrence, start_time, finsh_time);
+            let duration = Duration::minutes(150);
+            let datetimes = gen_datetimes(start_dt, finsh_dt, recurrence, start_time, duration);
             assert_eq!(
                 datetimes,
                 vec![
@@ -1657,5 +2372,43 @@ mod tests {
                 ]
             );
         }
+
+        #[test]
+        fn longer_than_a_day() {
+            // An outage from 20:00 on the 1st to 06:00 on the 3rd is still going when the national
+            // schedule starts on the 2nd
+            let start_dt = rfc3339("2022-01-02T12:00:00+02:00");
+            let finsh_dt = rfc3339("2022-01-04T00:00:00+02:00");
+            let recurrence = Recurrence::Monthly { date_of_month: 1 };
+            let start_time = NaiveTime::from_hms_opt(20, 0, 0).unwrap();
+            let duration = Duration::hours(34);
+            let datetimes = gen_datetimes(start_dt, finsh_dt, recurrence, start_time, duration);
+            assert_eq!(
+                datetimes,
+                vec![(
+                    rfc3339("2022-01-02T12:00:00+02:00"),
+                    rfc3339("2022-01-03T06:00:00+02:00")
+                )]
+            );
+        }
+
+        #[test]
+        fn dated_only_on_its_date() {
+            let start_dt = rfc3339("2022-01-01T00:00:00+02:00");
+            let finsh_dt = rfc3339("2023-01-01T00:00:00+02:00");
+            let recurrence = Recurrence::Dated {
+                date: NaiveDate::from_ymd_opt(2022, 3, 15).unwrap(),
+            };
+            let start_time = NaiveTime::from_hms_opt(22, 0, 0).unwrap();
+            let duration = Duration::minutes(150);
+            let datetimes = gen_datetimes(start_dt, finsh_dt, recurrence, start_time, duration);
+            assert_eq!(
+                datetimes,
+                vec![(
+                    rfc3339("2022-03-15T22:00:00+02:00"),
+                    rfc3339("2022-03-16T00:30:00+02:00")
+                )]
+            );
+        }
     }
 }
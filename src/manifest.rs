@@ -0,0 +1,249 @@
+//! Writes output files atomically, and keeps a `manifest.json` describing every file in
+//! `calendars/` so that the website and mirrors can check they've got complete, untampered files.
+use crate::structs::PowerOutage;
+use crate::BoxedError;
+use chrono::{DateTime, FixedOffset};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{read, read_to_string, remove_file, rename, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Write a file by calling `write` on a temporary file in the same directory, and then renaming it
+/// to `path`. Renaming is atomic, so if the program crashes part way through, `path` still has its
+/// previous contents instead of being truncated.
+pub fn write_atomically(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> Result<(), BoxedError>,
+) -> Result<(), BoxedError> {
+    let fname = path
+        .file_name()
+        .and_then(|fname| fname.to_str())
+        .ok_or_else(|| format!("Couldn't get the file name of {path:?}"))?;
+    // Start with a `.` so that the temporary file isn't picked up by `ls calendars/*`
+    let tmp_path = path.with_file_name(format!(".{fname}.tmp"));
+
+    let mut file = File::create(&tmp_path)?;
+    let result = write(&mut file).and_then(|()| Ok(file.sync_all()?));
+    drop(file);
+    match result {
+        Ok(()) => Ok(rename(&tmp_path, path)?),
+        Err(e) => {
+            let _ = remove_file(&tmp_path);
+            Err(e)
+        }
+    }
+}
+
+/// The hex-encoded SHA-256 hash of the file at `path`.
+pub fn sha256_of_file(path: &Path) -> Result<String, BoxedError> {
+    Ok(Sha256::digest(read(path)?)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+/// A description of every file in the output directory (which is `calendars/` by default).
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Manifest {
+    /// The directory containing the manifest and the files it describes
+    #[serde(skip)]
+    dir: PathBuf,
+    /// When the manifest was last written
+    pub generated_at: Option<DateTime<FixedOffset>>,
+    /// Maps each file's path (relative to the manifest's directory) to a description of it
+    pub files: BTreeMap<String, ManifestEntry>,
+}
+
+/// A description of one output file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    /// The hex-encoded SHA-256 hash of the file
+    pub sha256: String,
+    /// The number of events (for ICS files) or outages (for CSV files) in the file, if it
+    /// contains any
+    pub event_count: Option<usize>,
+    /// The start of the earliest outage in the file which hasn't been cancelled
+    pub first_outage: Option<DateTime<FixedOffset>>,
+    /// The end of the latest outage in the file which hasn't been cancelled
+    pub last_outage: Option<DateTime<FixedOffset>>,
+    /// When the file was written
+    pub generated_at: DateTime<FixedOffset>,
+}
+
+/// The outages which haven't been cancelled.
+fn active(outages: &[PowerOutage]) -> impl Iterator<Item = &PowerOutage> {
+    outages.iter().filter(|outage| !outage.cancelled)
+}
+
+impl ManifestEntry {
+    /// Describe the file at `path`, which was just written and contains `outages`.
+    pub fn new(
+        path: &Path,
+        event_count: Option<usize>,
+        outages: &[PowerOutage],
+        generated_at: DateTime<FixedOffset>,
+    ) -> Result<ManifestEntry, BoxedError> {
+        Ok(ManifestEntry {
+            sha256: sha256_of_file(path)?,
+            event_count,
+            first_outage: active(outages).map(|outage| outage.start).min(),
+            last_outage: active(outages).map(|outage| outage.finsh).max(),
+            generated_at,
+        })
+    }
+}
+
+impl Manifest {
+    /// Read the manifest from `path`. Files which weren't rewritten during this run keep their
+    /// entries from the previous run, so a missing or unreadable manifest is treated as empty.
+    pub fn read(path: &Path) -> Manifest {
+        let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        let Ok(contents) = read_to_string(path) else {
+            info!("No manifest found at {path:?}");
+            return Manifest {
+                dir,
+                ..Default::default()
+            };
+        };
+        let manifest = serde_json::from_str(&contents).unwrap_or_else(|e| {
+            warn!("Ignoring the manifest at {path:?} because it couldn't be parsed: {e}");
+            Manifest::default()
+        });
+        Manifest { dir, ..manifest }
+    }
+
+    /// The key used for `path`, which is its path relative to the manifest's directory.
+    fn key(&self, path: &Path) -> String {
+        path.strip_prefix(&self.dir)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Whether the manifest has an entry for the file at `path`.
+    pub fn contains(&self, path: &Path) -> bool {
+        self.files.contains_key(&self.key(path))
+    }
+
+    /// Add or replace the entry for the file at `path`.
+    pub fn insert(&mut self, path: &Path, entry: ManifestEntry) {
+        self.files.insert(self.key(path), entry);
+    }
+
+    /// Forget about every file which no longer exists, and then atomically write the manifest to
+    /// `path`.
+    pub fn write(
+        &mut self,
+        path: &Path,
+        generated_at: DateTime<FixedOffset>,
+    ) -> Result<(), BoxedError> {
+        let dir = &self.dir;
+        self.files.retain(|key, _entry| dir.join(key).exists());
+        self.generated_at = Some(generated_at);
+        info!("Writing manifest of {} files to {path:?}", self.files.len());
+        write_atomically(path, |file| {
+            let mut writer = BufWriter::new(file);
+            serde_json::to_writer_pretty(&mut writer, self)?;
+            writeln!(writer)?;
+            Ok(writer.flush()?)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::manifest::{sha256_of_file, write_atomically, Manifest, ManifestEntry};
+    use crate::structs::PowerOutage;
+    use chrono::DateTime;
+    use std::fs::{create_dir_all, read_to_string, remove_dir_all, write};
+    use std::io::Write;
+
+    fn outage(start: &str, finsh: &str) -> PowerOutage {
+        PowerOutage {
+            area_name: "test-area".to_owned(),
+            stage: 2,
+            start: DateTime::parse_from_rfc3339(start).unwrap(),
+            finsh: DateTime::parse_from_rfc3339(finsh).unwrap(),
+            source: "test source".to_owned(),
+            cancelled: false,
+            tentative: false,
+        }
+    }
+
+    #[test]
+    fn failed_write_keeps_previous_contents() {
+        let dir = std::env::temp_dir().join("eskom-calendar-test-atomic");
+        create_dir_all(&dir).unwrap();
+        let path = dir.join("test.ics");
+        write(&path, "old").unwrap();
+
+        let result = write_atomically(&path, |file| {
+            write!(file, "partial")?;
+            Err(Box::from("crashed part way through"))
+        });
+        let contents = read_to_string(&path).unwrap();
+        let leftovers = dir.read_dir().unwrap().count();
+        remove_dir_all(&dir).unwrap();
+
+        assert!(result.is_err());
+        assert_eq!(contents, "old");
+        assert_eq!(leftovers, 1, "The temporary file should be removed");
+    }
+
+    #[test]
+    fn successful_write_replaces_contents() {
+        let dir = std::env::temp_dir().join("eskom-calendar-test-atomic-ok");
+        create_dir_all(&dir).unwrap();
+        let path = dir.join("test.ics");
+        write(&path, "old").unwrap();
+
+        write_atomically(&path, |file| Ok(write!(file, "new")?)).unwrap();
+        let contents = read_to_string(&path).unwrap();
+        remove_dir_all(&dir).unwrap();
+
+        assert_eq!(contents, "new");
+    }
+
+    #[test]
+    fn sha256_of_known_contents() {
+        let path = std::env::temp_dir().join("eskom-calendar-test-sha256.txt");
+        write(&path, "abc").unwrap();
+        let sha256 = sha256_of_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn entries_describe_outages() {
+        let dir = std::env::temp_dir().join("eskom-calendar-test-manifest");
+        create_dir_all(&dir).unwrap();
+        let path = dir.join("test.csv");
+        write(&path, "abc").unwrap();
+        let generated_at = DateTime::parse_from_rfc3339("2023-05-01T00:00:00+02:00").unwrap();
+        let outages = vec![
+            outage("2023-05-29T18:00:00+02:00", "2023-05-29T20:00:00+02:00"),
+            outage("2023-05-29T10:00:00+02:00", "2023-05-29T12:00:00+02:00"),
+        ];
+        let entry = ManifestEntry::new(&path, Some(2), &outages, generated_at).unwrap();
+        assert_eq!(entry.first_outage, Some(outages[1].start));
+        assert_eq!(entry.last_outage, Some(outages[0].finsh));
+
+        // Entries for files which don't exist anymore are removed when writing
+        let manifest_path = dir.join("manifest.json");
+        let mut manifest = Manifest::read(&manifest_path);
+        manifest.insert(&path, entry.clone());
+        manifest.insert(&dir.join("deleted.ics"), entry);
+        manifest.write(&manifest_path, generated_at).unwrap();
+        let read = Manifest::read(&manifest_path);
+        remove_dir_all(&dir).unwrap();
+
+        assert_eq!(read, manifest);
+        assert_eq!(read.files.keys().collect::<Vec<_>>(), vec!["test.csv"]);
+    }
+}
@@ -0,0 +1,227 @@
+//! Rewrites the `generated/` CSVs in a canonical form, so that the same schedule is always written
+//! the same way no matter who transcribed it: no padding around the values, times as `HH:MM`, and
+//! the rows sorted by day (or date, or rule), start time, finish time and stage with no duplicates.
+use crate::manifest::write_atomically;
+use crate::source::{CsvSource, ScheduleSource};
+use crate::structs::{Recurrence, RecurringShedding};
+use crate::BoxedError;
+use log::info;
+use std::fs::read_to_string;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// The canonical CSV row of a shedding, without the trailing newline.
+fn canonical_row(shedding: &RecurringShedding) -> String {
+    let (start, finsh) = shedding.format_times();
+    match shedding.recurrence {
+        Recurrence::Weekly { day_of_week: day } | Recurrence::Monthly { date_of_month: day } => {
+            format!("{day},{start},{finsh},{}", shedding.stage)
+        }
+        Recurrence::Periodic(cycle) => format!(
+            "{},{},{},{start},{finsh},{}",
+            cycle.day(),
+            cycle.period(),
+            cycle.start().format("%Y-%m-%d"),
+            shedding.stage
+        ),
+        Recurrence::Dated { date } => format!(
+            "{},{start},{finsh},{}",
+            date.format("%Y-%m-%d"),
+            shedding.stage
+        ),
+        // Rules are always quoted, since they can contain commas (like `BYDAY=MO,TU`)
+        Recurrence::Rule(rule) => format!(
+            "\"{rule}\",{},{start},{finsh},{}",
+            rule.start().format("%Y-%m-%d"),
+            shedding.stage
+        ),
+    }
+}
+
+/// The canonical contents of the CSV schedule at `path`.
+pub fn canonical_csv(path: &Path) -> Result<String, BoxedError> {
+    // The columns are always written in the same order, whatever order they were in before
+    let contents = read_to_string(path)?;
+    let first_line = contents
+        .lines()
+        .find(|line| !line.starts_with('#'))
+        .ok_or_else(|| format!("{path:?} is empty"))?;
+    let columns = first_line.split(',').map(str::trim).collect::<Vec<_>>();
+    let header = if columns.contains(&"date_of_month") {
+        "date_of_month,start_time,finsh_time,stage"
+    } else if columns.contains(&"day_of_week") {
+        "day_of_week,start_time,finsh_time,stage"
+    } else if columns.contains(&"day_of_cycle") {
+        "day_of_cycle,period_of_cycle,start_of_cycle,start_time,finsh_time,stage"
+    } else if columns.contains(&"rrule") {
+        "rrule,start_of_rule,start_time,finsh_time,stage"
+    } else {
+        "date,start_time,finsh_time,stage"
+    };
+
+    let mut sheddings = CsvSource.read(path)?;
+    sheddings.sort_by_key(|shedding| {
+        (
+            shedding.recurrence,
+            shedding.start_time,
+            shedding.finsh_time(),
+            shedding.duration,
+            shedding.stage,
+        )
+    });
+    sheddings.dedup();
+
+    let mut csv = format!("{header}\n");
+    for shedding in &sheddings {
+        csv.push_str(&canonical_row(shedding));
+        csv.push('\n');
+    }
+    Ok(csv)
+}
+
+/// Rewrite every CSV in `paths` in its canonical form. If `check` is set, nothing is written and
+/// it's an error if any of them aren't already canonical.
+pub fn fmt_schedules(paths: &[PathBuf], check: bool) -> Result<(), BoxedError> {
+    let mut not_canonical = vec![];
+    for path in paths
+        .iter()
+        .filter(|path| path.extension().is_some_and(|ext| ext == "csv"))
+    {
+        let canonical = canonical_csv(path)?;
+        if read_to_string(path)? == canonical {
+            continue;
+        }
+        if check {
+            println!("{path:?} isn't canonical");
+        } else {
+            info!("Rewriting {path:?}");
+            write_atomically(path, |file| Ok(file.write_all(canonical.as_bytes())?))?;
+        }
+        not_canonical.push(path);
+    }
+    match (check, not_canonical.len()) {
+        (true, 0) => Ok(()),
+        (true, n) => Err(format!(
+            "{n} schedules aren't canonical, run `cargo run -- fmt-schedules` to fix them"
+        )
+        .into()),
+        (false, n) => {
+            println!("Rewrote {n} of {} schedules", paths.len());
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::normalise::{canonical_csv, fmt_schedules};
+    use std::fs::{create_dir_all, read_to_string, remove_dir_all, write};
+
+    #[test]
+    fn pads_are_removed_and_rows_sorted() {
+        let dir = std::env::temp_dir().join("eskom-calendar-test-normalise-padded");
+        create_dir_all(&dir).unwrap();
+        let path = dir.join("nelson-mandela-bay-group-1.csv");
+        write(
+            &path,
+            "day_of_cycle ,period_of_cycle ,start_of_cycle ,start_time ,finsh_time ,stage\n\
+             2 ,             19 ,2023-09-15     ,00:00      ,02:30      ,    1\n\
+             1 ,             19 ,2023-09-15     ,00:00      ,02:30      ,    2\n\
+             1 ,             19 ,2023-09-15     ,00:00      ,02:30      ,    1\n",
+        )
+        .unwrap();
+        let canonical = canonical_csv(&path).unwrap();
+        remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            canonical,
+            "day_of_cycle,period_of_cycle,start_of_cycle,start_time,finsh_time,stage\n\
+             1,19,2023-09-15,00:00,02:30,1\n\
+             1,19,2023-09-15,00:00,02:30,2\n\
+             2,19,2023-09-15,00:00,02:30,1\n"
+        );
+    }
+
+    #[test]
+    fn duplicates_are_removed() {
+        let dir = std::env::temp_dir().join("eskom-calendar-test-normalise-duplicates");
+        create_dir_all(&dir).unwrap();
+        let path = dir.join("area.csv");
+        write(
+            &path,
+            "date_of_month,start_time,finsh_time,stage\n1,22:00,00:30,2\n1,02:00,4:30,1\n1,22:00,00:30,2\n",
+        )
+        .unwrap();
+        let canonical = canonical_csv(&path).unwrap();
+        remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            canonical,
+            "date_of_month,start_time,finsh_time,stage\n1,02:00,04:30,1\n1,22:00,00:30,2\n"
+        );
+    }
+
+    #[test]
+    fn dated_rows_are_sorted_by_date() {
+        let dir = std::env::temp_dir().join("eskom-calendar-test-normalise-dated");
+        create_dir_all(&dir).unwrap();
+        let path = dir.join("nelson-mandela-bay-block-1.csv");
+        write(
+            &path,
+            "date, start_time, finsh_time, stage\n2023-10-01, 06:00, 08:30, 1\n2023-09-30, 22:00, 00:30, 1\n",
+        )
+        .unwrap();
+        let canonical = canonical_csv(&path).unwrap();
+        remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            canonical,
+            "date,start_time,finsh_time,stage\n2023-09-30,22:00,00:30,1\n2023-10-01,06:00,08:30,1\n"
+        );
+    }
+
+    #[test]
+    fn rules_are_quoted() {
+        let dir = std::env::temp_dir().join("eskom-calendar-test-normalise-rules");
+        create_dir_all(&dir).unwrap();
+        let path = dir.join("area.csv");
+        write(
+            &path,
+            "rrule,start_of_rule,start_time,finsh_time,stage\n\
+             \"BYDAY=TU,MO;FREQ=WEEKLY\",2023-09-01,06:00,8:30,1\n",
+        )
+        .unwrap();
+        let canonical = canonical_csv(&path).unwrap();
+        remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            canonical,
+            "rrule,start_of_rule,start_time,finsh_time,stage\n\
+             \"FREQ=WEEKLY;BYDAY=MO,TU\",2023-09-01,06:00,08:30,1\n"
+        );
+    }
+
+    #[test]
+    fn check_fails_without_writing() {
+        let dir = std::env::temp_dir().join("eskom-calendar-test-normalise-check");
+        create_dir_all(&dir).unwrap();
+        let canonical = dir.join("canonical.csv");
+        let messy = dir.join("messy.csv");
+        let tight = "day_of_week,start_time,finsh_time,stage\n1,06:00,08:30,1\n";
+        write(&canonical, tight).unwrap();
+        write(
+            &messy,
+            "day_of_week, start_time, finsh_time, stage\n1, 06:00, 08:30, 1\n",
+        )
+        .unwrap();
+        let paths = vec![canonical, messy.clone()];
+
+        let checked = fmt_schedules(&paths, true);
+        let unchanged = read_to_string(&messy).unwrap();
+        fmt_schedules(&paths, false).unwrap();
+        let rewritten = read_to_string(&messy).unwrap();
+        let rechecked = fmt_schedules(&paths, true);
+        remove_dir_all(&dir).unwrap();
+
+        assert!(checked.is_err());
+        assert_ne!(unchanged, tight);
+        assert_eq!(rewritten, tight);
+        assert!(rechecked.is_ok());
+    }
+}
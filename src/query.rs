@@ -0,0 +1,429 @@
+//! Answers questions about a single area (is the power off now? when is the next outage?) by
+//! calculating only that area's outages, instead of writing out every calendar.
+use crate::config::Config;
+use crate::structs::{
+    AreaMetadata, ManuallyInputSchedule, PowerOutage, QueryCommand, RecurringShedding,
+};
+use crate::{calculate_power_outages, fmt, geo, search, source, BoxedError};
+use chrono::{DateTime, FixedOffset, Utc};
+use log::info;
+use std::fmt::Display;
+use std::path::PathBuf;
+
+/// The current time in South African Standard Time.
+pub fn now_sast() -> DateTime<FixedOffset> {
+    Utc::now().with_timezone(&FixedOffset::east_opt(2 * 60 * 60).unwrap())
+}
+
+/// Run a `query status`, `query next`, `query search` or `query locate` command, printing the results to stdout.
+pub fn run(
+    command: &QueryCommand,
+    config: &Config,
+    paths: &[PathBuf],
+    metadata: &AreaMetadata,
+    manually_specified: &ManuallyInputSchedule,
+) -> Result<(), BoxedError> {
+    let now = now_sast();
+    let area_names = match command {
+        QueryCommand::Status { area } | QueryCommand::Next { area, .. } => {
+            resolve_area_names(area, paths, metadata)?
+        }
+        QueryCommand::Search { query, limit } => {
+            search::print_search(query, metadata, *limit);
+            return Ok(());
+        }
+        QueryCommand::Locate {
+            latitude,
+            longitude,
+        } => {
+            let boundaries = geo::read_boundaries(&config.boundaries_dir)?;
+            let area_names = geo::locate(&boundaries, *latitude, *longitude)
+                .into_iter()
+                .map(|name| name.to_owned())
+                .collect::<Vec<_>>();
+            if area_names.is_empty() {
+                return Err(Box::from(format!(
+                    "No area boundary contains ({latitude}, {longitude})"
+                )));
+            }
+            area_names
+        }
+    };
+    for area_name in area_names {
+        let path = paths
+            .iter()
+            .find(|path| fmt::path_to_area_name(path).is_ok_and(|name| name == area_name))
+            .ok_or_else(|| format!("No schedule found for {area_name}"))?;
+        let sheddings = source::read_schedule(path)?.sheddings;
+        let outages = upcoming_outages(&area_name, sheddings, manually_specified, now)?;
+        match command {
+            QueryCommand::Status { .. } | QueryCommand::Locate { .. } => println!(
+                "{}",
+                area_status(&area_name, &outages, manually_specified, now)
+            ),
+            QueryCommand::Search { .. } => unreachable!("Searches are handled above"),
+            QueryCommand::Next { count, .. } => {
+                println!("{}:", fmt::prettify_area_name(&area_name));
+                if outages.is_empty() {
+                    println!("  No upcoming loadshedding");
+                }
+                for outage in outages.iter().take(*count) {
+                    println!("  {}", outage_summary(outage));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Find the areas which the user might be asking about. `query` can either be the name of an area
+/// (ie the name of a CSV in `generated/`) or the name of a suburb listed in `area_metadata.yaml`,
+/// in which case every area containing that suburb is returned.
+pub fn resolve_area_names(
+    query: &str,
+    paths: &[PathBuf],
+    metadata: &AreaMetadata,
+) -> Result<Vec<String>, BoxedError> {
+    let area_names = paths
+        .iter()
+        .map(|path| fmt::path_to_area_name(path))
+        .collect::<Result<Vec<_>, _>>()?;
+    let query = normalise_name(query);
+    if let Some(area_name) = area_names.iter().find(|name| **name == query) {
+        return Ok(vec![area_name.clone()]);
+    }
+
+    let mut matches = metadata
+        .area_details
+        .iter()
+        .filter(|detail| {
+            detail
+                .suburbs()
+                .any(|suburb| normalise_name(suburb) == query)
+        })
+        .map(|detail| detail.area_name().to_owned())
+        .filter(|area_name| area_names.contains(area_name))
+        .collect::<Vec<_>>();
+    matches.sort();
+    matches.dedup();
+    if matches.is_empty() {
+        return Err(Box::from(format!(
+            "Couldn't find an area or suburb called {query:?}"
+        )));
+    }
+    info!("Suburb {query:?} is in {matches:?}");
+    Ok(matches)
+}
+
+/// Lowercase the name and use dashes instead of spaces, so that `Parow East` matches `parow-east`.
+fn normalise_name(name: &str) -> String {
+    name.trim()
+        .to_lowercase()
+        .split(|c: char| c.is_whitespace() || c == '-' || c == '_')
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Calculate the outages for an area which haven't finished yet, sorted by their start time. Only
+/// the changes which haven't finished yet are used to calculate the outages.
+pub fn upcoming_outages(
+    area_name: &str,
+    sheddings: Vec<RecurringShedding>,
+    manually_specified: &ManuallyInputSchedule,
+    now: DateTime<FixedOffset>,
+) -> Result<Vec<PowerOutage>, BoxedError> {
+    let window = ManuallyInputSchedule {
+        changes: manually_specified
+            .changes
+            .iter()
+            .filter(|c| c.finsh > now)
+            .cloned()
+            .collect(),
+        historical_changes: vec![],
+        stage_rules: manually_specified.stage_rules.clone(),
+        exemptions: manually_specified.exemptions.clone(),
+        projected_until: manually_specified.projected_until,
+    };
+    let (mut outages, _last_finsh) = calculate_power_outages(area_name, sheddings, &window)?;
+    outages.retain(|outage| outage.finsh > now);
+    outages.sort_by_key(|outage| outage.start);
+    Ok(outages)
+}
+
+/// The loadshedding status of an area at a particular point in time.
+#[derive(Debug, PartialEq)]
+pub struct AreaStatus {
+    pub area_name: String,
+    /// The stage of loadshedding applied to this area, or 0 if there is no loadshedding
+    pub stage: u8,
+    /// The outage which is happening right now, if the power is off
+    pub current_outage: Option<PowerOutage>,
+    /// When the power will come back on, taking back-to-back outages into account
+    pub power_returns: Option<DateTime<FixedOffset>>,
+    /// The next outage after the power returns
+    pub next_outage: Option<PowerOutage>,
+}
+
+/// Calculate the status of an area at time `now`, given the upcoming outages of that area (sorted
+/// by start time). The stage is the one that area's outages were calculated with, so it includes
+/// any stage rules, exemptions and tentative projections.
+pub fn area_status(
+    area_name: &str,
+    outages: &[PowerOutage],
+    manually_specified: &ManuallyInputSchedule,
+    now: DateTime<FixedOffset>,
+) -> AreaStatus {
+    let exempt = manually_specified
+        .exemptions_for_area(area_name)
+        .iter()
+        .any(|e| e.start <= now && now < e.finsh);
+    let stage = if exempt {
+        0
+    } else {
+        manually_specified
+            .changes_for_area(area_name)
+            .iter()
+            .find(|c| c.start <= now && now < c.finsh)
+            .map_or(0, |c| c.stage)
+    };
+    let current_outage = outages
+        .iter()
+        .find(|outage| outage.start <= now && now < outage.finsh)
+        .cloned();
+
+    // Outages are often back-to-back (ie when the stage changes), so keep extending the time when
+    // the power returns until there's a gap
+    let power_returns = current_outage.as_ref().map(|current| {
+        outages
+            .iter()
+            .filter(|outage| outage.start >= current.start)
+            .fold(current.finsh, |returns, outage| {
+                if outage.start <= returns {
+                    returns.max(outage.finsh)
+                } else {
+                    returns
+                }
+            })
+    });
+    let next_outage = outages
+        .iter()
+        .find(|outage| outage.start > power_returns.unwrap_or(now))
+        .cloned();
+
+    AreaStatus {
+        area_name: area_name.to_owned(),
+        stage,
+        current_outage,
+        power_returns,
+        next_outage,
+    }
+}
+
+impl Display for AreaStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{}: {}",
+            fmt::prettify_area_name(&self.area_name),
+            if self.stage == 0 {
+                "No loadshedding".to_owned()
+            } else {
+                format!("Stage {}", self.stage)
+            }
+        )?;
+        match (&self.current_outage, self.power_returns) {
+            (Some(current), Some(returns)) => write!(
+                f,
+                "  Power is off (stage {}), and returns {}",
+                current.stage,
+                returns.format("on %A at %H:%M")
+            )?,
+            _ => write!(f, "  Power is on")?,
+        }
+        if let Some(next) = &self.next_outage {
+            write!(f, "\n  Next outage: {}", outage_summary(next))?;
+        }
+        Ok(())
+    }
+}
+
+/// A one-line human readable summary of an outage.
+fn outage_summary(outage: &PowerOutage) -> String {
+    format!(
+        "{} to {} (stage {}{})",
+        outage.start.format("%A %d %h from %H:%M"),
+        outage.finsh.format("%H:%M"),
+        outage.stage,
+        if outage.tentative { ", tentative" } else { "" },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, FixedOffset};
+
+    fn rfc3339(s: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(s).unwrap()
+    }
+
+    mod resolve_area_names {
+        use crate::query::resolve_area_names;
+        use crate::structs::AreaMetadata;
+        use std::path::PathBuf;
+
+        fn paths() -> Vec<PathBuf> {
+            vec![
+                PathBuf::from("generated/city-of-cape-town-area-7.csv"),
+                PathBuf::from("generated/western-cape-stellenbosch.csv"),
+                PathBuf::from("generated/eskom-direct-1-even.csv"),
+            ]
+        }
+
+        fn metadata() -> AreaMetadata {
+            serde_yaml::from_str(
+                r#"
+area_details:
+- calendar_name: western-cape-stellenbosch.ics
+  areas: [{name: stellenbosch}, {name: parow east}]
+- calendar_name: eskom-direct-1-even.ics
+  areas:
+  - province: gauteng
+    name: ["parow-east", "boipatong"]
+- calendar_name: no-schedule.ics
+  areas: [{name: boipatong}]
+"#,
+            )
+            .unwrap()
+        }
+
+        #[test]
+        fn by_area_name() {
+            assert_eq!(
+                resolve_area_names("City-Of-Cape-Town-Area-7", &paths(), &metadata()).unwrap(),
+                vec!["city-of-cape-town-area-7"]
+            );
+        }
+
+        #[test]
+        fn by_suburb_name() {
+            assert_eq!(
+                resolve_area_names("Parow East", &paths(), &metadata()).unwrap(),
+                vec!["eskom-direct-1-even", "western-cape-stellenbosch"]
+            );
+        }
+
+        #[test]
+        fn ignores_areas_without_schedules() {
+            assert_eq!(
+                resolve_area_names("boipatong", &paths(), &metadata()).unwrap(),
+                vec!["eskom-direct-1-even"]
+            );
+        }
+
+        #[test]
+        fn err_if_unknown() {
+            assert!(resolve_area_names("atlantis", &paths(), &metadata()).is_err());
+        }
+    }
+
+    mod area_status {
+        use crate::query::{area_status, tests::rfc3339};
+        use crate::structs::{ManuallyInputSchedule, PowerOutage, RawManuallyInputSchedule};
+
+        fn outage(stage: u8, start: &str, finsh: &str) -> PowerOutage {
+            PowerOutage {
+                area_name: "test-area".to_owned(),
+                stage,
+                start: rfc3339(start),
+                finsh: rfc3339(finsh),
+                source: "test source".to_owned(),
+                cancelled: false,
+                tentative: false,
+            }
+        }
+
+        fn schedule(extra: &str) -> ManuallyInputSchedule {
+            serde_yaml::from_str::<RawManuallyInputSchedule>(&format!(
+                "
+changes:
+- stage: 4
+  start: 2023-05-29T08:00:00
+  finsh: 2023-05-30T00:00:00
+  source: test source
+historical_changes: []
+{extra}"
+            ))
+            .unwrap()
+            .try_into()
+            .unwrap()
+        }
+
+        #[test]
+        fn power_off_with_back_to_back_outages() {
+            let outages = vec![
+                outage(2, "2023-05-29T10:00:00+02:00", "2023-05-29T12:00:00+02:00"),
+                outage(4, "2023-05-29T12:00:00+02:00", "2023-05-29T12:30:00+02:00"),
+                outage(4, "2023-05-29T18:00:00+02:00", "2023-05-29T20:30:00+02:00"),
+            ];
+            let status = area_status(
+                "test-area",
+                &outages,
+                &schedule(""),
+                rfc3339("2023-05-29T11:00:00+02:00"),
+            );
+            assert_eq!(status.stage, 4);
+            assert_eq!(status.current_outage, Some(outages[0].clone()));
+            assert_eq!(
+                status.power_returns,
+                Some(rfc3339("2023-05-29T12:30:00+02:00"))
+            );
+            assert_eq!(status.next_outage, Some(outages[2].clone()));
+        }
+
+        #[test]
+        fn power_on() {
+            let outages = vec![outage(
+                4,
+                "2023-05-29T18:00:00+02:00",
+                "2023-05-29T20:30:00+02:00",
+            )];
+            let status = area_status(
+                "test-area",
+                &outages,
+                &schedule(""),
+                rfc3339("2023-05-30T11:00:00+02:00"),
+            );
+            assert_eq!(status.stage, 0);
+            assert_eq!(status.current_outage, None);
+            assert_eq!(status.power_returns, None);
+            assert_eq!(status.next_outage, None);
+        }
+
+        #[test]
+        fn stage_includes_rules_and_exemptions() {
+            let now = rfc3339("2023-05-29T11:00:00+02:00");
+            let lower = schedule(
+                "
+stage_rules:
+- include_regex: test-area
+  offset: -1
+",
+            );
+            assert_eq!(area_status("test-area", &[], &lower, now).stage, 3);
+            assert_eq!(area_status("other-area", &[], &lower, now).stage, 4);
+
+            let exempt = schedule(
+                "
+exemptions:
+- start: 2023-05-29T00:00:00
+  finsh: 2023-05-30T00:00:00
+  source: test source
+  include_regex: test-area
+",
+            );
+            assert_eq!(area_status("test-area", &[], &exempt, now).stage, 0);
+            assert_eq!(area_status("other-area", &[], &exempt, now).stage, 4);
+        }
+    }
+}
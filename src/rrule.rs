@@ -0,0 +1,273 @@
+//! A subset of the recurrence rules (RRULEs) from RFC 5545, for local schedules which don't fit a
+//! weekly, monthly or fixed-length cycle, like "every second Tuesday" or "weekdays only". Rules
+//! are written the same way as in an iCalendar file, like `FREQ=WEEKLY;INTERVAL=2;BYDAY=TU`, and
+//! count their intervals from a start date (which is `DTSTART` in RFC 5545).
+//!
+//! The supported parts are `FREQ` (one of `DAILY`, `WEEKLY` or `MONTHLY`), `INTERVAL`, `BYDAY`
+//! (without ordinals like `1MO`), `BYMONTHDAY` (without negative days), `UNTIL` and `WKST=MO`.
+use crate::BoxedError;
+use chrono::{Datelike, Days, NaiveDate, Weekday};
+use std::fmt::Display;
+use std::num::NonZeroU16;
+
+/// The days of the week in the order that they're numbered in a `BYDAY` bitmask.
+const WEEKDAYS: [(Weekday, &str); 7] = [
+    (Weekday::Mon, "MO"),
+    (Weekday::Tue, "TU"),
+    (Weekday::Wed, "WE"),
+    (Weekday::Thu, "TH"),
+    (Weekday::Fri, "FR"),
+    (Weekday::Sat, "SA"),
+    (Weekday::Sun, "SU"),
+];
+
+/// How often a rule repeats, before `INTERVAL` is taken into account.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+/// A parsed recurrence rule. The fields are private so that every `RRule` has been checked by
+/// `RRule::new`, and so can always be evaluated.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct RRule {
+    /// The first date that the rule can occur on, which intervals are counted from
+    start: NaiveDate,
+    freq: Frequency,
+    interval: NonZeroU16,
+    /// The days of the week the rule is limited to, with Monday as bit 0. Zero means any day.
+    by_day: u8,
+    /// The dates of the month the rule is limited to, with the 1st as bit 1. Zero means any date.
+    by_month_day: u32,
+    /// The last date that the rule can occur on, if it ever stops
+    until: Option<NaiveDate>,
+}
+
+/// The bit of `weekday` in a `BYDAY` bitmask.
+fn weekday_bit(weekday: Weekday) -> u8 {
+    1 << weekday.num_days_from_monday()
+}
+
+/// The Monday on or before `date`.
+fn week_start(date: NaiveDate) -> NaiveDate {
+    date - Days::new(date.weekday().num_days_from_monday().into())
+}
+
+impl RRule {
+    /// Parse `rule` (like `FREQ=DAILY;INTERVAL=19`), counting its intervals from `start`. A
+    /// leading `RRULE:` is allowed, so rules can be copied straight out of an iCalendar file.
+    pub fn new(rule: &str, start: NaiveDate) -> Result<RRule, BoxedError> {
+        let mut freq = None;
+        let mut interval = NonZeroU16::MIN;
+        let mut by_day = 0;
+        let mut by_month_day = 0;
+        let mut until = None;
+
+        let rule = rule.trim();
+        for part in rule.strip_prefix("RRULE:").unwrap_or(rule).split(';') {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("{part:?} in RRULE {rule:?} isn't like KEY=VALUE"))?;
+            match key {
+                "FREQ" => {
+                    freq = Some(match value {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        _ => return Err(format!("Unsupported FREQ {value:?} in {rule:?}").into()),
+                    })
+                }
+                "INTERVAL" => interval = value.parse()?,
+                "BYDAY" => {
+                    for day in value.split(',') {
+                        let (weekday, _) = WEEKDAYS
+                            .iter()
+                            .find(|(_, name)| *name == day)
+                            .ok_or_else(|| format!("Unsupported BYDAY {day:?} in {rule:?}"))?;
+                        by_day |= weekday_bit(*weekday);
+                    }
+                }
+                "BYMONTHDAY" => {
+                    for date in value.split(',') {
+                        match date.parse::<u8>() {
+                            Ok(date @ 1..=31) => by_month_day |= 1 << date,
+                            _ => {
+                                return Err(
+                                    format!("Unsupported BYMONTHDAY {date:?} in {rule:?}").into()
+                                )
+                            }
+                        }
+                    }
+                }
+                "UNTIL" => {
+                    // UNTIL can be a date or a datetime, but only the date matters here
+                    let date = value.get(..8).unwrap_or(value);
+                    until = Some(NaiveDate::parse_from_str(date, "%Y%m%d")?);
+                }
+                "WKST" if value == "MO" => {}
+                _ => return Err(format!("Unsupported {part:?} in RRULE {rule:?}").into()),
+            }
+        }
+
+        let freq = freq.ok_or_else(|| format!("RRULE {rule:?} doesn't have a FREQ"))?;
+        if freq == Frequency::Weekly && by_month_day != 0 {
+            return Err(format!("RRULE {rule:?} can't have BYMONTHDAY with FREQ=WEEKLY").into());
+        }
+        Ok(RRule {
+            start,
+            freq,
+            interval,
+            by_day,
+            by_month_day,
+            until,
+        })
+    }
+
+    /// The first date that the rule can occur on.
+    pub fn start(&self) -> NaiveDate {
+        self.start
+    }
+
+    /// Whether the rule occurs on `date`.
+    pub fn occurs_on(&self, date: NaiveDate) -> bool {
+        if date < self.start || self.until.is_some_and(|until| date > until) {
+            return false;
+        }
+        let interval = i64::from(self.interval.get());
+        let in_interval = match self.freq {
+            Frequency::Daily => (date - self.start).num_days() % interval == 0,
+            Frequency::Weekly => {
+                (week_start(date) - week_start(self.start)).num_days() / 7 % interval == 0
+            }
+            Frequency::Monthly => {
+                let months = |d: NaiveDate| i64::from(d.year()) * 12 + i64::from(d.month0());
+                (months(date) - months(self.start)) % interval == 0
+            }
+        };
+        // Like RFC 5545, weekly and monthly rules without any BY* parts take their day from the
+        // start date
+        let (by_day, by_month_day) = match (self.freq, self.by_day, self.by_month_day) {
+            (Frequency::Weekly, 0, _) => (weekday_bit(self.start.weekday()), 0),
+            (Frequency::Monthly, 0, 0) => (0, 1 << self.start.day()),
+            (_, by_day, by_month_day) => (by_day, by_month_day),
+        };
+        in_interval
+            && (by_day == 0 || by_day & weekday_bit(date.weekday()) != 0)
+            && (by_month_day == 0 || by_month_day & (1 << date.day()) != 0)
+    }
+}
+
+impl Display for RRule {
+    /// Formats the rule in a canonical form, without the start date.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let freq = match self.freq {
+            Frequency::Daily => "DAILY",
+            Frequency::Weekly => "WEEKLY",
+            Frequency::Monthly => "MONTHLY",
+        };
+        write!(f, "FREQ={freq}")?;
+        if self.interval.get() > 1 {
+            write!(f, ";INTERVAL={}", self.interval)?;
+        }
+        if self.by_day != 0 {
+            let days = WEEKDAYS
+                .iter()
+                .filter(|(weekday, _)| self.by_day & weekday_bit(*weekday) != 0)
+                .map(|(_, name)| *name)
+                .collect::<Vec<_>>();
+            write!(f, ";BYDAY={}", days.join(","))?;
+        }
+        if self.by_month_day != 0 {
+            let dates = (1..=31)
+                .filter(|date| self.by_month_day & (1 << date) != 0)
+                .map(|date| date.to_string())
+                .collect::<Vec<_>>();
+            write!(f, ";BYMONTHDAY={}", dates.join(","))?;
+        }
+        if let Some(until) = self.until {
+            write!(f, ";UNTIL={}", until.format("%Y%m%d"))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rrule::RRule;
+    use chrono::NaiveDate;
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    /// The dates in September 2023 that `rule` occurs on, when it starts on `start`.
+    fn september(rule: &str, start: &str) -> Vec<u32> {
+        let rule = RRule::new(rule, date(start)).unwrap();
+        (1..=30)
+            .filter(|day| rule.occurs_on(NaiveDate::from_ymd_opt(2023, 9, *day).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn weekdays_fortnights_and_cycles() {
+        // 2023-09-01 is a Friday
+        assert_eq!(
+            september("FREQ=DAILY;BYDAY=MO,TU,WE,TH,FR", "2023-09-01"),
+            vec![1, 4, 5, 6, 7, 8, 11, 12, 13, 14, 15, 18, 19, 20, 21, 22, 25, 26, 27, 28, 29]
+        );
+        assert_eq!(
+            september("RRULE:FREQ=WEEKLY;INTERVAL=2;BYDAY=TU", "2023-09-01"),
+            vec![12, 26]
+        );
+        assert_eq!(
+            september("FREQ=WEEKLY", "2023-09-01"),
+            vec![1, 8, 15, 22, 29]
+        );
+        assert_eq!(
+            september("FREQ=DAILY;INTERVAL=19", "2023-08-20"),
+            vec![8, 27]
+        );
+        assert_eq!(
+            september("FREQ=MONTHLY;BYMONTHDAY=5,31", "2023-01-01"),
+            vec![5]
+        );
+        assert_eq!(
+            september("FREQ=DAILY;UNTIL=20230903", "2023-08-01"),
+            vec![1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn never_before_start() {
+        assert_eq!(september("FREQ=DAILY", "2023-09-28"), vec![28, 29, 30]);
+    }
+
+    #[test]
+    fn unsupported_rules_are_errors() {
+        for rule in [
+            "INTERVAL=2",
+            "FREQ=YEARLY",
+            "FREQ=MONTHLY;BYDAY=1MO",
+            "FREQ=WEEKLY;BYMONTHDAY=1",
+            "FREQ=DAILY;INTERVAL=0",
+            "FREQ=DAILY;COUNT=3",
+        ] {
+            assert!(RRule::new(rule, date("2023-09-01")).is_err(), "{rule}");
+        }
+    }
+
+    #[test]
+    fn displayed_canonically() {
+        let rule = RRule::new(
+            "FREQ=WEEKLY;BYDAY=FR,MO;INTERVAL=2;UNTIL=20231231T235959Z",
+            date("2023-09-01"),
+        )
+        .unwrap();
+        assert_eq!(
+            rule.to_string(),
+            "FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,FR;UNTIL=20231231"
+        );
+    }
+}
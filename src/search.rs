@@ -0,0 +1,316 @@
+//! Fuzzy search over the suburbs, municipalities and provinces in `area_metadata.yaml`, so that
+//! people can find their calendar without knowing which loadshedding block they're in.
+use crate::manifest::write_atomically;
+use crate::structs::{AreaDetail, AreaMetadata};
+use crate::{fmt, BoxedError};
+use log::info;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use unicode_normalization::UnicodeNormalization;
+
+/// Any fuzzy match scoring less than this is discarded.
+const MIN_SIMILARITY: f64 = 0.85;
+
+/// Which part of the metadata a search result matched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchedField {
+    AreaName,
+    Suburb,
+    Municipality,
+    Province,
+}
+
+impl MatchedField {
+    /// Suburbs are more specific than municipalities, which are more specific than provinces, so
+    /// they're ranked higher for equally good matches.
+    fn weight(&self) -> f64 {
+        match self {
+            MatchedField::AreaName | MatchedField::Suburb => 1.0,
+            MatchedField::Municipality => 0.9,
+            MatchedField::Province => 0.8,
+        }
+    }
+}
+
+/// A calendar which might be what the user is searching for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchResult {
+    /// The area name, which lines up with the `generated/` CSV file names
+    pub area_name: String,
+    /// How well the query matched, from 0 (not at all) to 1 (exactly)
+    pub score: f64,
+    /// The name which matched the query
+    pub matched: String,
+    /// Which field of the metadata `matched` came from
+    pub field: MatchedField,
+}
+
+/// Fold a name to lowercase ASCII letters and digits only, so that `Wag-'n-Bietjie`, `wag n
+/// bietjie` and `wagnbietjie` all compare equal. Accents are removed (`ö` becomes `o`) and
+/// everything that isn't a letter or digit (including mis-encoded characters) is dropped.
+pub fn search_key(name: &str) -> String {
+    name.nfkd()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Score how well `query` matches `candidate`, where both have already been passed through
+/// `search_key`. Exact matches score 1, prefixes 0.95, substrings 0.9, and anything else is scored
+/// by its Jaro-Winkler similarity.
+fn similarity(query: &str, candidate: &str) -> f64 {
+    if query.is_empty() || candidate.is_empty() {
+        0.0
+    } else if query == candidate {
+        1.0
+    } else if candidate.starts_with(query) {
+        0.95
+    } else if candidate.contains(query) {
+        0.9
+    } else {
+        strsim::jaro_winkler(query, candidate)
+    }
+}
+
+/// Every (name, field) pair which can be used to find a calendar.
+fn searchable_names(detail: &AreaDetail) -> impl Iterator<Item = (&str, MatchedField)> {
+    std::iter::once((detail.area_name(), MatchedField::AreaName))
+        .chain(detail.suburbs().map(|s| (s.as_str(), MatchedField::Suburb)))
+        .chain(
+            detail
+                .municipality
+                .iter()
+                .chain(detail.areas.iter().filter_map(|g| g.municipality.as_ref()))
+                .map(|m| (m.as_str(), MatchedField::Municipality)),
+        )
+        .chain(
+            detail
+                .province
+                .iter()
+                .chain(detail.areas.iter().filter_map(|g| g.province.as_ref()))
+                .map(|p| (p.as_str(), MatchedField::Province)),
+        )
+}
+
+/// Search the metadata for calendars matching `query`, returning at most `limit` results with the
+/// best matches first. Each calendar appears at most once, with its best matching name.
+pub fn search(query: &str, metadata: &AreaMetadata, limit: usize) -> Vec<SearchResult> {
+    let query_key = search_key(query);
+    let mut results = metadata
+        .area_details
+        .iter()
+        .filter_map(|detail| {
+            searchable_names(detail)
+                .filter_map(|(name, field)| {
+                    let similarity = similarity(&query_key, &search_key(name));
+                    (similarity >= MIN_SIMILARITY).then(|| SearchResult {
+                        area_name: detail.area_name().to_owned(),
+                        score: similarity * field.weight(),
+                        matched: name.to_owned(),
+                        field,
+                    })
+                })
+                .max_by(|a, b| a.score.total_cmp(&b.score))
+        })
+        .collect::<Vec<_>>();
+    results.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| a.area_name.cmp(&b.area_name))
+    });
+    results.truncate(limit);
+    results
+}
+
+/// Print the results of a search to stdout.
+pub fn print_search(query: &str, metadata: &AreaMetadata, limit: usize) {
+    let results = search(query, metadata, limit);
+    if results.is_empty() {
+        println!("No calendars found for {query:?}");
+    }
+    for result in results {
+        println!(
+            "{:>5.1}%  {} ({} {:?})",
+            result.score * 100.0,
+            result.area_name,
+            match result.field {
+                MatchedField::AreaName => "area",
+                MatchedField::Suburb => "suburb",
+                MatchedField::Municipality => "municipality",
+                MatchedField::Province => "province",
+            },
+            result.matched,
+        );
+    }
+}
+
+/// A precomputed search index, so the website can look up calendars without parsing
+/// `area_metadata.yaml`.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct SearchIndex {
+    /// Every calendar which can be searched for
+    pub areas: Vec<SearchIndexArea>,
+    /// Maps every search key (see `search_key`) to the indices in `areas` of the calendars it
+    /// belongs to.
+    pub terms: BTreeMap<String, Vec<usize>>,
+}
+
+/// A calendar in the search index.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct SearchIndexArea {
+    pub calendar_name: String,
+    pub pretty_name: String,
+    pub provider: Option<String>,
+    pub provinces: Vec<String>,
+    pub municipalities: Vec<String>,
+}
+
+impl SearchIndex {
+    pub fn new(metadata: &AreaMetadata) -> Self {
+        let mut terms: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        let areas = metadata
+            .area_details
+            .iter()
+            .enumerate()
+            .map(|(i, detail)| {
+                for (name, _field) in searchable_names(detail) {
+                    let key = search_key(name);
+                    if key.is_empty() {
+                        continue;
+                    }
+                    let indices = terms.entry(key).or_default();
+                    if indices.last() != Some(&i) {
+                        indices.push(i);
+                    }
+                }
+                SearchIndexArea {
+                    calendar_name: detail.calendar_name.clone(),
+                    pretty_name: fmt::prettify_area_name(detail.area_name()),
+                    provider: detail.provider.clone(),
+                    provinces: detail.provinces(),
+                    municipalities: detail.municipalities(),
+                }
+            })
+            .collect();
+        SearchIndex { areas, terms }
+    }
+}
+
+/// Write the search index for the website to `path` as JSON.
+pub fn write_search_index(metadata: &AreaMetadata, path: &Path) -> Result<(), BoxedError> {
+    let index = SearchIndex::new(metadata);
+    info!(
+        "Writing search index with {} areas and {} terms to {path:?}",
+        index.areas.len(),
+        index.terms.len()
+    );
+    write_atomically(path, |file| {
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, &index)?;
+        Ok(writer.flush()?)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::search::{search, search_key, MatchedField, SearchIndex};
+    use crate::structs::AreaMetadata;
+
+    fn metadata() -> AreaMetadata {
+        serde_yaml::from_str(
+            r#"
+area_details:
+- calendar_name: western-cape-stellenbosch.ics
+  province: western-cape
+  municipality: stellenbosch
+  provider: eskom
+  areas: [{name: stellenbosch}, {name: "Parow East"}]
+- calendar_name: eskom-direct-1-even.ics
+  provider: eskom
+  areas:
+  - province: free-state
+    municipality: "letsemeng"
+    name: ["wag-'n-bietjie", "wilhelmsh÷he", "parow-east"]
+- calendar_name: city-of-cape-town-area-1.ics
+  province: western-cape
+  municipality: city-of-cape-town-metropolitan
+  provider: city-of-cape-town
+  areas: [{name: "bellville"}]
+"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn search_key_ignores_accents_and_punctuation() {
+        assert_eq!(search_key("Wag-'n-Bietjie"), "wagnbietjie");
+        assert_eq!(search_key("wag n bietjie"), "wagnbietjie");
+        assert_eq!(search_key("Wilhelmshöhe"), "wilhelmshohe");
+        assert_eq!(search_key("wilhelmsh÷he"), "wilhelmshhe");
+        assert_eq!(search_key("Beachview – SPL"), "beachviewspl");
+    }
+
+    #[test]
+    fn punctuation_insensitive() {
+        let results = search("wag n bietjie", &metadata(), 10);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].area_name, "eskom-direct-1-even");
+        assert_eq!(results[0].field, MatchedField::Suburb);
+        assert_eq!(results[0].score, 1.0);
+    }
+
+    #[test]
+    fn accent_insensitive_and_fuzzy() {
+        let results = search("Wilhelmshöhe", &metadata(), 10);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].area_name, "eskom-direct-1-even");
+        assert_eq!(results[0].matched, "wilhelmsh÷he");
+    }
+
+    #[test]
+    fn ranks_exact_matches_first() {
+        let results = search("stellenbosch", &metadata(), 10);
+        assert_eq!(results[0].area_name, "western-cape-stellenbosch");
+        assert_eq!(results[0].score, 1.0);
+    }
+
+    #[test]
+    fn ties_are_sorted_by_area_name() {
+        let areas = search("parow east", &metadata(), 10)
+            .into_iter()
+            .map(|r| r.area_name)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            areas,
+            vec!["eskom-direct-1-even", "western-cape-stellenbosch"]
+        );
+    }
+
+    #[test]
+    fn province_matches_rank_below_area_names() {
+        let results = search("western cape", &metadata(), 10);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].area_name, "western-cape-stellenbosch");
+        assert_eq!(results[0].field, MatchedField::AreaName);
+        assert_eq!(results[1].area_name, "city-of-cape-town-area-1");
+        assert_eq!(results[1].field, MatchedField::Province);
+        assert!(results[1].score < results[0].score);
+    }
+
+    #[test]
+    fn limit_is_respected() {
+        assert_eq!(search("western cape", &metadata(), 1).len(), 1);
+    }
+
+    #[test]
+    fn index_maps_terms_to_areas() {
+        let index = SearchIndex::new(&metadata());
+        assert_eq!(index.areas.len(), 3);
+        assert_eq!(index.terms["paroweast"], vec![0, 1]);
+        assert_eq!(index.terms["westerncape"], vec![0, 2]);
+        assert_eq!(index.terms["bellville"], vec![2]);
+        assert_eq!(index.areas[2].pretty_name, "Cape Town 1");
+    }
+}
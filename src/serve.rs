@@ -0,0 +1,123 @@
+//! A tiny HTTP server for the output directory, so that the calendars can be tried out locally
+//! (for example, by subscribing to them from a phone on the same network) before they're published.
+use crate::BoxedError;
+use log::{info, warn};
+use std::fs::read;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+
+/// Serve the files in `dir` on `port` until the program is stopped. Requests are handled one at a
+/// time, which is plenty for local testing.
+pub fn serve(dir: &Path, port: u16) -> Result<(), BoxedError> {
+    let listener = TcpListener::bind(("0.0.0.0", port))?;
+    println!("Serving {dir:?} on http://localhost:{port}/");
+    for stream in listener.incoming() {
+        match stream
+            .map_err(BoxedError::from)
+            .and_then(|s| respond(s, dir))
+        {
+            Ok(()) => {}
+            Err(e) => warn!("Error while handling request: {e}"),
+        }
+    }
+    Ok(())
+}
+
+/// Read one request from `stream` and reply with the requested file from `dir`.
+fn respond(mut stream: TcpStream, dir: &Path) -> Result<(), BoxedError> {
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    info!("{method} {target}");
+
+    let (status, content_type, body) = if method != "GET" {
+        (
+            "405 Method Not Allowed",
+            "text/plain",
+            b"Only GET is supported".to_vec(),
+        )
+    } else {
+        match resolve(dir, target).and_then(|path| read(&path).ok().map(|body| (path, body))) {
+            Some((path, body)) => ("200 OK", content_type(&path), body),
+            None => ("404 Not Found", "text/plain", b"Not found".to_vec()),
+        }
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(&body)?;
+    Ok(())
+}
+
+/// The file in `dir` which `target` (like `/city-of-cape-town-area-1.ics?foo=bar`) refers to.
+/// Targets which try to escape `dir` (like `/../secrets`) don't refer to anything.
+fn resolve(dir: &Path, target: &str) -> Option<PathBuf> {
+    let path = target.split(['?', '#']).next()?.trim_start_matches('/');
+    let relative = Path::new(path);
+    if path.is_empty()
+        || relative
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        return None;
+    }
+    Some(dir.join(relative))
+}
+
+/// The MIME type of the file at `path`, so that calendar apps and browsers know what to do with
+/// it.
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("ics") => "text/calendar; charset=utf-8",
+        Some("csv") => "text/csv; charset=utf-8",
+        Some("json") => "application/json",
+        Some("geojson") => "application/geo+json",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::serve::{content_type, resolve};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn resolves_files_in_dir() {
+        assert_eq!(
+            resolve(Path::new("calendars"), "/western-cape-stellenbosch.ics?v=2"),
+            Some(PathBuf::from("calendars/western-cape-stellenbosch.ics"))
+        );
+        assert_eq!(
+            resolve(
+                Path::new("calendars"),
+                "/parquet/month=2023-05/outages.parquet"
+            ),
+            Some(PathBuf::from(
+                "calendars/parquet/month=2023-05/outages.parquet"
+            ))
+        );
+    }
+
+    #[test]
+    fn doesnt_escape_dir() {
+        assert_eq!(resolve(Path::new("calendars"), "/../Cargo.toml"), None);
+        assert_eq!(resolve(Path::new("calendars"), "/parquet/../../src"), None);
+        assert_eq!(resolve(Path::new("calendars"), "/"), None);
+    }
+
+    #[test]
+    fn calendars_are_served_as_calendars() {
+        assert_eq!(
+            content_type(Path::new("calendars/western-cape-stellenbosch.ics")),
+            "text/calendar; charset=utf-8"
+        );
+        assert_eq!(
+            content_type(Path::new("calendars/outages.parquet")),
+            "application/octet-stream"
+        );
+    }
+}
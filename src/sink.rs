@@ -0,0 +1,479 @@
+//! The outputs which the calculated outages are written to. Each output format is an `OutputSink`,
+//! so a new format only needs a new sink (and a way to select it in `Outputs`), without touching
+//! the pipeline in `generate` which calculates the outages.
+use crate::config::{Config, Outputs};
+use crate::manifest::ManifestEntry;
+use crate::structs::{BuildContext, ManuallyInputSchedule, PowerOutage};
+use crate::timeline::{self, Timeline};
+use crate::BoxedError;
+use crate::{export, geo, overwrite_lines_to_csv, query, read, search, write_sheddings_to_ics};
+use chrono::{DateTime, FixedOffset};
+use std::path::{Path, PathBuf};
+
+/// Everything about the current run which a sink might need, beyond the outages themselves.
+pub struct SinkContext<'a> {
+    pub config: &'a Config,
+    pub build: &'a BuildContext,
+    /// The changes, stage rules and exemptions from `manually_specified.yaml`
+    pub manually_specified: &'a ManuallyInputSchedule,
+    /// The national stage timeline, followed by the timelines of providers which differ from it
+    pub timelines: &'a [Timeline],
+    /// Areas whose schedules have been replaced, and so get a warning instead of new outages
+    pub expired: &'a [&'a str],
+    /// When the schedules of the `expired` areas stopped being valid
+    pub expired_at: DateTime<FixedOffset>,
+}
+
+/// The outages of one area, as calculated from its CSV schedule.
+pub struct AreaOutages<'a> {
+    /// The path of the area's CSV schedule in `generated/`
+    pub csv_path: &'a Path,
+    pub area_name: String,
+    pub outages: Vec<PowerOutage>,
+    /// When the area's schedule stops having information about the national stages
+    pub last_finsh: Option<DateTime<FixedOffset>>,
+    /// Whether the area's inputs have changed since its files were last written
+    pub changed: bool,
+}
+
+/// A file written by a sink, along with its entry in the manifest.
+pub type WrittenFile = (PathBuf, ManifestEntry);
+
+/// Somewhere the outages get written to. Sinks are given each area's outages as they're
+/// calculated (in parallel, so `write_area` only takes `&self`), and then every area's outages
+/// once they've all been calculated.
+pub trait OutputSink: Sync {
+    /// A short name for the sink, used in logs.
+    fn name(&self) -> &'static str;
+
+    /// The files which this sink writes for each area. Areas whose inputs haven't changed and
+    /// whose files all exist aren't recalculated, unless some sink `needs_every_area`.
+    fn area_paths(&self, _area_name: &str, _ctx: &SinkContext) -> Vec<PathBuf> {
+        vec![]
+    }
+
+    /// Whether `finalize` needs the outages of every area, even those which haven't changed.
+    fn needs_every_area(&self) -> bool {
+        true
+    }
+
+    /// Write the outputs of a single area.
+    fn write_area(
+        &self,
+        _area: &mut AreaOutages,
+        _ctx: &SinkContext,
+    ) -> Result<Vec<WrittenFile>, BoxedError> {
+        Ok(vec![])
+    }
+
+    /// Write the outputs which combine every area, after all the areas have been written.
+    fn finalize(
+        &self,
+        _outages: &[PowerOutage],
+        _ctx: &SinkContext,
+    ) -> Result<Vec<WrittenFile>, BoxedError> {
+        Ok(vec![])
+    }
+}
+
+/// The sinks for every output enabled in `outputs`.
+pub fn sinks_for(outputs: &Outputs) -> Vec<Box<dyn OutputSink>> {
+    let mut sinks: Vec<Box<dyn OutputSink>> = vec![];
+    if outputs.ics_files {
+        sinks.push(Box::new(IcsSink));
+    }
+    if outputs.search_index {
+        sinks.push(Box::new(SearchIndexSink));
+    }
+    if outputs.geojson_file {
+        sinks.push(Box::new(GeoJsonSink));
+    }
+    if outputs.parquet_files {
+        sinks.push(Box::new(ParquetSink));
+    }
+    if outputs.csv_file {
+        sinks.push(Box::new(CsvSink));
+    }
+    if outputs.timeline_files {
+        sinks.push(Box::new(TimelineSink));
+    }
+    sinks
+}
+
+/// One human-friendly ICS calendar per area, which is only rewritten when its inputs change.
+pub struct IcsSink;
+
+impl OutputSink for IcsSink {
+    fn name(&self) -> &'static str {
+        "ics"
+    }
+
+    fn area_paths(&self, area_name: &str, ctx: &SinkContext) -> Vec<PathBuf> {
+        vec![ctx.config.ics_path(area_name)]
+    }
+
+    fn needs_every_area(&self) -> bool {
+        false
+    }
+
+    fn write_area(
+        &self,
+        area: &mut AreaOutages,
+        ctx: &SinkContext,
+    ) -> Result<Vec<WrittenFile>, BoxedError> {
+        if !area.changed {
+            return Ok(vec![]);
+        }
+        let calendar = write_sheddings_to_ics(
+            area.csv_path,
+            &mut area.outages,
+            area.last_finsh,
+            &ctx.manually_specified.exemptions_for_area(&area.area_name),
+            ctx.expired.to_vec(),
+            ctx.expired_at,
+            ctx.build,
+            ctx.config,
+        )?;
+        let event_count = calendar
+            .components
+            .iter()
+            .filter(|c| c.as_event().is_some())
+            .count();
+        let path = ctx.config.ics_path(&area.area_name);
+        let entry = ManifestEntry::new(
+            &path,
+            Some(event_count),
+            &area.outages,
+            ctx.build.compiled_at,
+        )?;
+        Ok(vec![(path, entry)])
+    }
+}
+
+/// One machine-friendly CSV file containing every outage, plus a few areas on their own.
+pub struct CsvSink;
+
+impl OutputSink for CsvSink {
+    fn name(&self) -> &'static str {
+        "csv"
+    }
+
+    fn finalize(
+        &self,
+        outages: &[PowerOutage],
+        ctx: &SinkContext,
+    ) -> Result<Vec<WrittenFile>, BoxedError> {
+        let config = ctx.config;
+        let path = config.output_path(&config.csv_file_name);
+        let entry = overwrite_lines_to_csv(&mut outages.to_vec(), &path, ctx.build)?;
+        let mut written = vec![(path, entry)];
+        // Some devices can't download the full CSV, so also write out some areas on their own
+        for area in &config.policies.individual_csv_areas {
+            let path = config.output_path(&format!("{}.csv", area));
+            let entry = overwrite_lines_to_csv(
+                &mut outages
+                    .iter()
+                    .filter(|&l| &l.area_name == area)
+                    .cloned()
+                    .collect::<Vec<_>>(),
+                &path,
+                ctx.build,
+            )?;
+            written.push((path, entry));
+        }
+        Ok(written)
+    }
+}
+
+/// Analytics-friendly Parquet files, partitioned by month.
+pub struct ParquetSink;
+
+impl OutputSink for ParquetSink {
+    fn name(&self) -> &'static str {
+        "parquet"
+    }
+
+    fn finalize(
+        &self,
+        outages: &[PowerOutage],
+        ctx: &SinkContext,
+    ) -> Result<Vec<WrittenFile>, BoxedError> {
+        let metadata = read::read_area_metadata(&ctx.config.area_metadata)?;
+        let paths = export::write_outages_to_parquet(
+            outages,
+            &metadata,
+            &ctx.config.output_path("parquet"),
+        )?;
+        paths
+            .into_iter()
+            .map(|path| {
+                let entry = ManifestEntry::new(&path, None, &[], ctx.build.compiled_at)?;
+                Ok((path, entry))
+            })
+            .collect()
+    }
+}
+
+/// A precomputed suburb search index for the website. It doesn't depend on the outages at all.
+pub struct SearchIndexSink;
+
+impl OutputSink for SearchIndexSink {
+    fn name(&self) -> &'static str {
+        "search-index"
+    }
+
+    fn needs_every_area(&self) -> bool {
+        false
+    }
+
+    fn finalize(
+        &self,
+        _outages: &[PowerOutage],
+        ctx: &SinkContext,
+    ) -> Result<Vec<WrittenFile>, BoxedError> {
+        let metadata = read::read_area_metadata(&ctx.config.area_metadata)?;
+        let path = ctx.config.output_path("search_index.json");
+        search::write_search_index(&metadata, &path)?;
+        let entry = ManifestEntry::new(&path, None, &[], ctx.build.compiled_at)?;
+        Ok(vec![(path, entry)])
+    }
+}
+
+/// A map of the areas which have boundaries, coloured by their current stage.
+pub struct GeoJsonSink;
+
+impl OutputSink for GeoJsonSink {
+    fn name(&self) -> &'static str {
+        "geojson"
+    }
+
+    fn finalize(
+        &self,
+        outages: &[PowerOutage],
+        ctx: &SinkContext,
+    ) -> Result<Vec<WrittenFile>, BoxedError> {
+        let boundaries = geo::read_boundaries(&ctx.config.boundaries_dir)?;
+        let statuses = boundaries
+            .iter()
+            .map(|boundary| {
+                let mut outages = outages
+                    .iter()
+                    .filter(|outage| outage.area_name == boundary.area_name && !outage.cancelled)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                outages.sort_by_key(|outage| outage.start);
+                query::area_status(
+                    &boundary.area_name,
+                    &outages,
+                    ctx.manually_specified,
+                    ctx.build.compiled_at,
+                )
+            })
+            .collect::<Vec<_>>();
+        let path = ctx.config.output_path("areas.geojson");
+        geo::write_stage_geojson(&boundaries, &statuses, &path)?;
+        let entry = ManifestEntry::new(&path, None, &[], ctx.build.compiled_at)?;
+        Ok(vec![(path, entry)])
+    }
+}
+
+/// The stage over time nationally and for each provider which differs, as ICS, CSV and JSON. It
+/// only depends on the changes, not on any area's outages.
+pub struct TimelineSink;
+
+impl OutputSink for TimelineSink {
+    fn name(&self) -> &'static str {
+        "timeline"
+    }
+
+    fn needs_every_area(&self) -> bool {
+        false
+    }
+
+    fn finalize(
+        &self,
+        _outages: &[PowerOutage],
+        ctx: &SinkContext,
+    ) -> Result<Vec<WrittenFile>, BoxedError> {
+        timeline::write_timelines(
+            ctx.timelines,
+            &ctx.config.output_path("timeline"),
+            ctx.build,
+        )?
+        .into_iter()
+        .map(|(path, block_count)| {
+            let entry = ManifestEntry::new(&path, Some(block_count), &[], ctx.build.compiled_at)?;
+            Ok((path, entry))
+        })
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{Config, Outputs};
+    use crate::sink::{
+        sinks_for, AreaOutages, CsvSink, GeoJsonSink, IcsSink, OutputSink, SinkContext,
+    };
+    use crate::structs::{BuildContext, ManuallyInputSchedule, PowerOutage};
+    use chrono::DateTime;
+    use std::fs::{create_dir_all, read_to_string, remove_dir_all, write};
+    use std::path::Path;
+
+    fn outage(area_name: &str, start: &str, finsh: &str) -> PowerOutage {
+        PowerOutage {
+            area_name: area_name.to_owned(),
+            stage: 2,
+            start: DateTime::parse_from_rfc3339(start).unwrap(),
+            finsh: DateTime::parse_from_rfc3339(finsh).unwrap(),
+            source: "test source".to_owned(),
+            cancelled: false,
+            tentative: false,
+        }
+    }
+
+    fn build() -> BuildContext {
+        BuildContext {
+            git_hash: "0123456789abcdef".to_owned(),
+            run_url: None,
+            compiled_at: DateTime::parse_from_rfc3339("2022-01-01T00:00:00+02:00").unwrap(),
+        }
+    }
+
+    fn manually_specified() -> ManuallyInputSchedule {
+        ManuallyInputSchedule {
+            changes: vec![],
+            historical_changes: vec![],
+            stage_rules: vec![],
+            exemptions: vec![],
+            projected_until: None,
+        }
+    }
+
+    fn config(name: &str) -> Config {
+        let output_dir = std::env::temp_dir().join(format!("eskom-calendar-test-sink-{name}"));
+        create_dir_all(&output_dir).unwrap();
+        Config {
+            output_dir,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn sinks_follow_outputs() {
+        let names = |outputs: &Outputs| {
+            sinks_for(outputs)
+                .iter()
+                .map(|sink| sink.name())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&Outputs::default()), vec!["ics", "csv"]);
+        let only_parquet = Config::default()
+            .with_export_formats(&[crate::structs::ExportFormat::Parquet])
+            .outputs;
+        assert_eq!(names(&only_parquet), vec!["parquet"]);
+    }
+
+    #[test]
+    fn unchanged_areas_arent_rewritten() {
+        let config = config("ics-unchanged");
+        let build = build();
+        let ctx = SinkContext {
+            config: &config,
+            build: &build,
+            manually_specified: &manually_specified(),
+            timelines: &[],
+            expired: &[],
+            expired_at: build.compiled_at,
+        };
+        let mut area = AreaOutages {
+            csv_path: Path::new("generated/test-area.csv"),
+            area_name: "test-area".to_owned(),
+            outages: vec![outage(
+                "test-area",
+                "2022-01-01T10:00:00+02:00",
+                "2022-01-01T12:00:00+02:00",
+            )],
+            last_finsh: None,
+            changed: false,
+        };
+        assert!(IcsSink.write_area(&mut area, &ctx).unwrap().is_empty());
+        area.changed = true;
+        let written = IcsSink.write_area(&mut area, &ctx).unwrap();
+        remove_dir_all(&config.output_dir).unwrap();
+        assert_eq!(written.len(), 1);
+        assert_eq!(written[0].0, config.output_dir.join("test-area.ics"));
+        assert_eq!(written[0].1.event_count, Some(1));
+    }
+
+    #[test]
+    fn csv_includes_individual_areas() {
+        let mut config = config("csv");
+        config.policies.individual_csv_areas = vec!["area-1".to_owned()];
+        let build = build();
+        let ctx = SinkContext {
+            config: &config,
+            build: &build,
+            manually_specified: &manually_specified(),
+            timelines: &[],
+            expired: &[],
+            expired_at: build.compiled_at,
+        };
+        let outages = vec![
+            outage(
+                "area-2",
+                "2022-01-01T10:00:00+02:00",
+                "2022-01-01T12:00:00+02:00",
+            ),
+            outage(
+                "area-1",
+                "2022-01-01T10:00:00+02:00",
+                "2022-01-01T12:00:00+02:00",
+            ),
+        ];
+        let written = CsvSink.finalize(&outages, &ctx).unwrap();
+        remove_dir_all(&config.output_dir).unwrap();
+        let summary = written
+            .iter()
+            .map(|(path, entry)| (path.clone(), entry.event_count))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                (config.output_dir.join("machine_friendly.csv"), Some(2)),
+                (config.output_dir.join("area-1.csv"), Some(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn geojson_status_is_as_of_compile_time() {
+        let mut config = config("geojson");
+        config.boundaries_dir = config.output_dir.join("boundaries");
+        create_dir_all(&config.boundaries_dir).unwrap();
+        write(
+            config.boundaries_dir.join("area-1.geojson"),
+            r#"{"type": "Polygon", "coordinates": [[[18, -34], [19, -34], [19, -33], [18, -34]]]}"#,
+        )
+        .unwrap();
+        let build = build();
+        let ctx = SinkContext {
+            config: &config,
+            build: &build,
+            manually_specified: &manually_specified(),
+            timelines: &[],
+            expired: &[],
+            expired_at: build.compiled_at,
+        };
+        // The outage is during the build, which is years before now
+        let outages = vec![outage(
+            "area-1",
+            "2021-12-31T23:00:00+02:00",
+            "2022-01-01T01:00:00+02:00",
+        )];
+        let written = GeoJsonSink.finalize(&outages, &ctx).unwrap();
+        let geojson = read_to_string(&written[0].0).unwrap();
+        remove_dir_all(&config.output_dir).unwrap();
+        assert!(geojson.contains(r#""power_off":true"#), "{geojson}");
+    }
+}
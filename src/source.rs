@@ -0,0 +1,322 @@
+//! The formats which an area's recurring loadshedding schedule can be read from. Each format is a
+//! `ScheduleSource`, so supporting a new format only needs a new source (added to `SOURCES`),
+//! without touching the code which turns the schedules into outages.
+use crate::structs::{
+    RawDatedShedding, RawMonthlyShedding, RawPeriodicShedding, RawRuleShedding, RawWeeklyShedding,
+    RecurringShedding,
+};
+use crate::BoxedError;
+use csv::{ReaderBuilder, Trim};
+use log::info;
+use serde::Deserialize;
+use std::fmt::Display;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+/// Every format which schedules can be read from.
+const SOURCES: &[&dyn ScheduleSource] = &[&CsvSource, &YamlSource, &JsonSource];
+
+/// Where a schedule was read from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Provenance {
+    /// The file the schedule was read from
+    pub path: PathBuf,
+    /// The name of the `ScheduleSource` which read it
+    pub format: &'static str,
+}
+
+impl Display for Provenance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} (read as {})", self.path, self.format)
+    }
+}
+
+/// The recurring loadshedding schedule of one area, along with where it was read from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    pub sheddings: Vec<RecurringShedding>,
+    pub provenance: Provenance,
+}
+
+/// A file format which recurring loadshedding schedules can be read from.
+pub trait ScheduleSource: Sync {
+    /// A short name for the format, used in logs and in a schedule's provenance.
+    fn name(&self) -> &'static str;
+
+    /// Whether this source can read the file at `path`, usually judged by its extension.
+    fn can_read(&self, path: &Path) -> bool;
+
+    /// Read every recurring shedding in the file at `path`, including those at stage 0.
+    fn read(&self, path: &Path) -> Result<Vec<RecurringShedding>, BoxedError>;
+}
+
+/// Whether `path` has the extension `ext`.
+fn has_extension(path: &Path, ext: &str) -> bool {
+    path.extension().is_some_and(|e| e == ext)
+}
+
+/// The source which can read the file at `path`, if there is one.
+pub fn source_for(path: &Path) -> Option<&'static dyn ScheduleSource> {
+    SOURCES.iter().find(|source| source.can_read(path)).copied()
+}
+
+/// Read the schedule at `path` with whichever source can read it. Sheddings at stage 0 are left
+/// out, since they never cause an outage.
+pub fn read_schedule(path: &Path) -> Result<Schedule, BoxedError> {
+    let source = source_for(path)
+        .ok_or_else(|| format!("Don't know how to read a schedule from {path:?}"))?;
+    let provenance = Provenance {
+        path: path.to_path_buf(),
+        format: source.name(),
+    };
+    let sheddings = source
+        .read(path)
+        .map_err(|e| format!("Couldn't read schedule from {provenance}: {e}"))?
+        .into_iter()
+        .filter(|shedding| shedding.stage != 0)
+        .collect();
+    Ok(Schedule {
+        sheddings,
+        provenance,
+    })
+}
+
+/// CSV files like those in `generated/`, where the kind of recurrence depends on the headers:
+/// `date_of_month` for monthly, `day_of_week` for weekly, `day_of_cycle` for periodic, `rrule` for
+/// RFC 5545 recurrence rules and `date` for schedules which don't repeat.
+pub struct CsvSource;
+
+impl ScheduleSource for CsvSource {
+    fn name(&self) -> &'static str {
+        "csv"
+    }
+
+    fn can_read(&self, path: &Path) -> bool {
+        has_extension(path, "csv")
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<RecurringShedding>, BoxedError> {
+        let mut reader = ReaderBuilder::new()
+            .trim(Trim::All) // Remove leading/trailing whitespace
+            .comment(Some(b'#')) // Allow comment lines if they start with #
+            .from_path(path)?;
+
+        let headers = reader.headers()?;
+
+        // Parse the CSV file in a manner that depends on the headers
+        if headers.iter().any(|h| h == "date_of_month") {
+            info!("Parsing {path:?} as a Monthly recurrence");
+            reader
+                .deserialize::<RawMonthlyShedding>()
+                .map(|res| RecurringShedding::try_from(res?))
+                .collect()
+        } else if headers.iter().any(|h| h == "day_of_week") {
+            info!("Parsing {path:?} as a Weekly recurrence");
+            // Monday is day 1
+            reader
+                .deserialize::<RawWeeklyShedding>()
+                .map(|res| RecurringShedding::try_from(res?))
+                .collect()
+        } else if headers.iter().any(|h| h == "day_of_cycle") {
+            info!("Parsing {path:?} as a Periodic recurrence");
+            reader
+                .deserialize::<RawPeriodicShedding>()
+                .map(|res| RecurringShedding::try_from(res?))
+                .collect()
+        } else if headers.iter().any(|h| h == "rrule") {
+            info!("Parsing {path:?} as a Rule recurrence");
+            reader
+                .deserialize::<RawRuleShedding>()
+                .map(|res| RecurringShedding::try_from(res?))
+                .collect()
+        } else if headers.iter().any(|h| h == "date") {
+            info!("Parsing {path:?} as a Dated schedule");
+            reader
+                .deserialize::<RawDatedShedding>()
+                .map(|res| RecurringShedding::try_from(res?))
+                .collect()
+        } else {
+            Err(Box::from(format!(
+                "Could not parse headers from {path:?}: {headers:?}"
+            )))
+        }
+    }
+}
+
+/// One row of a YAML or JSON schedule. The kind of recurrence depends on which fields the row
+/// has, in the same way as the headers of a CSV schedule.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum RawRecurringShedding {
+    Monthly(RawMonthlyShedding),
+    Weekly(RawWeeklyShedding),
+    Periodic(RawPeriodicShedding),
+    Rule(RawRuleShedding),
+    Dated(RawDatedShedding),
+}
+
+impl TryFrom<RawRecurringShedding> for RecurringShedding {
+    type Error = BoxedError;
+
+    fn try_from(raw: RawRecurringShedding) -> Result<Self, Self::Error> {
+        match raw {
+            RawRecurringShedding::Monthly(raw) => raw.try_into(),
+            RawRecurringShedding::Weekly(raw) => raw.try_into(),
+            RawRecurringShedding::Periodic(raw) => raw.try_into(),
+            RawRecurringShedding::Rule(raw) => raw.try_into(),
+            RawRecurringShedding::Dated(raw) => raw.try_into(),
+        }
+    }
+}
+
+/// A YAML or JSON schedule, which is a list of rows under `sheddings`. For example:
+///
+/// ```yaml
+/// sheddings:
+///   - { date_of_month: 1, start_time: "00:00", finsh_time: "02:30", stage: 1 }
+///   - { date_of_month: 1, start_time: "00:00", finsh_time: "02:30", stage: 5 }
+/// ```
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct RawSchedule {
+    sheddings: Vec<RawRecurringShedding>,
+}
+
+/// YAML files with the same rows as a CSV schedule. See `RawSchedule`.
+pub struct YamlSource;
+
+impl ScheduleSource for YamlSource {
+    fn name(&self) -> &'static str {
+        "yaml"
+    }
+
+    fn can_read(&self, path: &Path) -> bool {
+        has_extension(path, "yaml") || has_extension(path, "yml")
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<RecurringShedding>, BoxedError> {
+        let raw = serde_yaml::from_str::<RawSchedule>(&read_to_string(path)?)?;
+        raw.sheddings.into_iter().map(TryInto::try_into).collect()
+    }
+}
+
+/// JSON files with the same rows as a CSV schedule. See `RawSchedule`.
+pub struct JsonSource;
+
+impl ScheduleSource for JsonSource {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn can_read(&self, path: &Path) -> bool {
+        has_extension(path, "json")
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<RecurringShedding>, BoxedError> {
+        let raw = serde_json::from_str::<RawSchedule>(&read_to_string(path)?)?;
+        raw.sheddings.into_iter().map(TryInto::try_into).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::source::{read_schedule, source_for};
+    use crate::structs::Recurrence;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+    use std::path::Path;
+
+    #[test]
+    fn sources_are_chosen_by_extension() {
+        let name = |path: &str| source_for(Path::new(path)).map(|source| source.name());
+        assert_eq!(name("generated/western-cape-stellenbosch.csv"), Some("csv"));
+        assert_eq!(
+            name("generated/western-cape-stellenbosch.yaml"),
+            Some("yaml")
+        );
+        assert_eq!(
+            name("generated/western-cape-stellenbosch.json"),
+            Some("json")
+        );
+        assert_eq!(name("generated/README.md"), None);
+    }
+
+    #[test]
+    fn formats_give_same_sheddings() {
+        let dir = std::env::temp_dir().join("eskom-calendar-test-sources");
+        create_dir_all(&dir).unwrap();
+        write(
+            dir.join("area.csv"),
+            "day_of_week,start_time,finsh_time,stage\n1,00:00,02:30,1\n1,00:00,02:30,0\n",
+        )
+        .unwrap();
+        write(
+            dir.join("area.yaml"),
+            "sheddings:\n  - { day_of_week: 1, start_time: \"00:00\", finsh_time: \"02:30\", stage: 1 }\n",
+        )
+        .unwrap();
+        write(
+            dir.join("area.json"),
+            r#"{"sheddings": [{"day_of_week": 1, "start_time": "00:00", "finsh_time": "02:30", "stage": 1}]}"#,
+        )
+        .unwrap();
+        let csv = read_schedule(&dir.join("area.csv")).unwrap();
+        let yaml = read_schedule(&dir.join("area.yaml")).unwrap();
+        let json = read_schedule(&dir.join("area.json")).unwrap();
+        remove_dir_all(&dir).unwrap();
+
+        // Stage 0 sheddings are left out
+        assert_eq!(csv.sheddings.len(), 1);
+        assert_eq!(
+            csv.sheddings[0].recurrence,
+            Recurrence::Weekly { day_of_week: 1 }
+        );
+        assert_eq!(csv.sheddings, yaml.sheddings);
+        assert_eq!(csv.sheddings, json.sheddings);
+        assert_eq!(yaml.provenance.format, "yaml");
+        assert_eq!(json.provenance.path, dir.join("area.json"));
+    }
+
+    #[test]
+    fn err_if_bad_row() {
+        let dir = std::env::temp_dir().join("eskom-calendar-test-sources-bad-row");
+        create_dir_all(&dir).unwrap();
+        write(
+            dir.join("area.csv"),
+            "day_of_week,start_time,finsh_time,stage\n1,00:00,02:30,1\n8,00:00,02:30,1\n",
+        )
+        .unwrap();
+        write(
+            dir.join("area.yaml"),
+            "sheddings:\n  - { day_of_week: 1, start_time: \"00:00\", finsh_time: \"2:3O\", stage: 1 }\n",
+        )
+        .unwrap();
+        let csv = read_schedule(&dir.join("area.csv"));
+        let yaml = read_schedule(&dir.join("area.yaml"));
+        remove_dir_all(&dir).unwrap();
+        assert!(csv.is_err());
+        assert!(yaml.is_err());
+    }
+
+    #[test]
+    fn err_if_day_outside_cycle() {
+        let dir = std::env::temp_dir().join("eskom-calendar-test-sources-bad-cycle");
+        create_dir_all(&dir).unwrap();
+        write(
+            dir.join("area.csv"),
+            "day_of_cycle,period_of_cycle,start_of_cycle,start_time,finsh_time,stage\n\
+             0,19,2023-01-01,00:00,02:30,1\n",
+        )
+        .unwrap();
+        let schedule = read_schedule(&dir.join("area.csv"));
+        remove_dir_all(&dir).unwrap();
+        assert!(schedule
+            .unwrap_err()
+            .to_string()
+            .contains("Day of the cycle"));
+    }
+
+    #[test]
+    fn err_if_unknown_format() {
+        assert!(read_schedule(Path::new("generated/README.md")).is_err());
+    }
+}
@@ -1,55 +1,60 @@
-use clap::Parser;
-use log::info;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand};
 use regex::Regex;
+use std::collections::BTreeSet;
 use std::fmt::{Debug, Display};
+use std::path::PathBuf;
 
-use chrono::{DateTime, FixedOffset, NaiveDate, NaiveTime};
+use crate::rrule::RRule;
+use crate::BoxedError;
+
+use chrono::{
+    DateTime, Datelike, Days, Duration, FixedOffset, NaiveDate, NaiveTime, TimeZone, Timelike,
+};
 use serde::{Deserialize, Serialize};
 
 /// Represents a duration of time for which the power will be out for a particular area.
 ///
 /// Requires specifying where the information came from (in `source`) as well as the stage of
 /// loadshedding.
-#[derive(PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct PowerOutage {
     pub area_name: String,
     pub stage: u8,
     pub start: DateTime<FixedOffset>,
     pub finsh: DateTime<FixedOffset>,
     pub source: String,
+    /// Whether this outage was published by a previous run but has since been withdrawn (because
+    /// its change was edited or removed). Cancelled outages are kept for a while so that people
+    /// who planned around them notice, but they aren't really going to happen.
+    pub cancelled: bool,
+    /// Whether this outage was projected from a change which continues until further notice,
+    /// instead of being announced. Tentative outages might not happen.
+    pub tentative: bool,
 }
 
 impl PowerOutage {
     pub fn csv_header() -> String {
-        "area_name,start,finsh,stage,source".to_owned()
+        "area_name,start,finsh,stage,source,cancelled,tentative".to_owned()
     }
 }
 
 impl PartialOrd for PowerOutage {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        match self.area_name.partial_cmp(&other.area_name) {
-            Some(core::cmp::Ordering::Equal) => {}
-            ord => return ord,
-        }
-        match self.start.partial_cmp(&other.start) {
-            Some(core::cmp::Ordering::Equal) => {}
-            ord => return ord,
-        }
-        match self.finsh.partial_cmp(&other.finsh) {
-            Some(core::cmp::Ordering::Equal) => {}
-            ord => return ord,
-        }
-        match self.stage.partial_cmp(&other.stage) {
-            Some(core::cmp::Ordering::Equal) => {}
-            ord => return ord,
-        }
-        self.source.partial_cmp(&other.source)
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for PowerOutage {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other).unwrap_or(std::cmp::Ordering::Equal)
+        self.area_name
+            .cmp(&other.area_name)
+            .then_with(|| self.start.cmp(&other.start))
+            .then_with(|| self.finsh.cmp(&other.finsh))
+            .then_with(|| self.stage.cmp(&other.stage))
+            .then_with(|| self.source.cmp(&other.source))
+            .then_with(|| self.cancelled.cmp(&other.cancelled))
+            .then_with(|| self.tentative.cmp(&other.tentative))
     }
 }
 
@@ -57,27 +62,43 @@ impl Display for PowerOutage {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "{area_name},{start:?},{finsh:?},{stage},{source:?}",
+            "{area_name},{start:?},{finsh:?},{stage},{source:?},{cancelled},{tentative}",
             area_name = self.area_name,
             start = self.start,
             finsh = self.finsh,
             stage = self.stage,
             source = self.source,
+            cancelled = self.cancelled,
+            tentative = self.tentative,
         )
     }
 }
 
+/// Details about this run of the program which are included in the calendars. These are computed
+/// once in `main` and passed to every formatter, so every event in a run describes the same build.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildContext {
+    /// The hash of the git commit used to build the calendars
+    pub git_hash: String,
+    /// A link to the GitHub Actions run which built the calendars, if there was one
+    pub run_url: Option<String>,
+    /// When the calendars were compiled
+    pub compiled_at: DateTime<FixedOffset>,
+}
+
 /// Parse a number of CSV files and a manually_specified YAML file into various load shedding
 /// outputs.
 ///
 /// # Examples
 ///
-/// By default, convert all files matching `generated/*.csv` into ICS calendar files (which are
-/// written to `calendars/*.ics`), and also write the same data as a machine-friendly CSV file to
-/// `calendars/machine_friendly.csv`:
+/// By default (or with the `generate` command), convert all files matching `generated/*.csv` into
+/// ICS calendar files (which are written to `calendars/*.ics`), and also write the same data as a
+/// machine-friendly CSV file to `calendars/machine_friendly.csv`:
 ///
 ///     cargo run --release
 ///
+///     cargo run --release -- generate
+///
 /// The program is silent by default. Set the `RUST_LOG` environment variable to choose your
 /// desired logging level to one of trace, info, debug, warn, error:
 ///
@@ -87,41 +108,326 @@ impl Display for PowerOutage {
 ///
 /// You can choose to only calculate loadshedding for files matching the provided regex:
 ///
-///     RUST_LOG=info cargo run --release -- --include-regex "city-of-cape-town-area-10"
+///     RUST_LOG=info cargo run --release -- generate --include-regex "city-of-cape-town-area-10"
+///
+///     RUST_LOG=info cargo run --release -- generate --include-regex "western-cape-stellenbosch"
 ///
-///     RUST_LOG=info cargo run --release -- --include-regex "western-cape-stellenbosch"
+///     RUST_LOG=info cargo run --release -- generate --include-regex "western-cape|eastern-cape"
 ///
-///     RUST_LOG=info cargo run --release -- --include-regex "western-cape|eastern-cape"
+///     RUST_LOG=info cargo run --release -- generate --include-regex "gauteng"
 ///
-///     RUST_LOG=info cargo run --release -- --include-regex "gauteng"
+/// Every file is written to a temporary file first and then renamed, so a crash never leaves a
+/// truncated file behind. `calendars/manifest.json` lists every output file along with its SHA-256
+/// hash, number of events, first and last outage, and when it was generated.
 ///
 /// You can choose whether or not you want ICS/CSV files to be calculated and written with the
 /// `--output-ics-files` and `--output-csv-file` flags. These are true by default. Calculating and
 /// writing the ICS files to disk takes a lot longer than the CSV file.
 ///
-///     RUST_LOG=info cargo run --release -- --output-ics-files=false
+///     RUST_LOG=info cargo run --release -- generate --output-ics-files=false
+///
+///     RUST_LOG=info cargo run --release -- generate --output-csv-file=false
+///
+/// Calendars whose inputs haven't changed since the last run are not rewritten. The inputs are
+/// the calendar's CSV schedule, the changes and exemptions in `manually_specified.yaml` which
+/// apply to it, its cancelled outages, the `min_event_minutes` policy and `FORMAT_VERSION` (in
+/// `src/cache.rs`). If you change what gets written to the calendars, bump `FORMAT_VERSION` so
+/// that they're all rewritten. The hashes of the inputs are kept in `calendars/.cache.json`. Use
+/// `--incremental=false` to rewrite every calendar anyway:
+///
+///     RUST_LOG=info cargo run --release -- generate --incremental=false
+///
+/// For long-term analysis, the same data can be written as Parquet files partitioned by month
+/// (to `calendars/parquet/month=YYYY-MM/outages.parquet`). The `export` command only writes the
+/// formats you ask for, and skips the ICS files:
+///
+///     RUST_LOG=info cargo run --release -- export parquet
+///
+///     RUST_LOG=info cargo run --release -- export csv search-index
+///
+/// If you only want to check that `manually_specified.yaml` is valid, you can use the `check`
+/// command. This is a lot faster than actually creating the ICS/CSV files. It also checks that
+/// `area_metadata.yaml` lines up with the schedules in `generated/`
+///
+///     RUST_LOG=info cargo run --release -- check
+///
+/// You can also ask about a single area (or a suburb listed in `area_metadata.yaml`) without
+/// writing any files, using the `query status` and `query next` commands:
+///
+///     cargo run --release -- query status city-of-cape-town-area-7
+///
+///     cargo run --release -- query next stellenbosch --count 3
+///
+/// If you don't know which area you're in, search the suburbs, municipalities and provinces:
+///
+///     cargo run --release -- query search "wag n bietjie"
+///
+/// If the area boundaries are available in `boundaries/*.geojson`, you can find your area from
+/// its GPS position, and also write out a GeoJSON map of every area coloured by its current stage
+/// (to `calendars/areas.geojson`):
+///
+///     cargo run --release -- query locate -33.9249 18.4241
+///
+///     RUST_LOG=info cargo run --release -- export geojson
+///
+/// To see how a change to `manually_specified.yaml` affects the calendars, compare the outages
+/// calculated from two versions of it (or from two git revisions checked out to directories):
+///
+///     cargo run --release -- diff old_manually_specified.yaml manually_specified.yaml
+///
+///     git worktree add ../eskom-calendar-main main
+///     cargo run --release -- diff ../eskom-calendar-main .
+///
+/// To try out the calendars locally (for example, by subscribing to them from a calendar app on
+/// the same network), serve the output directory over HTTP:
+///
+///     cargo run --release -- serve --port 8000
 ///
-///     RUST_LOG=info cargo run --release -- --output-csv-file=false
+/// After editing the schedules in `generated/` by hand, rewrite them in a canonical form (or only
+/// check that they are with `--check`):
 ///
-/// If you only want to check that `manually_specified.yaml` is valid, you can use the
-/// `--only-check-for-overlaps` flag. This is a lot faster than actually creating the ICS/CSV files
+///     cargo run --release -- fmt-schedules --check
 ///
-///     RUST_LOG=info cargo run --release -- --only-check-for-overlaps=true
+/// Eskom's own schedules (the `eskom-direct-*` calendars) come from the `*_LS.xlsx` spreadsheets
+/// which Eskom publishes for each province. Import all of them at once to update `generated/` and
+/// `area_metadata.yaml`, or only check that those are up to date with `--verify`:
+///
+///     cargo run --release -- import-eskom --verify xlsx/*_LS.xlsx
+///
+/// All of the paths and outputs can also be set in a TOML config file (see `config.rs` for every
+/// setting), which is useful for running against a fork of the data with a different layout:
+///
+///     RUST_LOG=info cargo run --release -- --config my-fork.toml
+///
+///     RUST_LOG=info cargo run --release -- --generated-dir ../my-fork/schedules --output-dir /tmp/calendars
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
 pub struct Args {
+    #[command(flatten)]
+    pub paths: PathArgs,
+    /// The options used when no command is given, which are the same as for `generate`.
+    #[command(flatten)]
+    pub generate: GenerateArgs,
+    /// Deprecated: use the `check` command instead.
+    #[arg(long, action=clap::ArgAction::Set, default_value_t = false, hide = true)]
+    pub only_check_for_overlaps: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Args {
+    /// The command to run. Without a command, the calendars are generated just like they were
+    /// before there were commands, so that existing scripts keep working.
+    ///
+    /// The options for generating calendars can only be given before a command if there isn't
+    /// one, since they'd otherwise be silently ignored.
+    pub fn command(self) -> Result<Command, clap::Error> {
+        match self.command {
+            Some(_) if !self.generate.is_empty() || self.only_check_for_overlaps => {
+                Err(<Args as CommandFactory>::command().error(
+                    ErrorKind::ArgumentConflict,
+                    "The options for generating calendars (like --output-csv-file) must come \
+                     after `generate`, and can't be used with any other command",
+                ))
+            }
+            Some(command) => Ok(command),
+            None if self.only_check_for_overlaps => Ok(Command::Check),
+            None => Ok(Command::Generate(self.generate)),
+        }
+    }
+}
+
+/// Where the inputs are read from and the outputs are written to. These are shared by every
+/// command.
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct PathArgs {
+    /// A TOML config file. Defaults to `eskom-calendar.toml`, if it exists. Any of the options
+    /// below override the config file.
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    /// The directory containing the CSV schedules. Defaults to `generated/`.
+    #[arg(long, global = true)]
+    pub generated_dir: Option<PathBuf>,
+    /// The YAML file containing the national loadshedding changes. Defaults to
+    /// `manually_specified.yaml`.
+    #[arg(long, global = true)]
+    pub manually_specified: Option<PathBuf>,
+    /// The YAML file describing which suburbs are in which area. Defaults to `area_metadata.yaml`.
+    #[arg(long, global = true)]
+    pub area_metadata: Option<PathBuf>,
+    /// The directory containing the GeoJSON area boundaries. Defaults to `boundaries/`.
+    #[arg(long, global = true)]
+    pub boundaries_dir: Option<PathBuf>,
+    /// The directory which the outputs are written to. Defaults to `calendars/`.
+    #[arg(long, global = true)]
+    pub output_dir: Option<PathBuf>,
+}
+
+/// Which calendars and outputs are written by `generate`.
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct GenerateArgs {
     /// An optional regex, against which all input CSV schedules must match.
     #[arg(short, long)]
     pub include_regex: Option<Regex>,
-    /// Whether or not to output human-friendly ICS files.
-    #[arg(long, action=clap::ArgAction::Set, default_value_t = true)]
-    pub output_ics_files: bool,
-    /// Whether or not to output a machine-friendly CSV file.
-    #[arg(long, action=clap::ArgAction::Set, default_value_t = true)]
-    pub output_csv_file: bool,
-    /// This option provides a fast check which ensures that the YAML is valid.
-    #[arg(long, action=clap::ArgAction::Set, default_value_t = false)]
-    pub only_check_for_overlaps: bool,
+    /// Whether or not to output human-friendly ICS files. Defaults to true.
+    #[arg(long, action=clap::ArgAction::Set)]
+    pub output_ics_files: Option<bool>,
+    /// Whether or not to output a machine-friendly CSV file. Defaults to true.
+    #[arg(long, action=clap::ArgAction::Set)]
+    pub output_csv_file: Option<bool>,
+    /// Whether or not to output analytics-friendly Parquet files, partitioned by month. Defaults
+    /// to false.
+    #[arg(long, action=clap::ArgAction::Set)]
+    pub output_parquet_files: Option<bool>,
+    /// Whether or not to output a JSON search index of all the suburbs for the website. Defaults
+    /// to false.
+    #[arg(long, action=clap::ArgAction::Set)]
+    pub output_search_index: Option<bool>,
+    /// Whether or not to output a GeoJSON map of the area boundaries, coloured by current stage.
+    /// Defaults to false.
+    #[arg(long, action=clap::ArgAction::Set)]
+    pub output_geojson_file: Option<bool>,
+    /// Whether or not to output the national stage timeline (and each provider's, where it
+    /// differs) as ICS, CSV and JSON. Defaults to false.
+    #[arg(long, action=clap::ArgAction::Set)]
+    pub output_timeline_files: Option<bool>,
+    /// Whether or not to skip rewriting ICS files whose inputs haven't changed since the last run.
+    /// Defaults to true.
+    #[arg(long, action=clap::ArgAction::Set)]
+    pub incremental: Option<bool>,
+}
+
+impl GenerateArgs {
+    /// Whether none of the options were given.
+    fn is_empty(&self) -> bool {
+        let GenerateArgs {
+            include_regex,
+            output_ics_files,
+            output_csv_file,
+            output_parquet_files,
+            output_search_index,
+            output_geojson_file,
+            output_timeline_files,
+            incremental,
+        } = self;
+        include_regex.is_none()
+            && [
+                output_ics_files,
+                output_csv_file,
+                output_parquet_files,
+                output_search_index,
+                output_geojson_file,
+                output_timeline_files,
+                incremental,
+            ]
+            .iter()
+            .all(|option| option.is_none())
+    }
+}
+
+/// Everything this program can do.
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Write the calendars and the other outputs. This is what happens if no command is given.
+    Generate(GenerateArgs),
+    /// Check that the input files are valid and consistent with each other, without writing
+    /// anything. This is a lot faster than generating the calendars.
+    Check,
+    /// Answer a question about one area instead of writing out all the calendars.
+    #[command(subcommand)]
+    Query(QueryCommand),
+    /// Write some of the outputs in formats for analysis or for the website, without the ICS
+    /// calendars.
+    Export {
+        /// Which formats to write
+        #[arg(value_enum, required = true)]
+        formats: Vec<ExportFormat>,
+        /// An optional regex, against which all input CSV schedules must match.
+        #[arg(short, long)]
+        include_regex: Option<Regex>,
+    },
+    /// Compare the outages calculated from two versions of the inputs, and print a Markdown
+    /// summary of the added, removed and modified outages which can be posted as a PR comment.
+    Diff {
+        /// The old inputs: either a YAML file like `manually_specified.yaml` (which is used with
+        /// the schedules in `generated/`) or a directory containing `manually_specified.yaml` and
+        /// `generated/`
+        old: PathBuf,
+        /// The new inputs, in the same form as `old`
+        new: PathBuf,
+    },
+    /// Serve the output directory over HTTP, to try out the calendars locally.
+    Serve {
+        /// The port to listen on
+        #[arg(short, long, default_value_t = 8000)]
+        port: u16,
+    },
+    /// Rewrite the `generated/` CSVs in a canonical form: unpadded, sorted and deduplicated.
+    FmtSchedules {
+        /// Don't write anything, only fail if any schedule isn't canonical
+        #[arg(long, default_value_t = false)]
+        check: bool,
+    },
+    /// Import Eskom's `*_LS.xlsx` spreadsheets into `generated/` and `area_metadata.yaml`.
+    ImportEskom {
+        /// The spreadsheets of every province, like `WesternCape_LS.xlsx`
+        #[arg(required = true)]
+        spreadsheets: Vec<PathBuf>,
+        /// Don't write anything, only fail if the existing files differ from the spreadsheets
+        #[arg(long, default_value_t = false)]
+        verify: bool,
+    },
+}
+
+/// The formats which can be written by `export`.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// The machine-friendly CSV file containing every outage
+    Csv,
+    /// Parquet files, partitioned by month
+    Parquet,
+    /// The JSON search index of all the suburbs for the website
+    SearchIndex,
+    /// The GeoJSON map of the area boundaries, coloured by current stage
+    Geojson,
+    /// The national stage timeline, and each provider's where it differs
+    Timeline,
+}
+
+/// Commands which answer questions about a single area, or help find the right one.
+#[derive(Subcommand, Debug)]
+pub enum QueryCommand {
+    /// Show whether the power is off right now, the current stage, and when the power returns.
+    Status {
+        /// The name of an area (like `city-of-cape-town-area-7`) or of a suburb (like `bellville`)
+        area: String,
+    },
+    /// Show the next few power outages.
+    Next {
+        /// The name of an area (like `city-of-cape-town-area-7`) or of a suburb (like `bellville`)
+        area: String,
+        /// How many outages to show
+        #[arg(short, long, default_value_t = 5)]
+        count: usize,
+    },
+    /// Find calendars by suburb, municipality or province. Matching ignores case, accents and
+    /// punctuation, and tolerates small spelling mistakes.
+    Search {
+        /// The name of a suburb, municipality or province
+        query: String,
+        /// How many calendars to show
+        #[arg(short, long, default_value_t = 10)]
+        limit: usize,
+    },
+    /// Find which area a GPS position is in, using the boundaries in `boundaries/*.geojson`, and
+    /// show its status.
+    #[command(allow_negative_numbers = true)]
+    Locate {
+        /// The latitude in decimal degrees (negative in South Africa), like `-33.9249`
+        latitude: f64,
+        /// The longitude in decimal degrees, like `18.4241`
+        longitude: f64,
+    },
 }
 
 /// A multitude of load shedding
@@ -130,6 +436,163 @@ pub struct ManuallyInputSchedule {
     pub changes: Vec<Change>,
     /// LoadShedding changes, always in the past
     pub historical_changes: Vec<Change>,
+    /// How some areas' stages differ from the national stage
+    pub stage_rules: Vec<StageRule>,
+    /// Periods when loadshedding is suspended in some areas
+    pub exemptions: Vec<Exemption>,
+    /// Changes which continue until further notice are projected up to this time, if it's set.
+    /// See `project_until`.
+    pub projected_until: Option<DateTime<FixedOffset>>,
+}
+
+impl ManuallyInputSchedule {
+    /// Resolve the selectors of every change with `metadata`, so that they know which areas they
+    /// apply to.
+    pub fn resolve_selectors(&mut self, metadata: &AreaMetadata) -> Result<(), BoxedError> {
+        for change in self
+            .changes
+            .iter_mut()
+            .chain(self.historical_changes.iter_mut())
+        {
+            change
+                .area_filter
+                .resolve_selectors(metadata)
+                .map_err(|e| {
+                    format!(
+                        "{e} for the change from {} to {} ({})",
+                        change.start, change.finsh, change.source
+                    )
+                })?;
+        }
+        for exemption in self.exemptions.iter_mut() {
+            exemption
+                .area_filter
+                .resolve_selectors(metadata)
+                .map_err(|e| {
+                    format!(
+                        "{e} for the exemption from {} to {} ({})",
+                        exemption.start, exemption.finsh, exemption.source
+                    )
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Project the changes which continue until further notice up to `until`, so that they're
+    /// included (as tentative changes) by `changes_for_area`.
+    pub fn project_until(&mut self, until: DateTime<FixedOffset>) {
+        self.projected_until = Some(until);
+    }
+
+    /// The tentative changes which continue each of `changes` that's until further notice, up
+    /// to the start of the next change in `changes` or `projected_until`, whichever is first.
+    fn projections(&self, changes: &[&Change]) -> Vec<Change> {
+        let Some(projected_until) = self.projected_until else {
+            return vec![];
+        };
+        changes
+            .iter()
+            .filter(|c| c.until_further_notice)
+            .filter_map(|c| {
+                let finsh = changes
+                    .iter()
+                    .map(|next| next.start)
+                    .filter(|start| start >= &c.finsh)
+                    .fold(projected_until, |finsh, start| finsh.min(start));
+                (c.finsh < finsh).then(|| Change {
+                    start: c.finsh,
+                    finsh,
+                    until_further_notice: false,
+                    tentative: true,
+                    ..(*c).clone()
+                })
+            })
+            .collect()
+    }
+
+    /// The changes which apply to `area_name` (including any tentative projections), after
+    /// applying the first stage rule which matches it (if any) to the national changes.
+    pub fn changes_for_area(&self, area_name: &str) -> Vec<Change> {
+        self.applied(self.changes.iter(), area_name)
+    }
+
+    /// Like `changes_for_area`, but including the historical changes, sorted by when they start.
+    pub fn timeline_for_area(&self, area_name: &str) -> Vec<Change> {
+        let mut changes = self.applied(
+            self.historical_changes.iter().chain(self.changes.iter()),
+            area_name,
+        );
+        changes.sort_by_key(|c| c.start);
+        changes
+    }
+
+    /// The national changes (including historical changes and any tentative projections), without
+    /// any stage rules applied, sorted by when they start.
+    pub fn national_timeline(&self) -> Vec<Change> {
+        let changes = self
+            .historical_changes
+            .iter()
+            .chain(self.changes.iter())
+            .filter(|c| c.national)
+            .collect::<Vec<_>>();
+        let projections = self.projections(&changes);
+        let mut changes = changes
+            .into_iter()
+            .chain(projections.iter())
+            .cloned()
+            .collect::<Vec<_>>();
+        changes.sort_by_key(|c| c.start);
+        changes
+    }
+
+    /// The `changes` which apply to `area_name` plus their tentative projections, after applying
+    /// the first stage rule which matches it (if any) to the national changes.
+    fn applied<'a>(
+        &self,
+        changes: impl Iterator<Item = &'a Change>,
+        area_name: &str,
+    ) -> Vec<Change> {
+        let rule = self
+            .stage_rules
+            .iter()
+            .find(|rule| rule.include_regex.is_match(area_name));
+        let changes = changes
+            .filter(|c| c.area_filter.applies_to(area_name))
+            .collect::<Vec<_>>();
+        let projections = self.projections(&changes);
+        changes
+            .into_iter()
+            .chain(projections.iter())
+            .flat_map(|c| match rule {
+                Some(rule) if c.national => rule.apply(c),
+                _ => vec![c.clone()],
+            })
+            .collect()
+    }
+
+    /// The exemptions which apply to `area_name`.
+    pub fn exemptions_for_area(&self, area_name: &str) -> Vec<&Exemption> {
+        self.exemptions
+            .iter()
+            .filter(|e| e.area_filter.applies_to(area_name))
+            .collect()
+    }
+
+    /// Remove (or clip) the parts of `outages` which are during an exemption for their area.
+    pub fn without_exemptions(&self, outages: Vec<PowerOutage>) -> Vec<PowerOutage> {
+        self.exemptions.iter().fold(outages, |outages, exemption| {
+            outages
+                .into_iter()
+                .flat_map(|outage| {
+                    if exemption.area_filter.applies_to(&outage.area_name) {
+                        exemption.clip(outage)
+                    } else {
+                        vec![outage]
+                    }
+                })
+                .collect()
+        })
+    }
 }
 
 /// A multitude of load shedding
@@ -139,32 +602,287 @@ pub struct RawManuallyInputSchedule {
     changes: Vec<RawChange>,
     /// LoadShedding changes, always in the past
     historical_changes: Vec<RawChange>,
+    /// How some areas' stages differ from the national stage
+    #[serde(default)]
+    stage_rules: Vec<RawStageRule>,
+    /// Periods when loadshedding is suspended in some areas
+    #[serde(default)]
+    exemptions: Vec<RawExemption>,
 }
 
-impl From<RawManuallyInputSchedule> for ManuallyInputSchedule {
-    fn from(raw: RawManuallyInputSchedule) -> Self {
-        ManuallyInputSchedule {
-            changes: raw.changes.into_iter().map(|r| r.into()).collect(),
+impl TryFrom<RawManuallyInputSchedule> for ManuallyInputSchedule {
+    type Error = BoxedError;
+
+    fn try_from(raw: RawManuallyInputSchedule) -> Result<Self, Self::Error> {
+        Ok(ManuallyInputSchedule {
+            changes: raw
+                .changes
+                .into_iter()
+                .map(TryInto::try_into)
+                .collect::<Result<_, _>>()?,
             historical_changes: raw
                 .historical_changes
                 .into_iter()
-                .map(|r| r.into())
-                .collect(),
+                .map(TryInto::try_into)
+                .collect::<Result<_, _>>()?,
+            stage_rules: raw
+                .stage_rules
+                .into_iter()
+                .map(TryInto::try_into)
+                .collect::<Result<_, _>>()?,
+            exemptions: raw
+                .exemptions
+                .into_iter()
+                .map(TryInto::try_into)
+                .collect::<Result<_, _>>()?,
+            projected_until: None,
+        })
+    }
+}
+
+/// A rule for how the stage of some areas differs from the national stage, for example because
+/// City of Cape Town often runs a stage lower than Eskom. Rules are only applied to national
+/// changes (those without an `include` or `include_regex`), so that one national change is enough
+/// for every area.
+#[derive(Debug, Clone)]
+pub struct StageRule {
+    /// The areas which this rule applies to
+    pub include_regex: Regex,
+    /// How many stages higher (or lower, if negative) these areas are than the national stage
+    pub offset: i8,
+    /// The time of day that the offset applies during, if it doesn't apply all day. The window
+    /// can go over midnight, like from 22:00 to 06:00
+    pub during: Option<(NaiveTime, NaiveTime)>,
+    /// How much later the stages change for these areas than they do nationally
+    pub delay: Duration,
+}
+
+impl StageRule {
+    /// Apply this rule to the national `change`, which can split it into several changes (when the
+    /// offset only applies during part of the day). Changes which end up at stage 0 are dropped.
+    pub fn apply(&self, change: &Change) -> Vec<Change> {
+        let (start, finsh) = (change.start + self.delay, change.finsh + self.delay);
+        let offset_stage = (i16::from(change.stage) + i16::from(self.offset)).clamp(0, 255) as u8;
+        let with = |start, finsh, stage| Change {
+            start,
+            finsh,
+            stage,
+            ..change.clone()
+        };
+
+        let Some((window_start, window_finsh)) = self.during else {
+            return vec![with(start, finsh, offset_stage)]
+                .into_iter()
+                .filter(|c| c.stage > 0)
+                .collect();
+        };
+        // Split the change at every boundary of the window, starting from the day before so that
+        // windows which go over midnight are handled
+        let offset = *start.offset();
+        let at = |day: NaiveDate, time| offset.from_utc_datetime(&(day.and_time(time) - offset));
+        let mut segments = vec![];
+        let mut covered_until = start;
+        let mut day = start.date_naive() - Days::new(1);
+        while covered_until < finsh {
+            let window_start = at(day, window_start);
+            let mut window_finsh = at(day, window_finsh);
+            if window_finsh <= window_start {
+                window_finsh += Duration::days(1);
+            }
+            day = day + Days::new(1);
+            if window_finsh <= covered_until {
+                continue;
+            }
+            if covered_until < window_start {
+                segments.push(with(covered_until, window_start.min(finsh), change.stage));
+            }
+            if window_start < finsh {
+                segments.push(with(
+                    window_start.max(covered_until),
+                    window_finsh.min(finsh),
+                    offset_stage,
+                ));
+            }
+            covered_until = window_finsh.min(finsh).max(covered_until);
         }
+        segments.retain(|c| c.stage > 0 && c.start < c.finsh);
+        segments
     }
 }
 
-/// A single duration of loadshedding that only has one stage.
+/// A rule for how the stage of some areas differs from the national stage, as written in
+/// `manually_specified.yaml`. For example:
+///
+/// ```yaml
+/// stage_rules:
+/// - include: coct
+///   offset: -1
+///   during: 06:00-22:00
+/// - include: citypower
+///   delay: 02:00
+/// ```
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RawStageRule {
+    /// A shorthand for the areas which this rule applies to, like `coct`. See
+    /// `RawChange::include`.
+    pub include: Option<String>,
+    /// A rust-regex pattern which the area name must match for this rule to apply to it.
+    pub include_regex: Option<String>,
+    /// How many stages higher (or lower, if negative) these areas are than the national stage
+    #[serde(default)]
+    pub offset: i8,
+    /// The time of day that the offset applies during, like `06:00-22:00`. If there isn't one,
+    /// the offset applies all day.
+    pub during: Option<String>,
+    /// How much later the stages change for these areas, like `02:00` for two hours later.
+    pub delay: Option<String>,
+}
+
+impl TryFrom<RawStageRule> for StageRule {
+    type Error = BoxedError;
+
+    fn try_from(raw: RawStageRule) -> Result<Self, Self::Error> {
+        let include_str = raw
+            .include_regex
+            .clone()
+            .or_else(|| raw.include.as_deref().and_then(shorthand_to_regex))
+            .ok_or_else(|| {
+                format!("Stage rule must have a known include or include_regex: {raw:?}")
+            })?;
+        let include_regex = Regex::new(&include_str)
+            .map_err(|e| format!("Invalid include_regex in stage rule {raw:?}: {e}"))?;
+        let parse_time = |time: &str| {
+            NaiveTime::parse_from_str(time.trim(), "%H:%M")
+                .map_err(|_| format!("Couldn't parse time {time:?} in stage rule {raw:?}"))
+        };
+        let during = match raw.during.as_deref() {
+            Some(during) => {
+                let (start, finsh) = during
+                    .split_once('-')
+                    .ok_or_else(|| format!("`during` must be like 06:00-22:00 in {raw:?}"))?;
+                Some((parse_time(start)?, parse_time(finsh)?))
+            }
+            None => None,
+        };
+        let delay = match raw.delay.as_deref() {
+            Some(delay) => parse_time(delay)? - NaiveTime::MIN,
+            None => Duration::zero(),
+        };
+        Ok(StageRule {
+            include_regex,
+            offset: raw.offset,
+            during,
+            delay,
+        })
+    }
+}
+
+/// A period when loadshedding is suspended in some areas, like during elections or matric exams.
+/// Outages during an exemption are removed (or clipped, if they only partly overlap it), and the
+/// affected calendars get an all-day event explaining why.
 #[derive(Debug, Clone)]
-pub struct Change {
-    /// The time when LoadShedding *should* start
+pub struct Exemption {
+    /// When the suspension of loadshedding starts
     pub start: DateTime<FixedOffset>,
-    /// The time when LoadShedding *should* end
+    /// When the suspension of loadshedding ends
     pub finsh: DateTime<FixedOffset>,
-    /// The stage of loadshedding
-    pub stage: u8,
-    /// The source of information for this loadshedding event
+    /// The source of information for this exemption
     pub source: String,
+    /// Why loadshedding is suspended, like `General elections`
+    pub reason: Option<String>,
+    /// The areas which this exemption applies to
+    pub area_filter: AreaFilter,
+}
+
+impl Exemption {
+    /// The parts of `outage` which aren't during this exemption. That's the whole outage if they
+    /// don't overlap, nothing if the exemption covers all of it, and two outages if the exemption
+    /// is in the middle of it.
+    pub fn clip(&self, outage: PowerOutage) -> Vec<PowerOutage> {
+        if outage.finsh <= self.start || self.finsh <= outage.start {
+            return vec![outage];
+        }
+        let before = PowerOutage {
+            finsh: self.start,
+            ..outage.clone()
+        };
+        let after = PowerOutage {
+            start: self.finsh,
+            ..outage
+        };
+        [before, after]
+            .into_iter()
+            .filter(|o| o.start < o.finsh)
+            .collect()
+    }
+}
+
+/// A period when loadshedding is suspended, as written in `manually_specified.yaml`. The areas
+/// are chosen in the same way as for a `RawChange`. For example:
+///
+/// ```yaml
+/// exemptions:
+/// - start: 2024-05-29T00:00:00
+///   finsh: 2024-05-30T00:00:00
+///   reason: General elections
+///   source: https://example.com
+/// ```
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RawExemption {
+    /// When the suspension of loadshedding starts
+    pub start: String,
+    /// When the suspension of loadshedding ends
+    pub finsh: String,
+    /// The source of information for this exemption
+    pub source: String,
+    /// Why loadshedding is suspended, like `General elections`
+    pub reason: Option<String>,
+    /// See `RawChange::include_regex`
+    pub include_regex: Option<String>,
+    /// See `RawChange::exclude_regex`
+    pub exclude_regex: Option<String>,
+    /// See `RawChange::include`
+    pub include: Option<String>,
+    /// See `RawChange::exclude`
+    pub exclude: Option<String>,
+    /// Structured selectors for the areas, like `municipality: city-of-tshwane`
+    #[serde(flatten)]
+    pub selector: AreaSelector,
+}
+
+impl TryFrom<RawExemption> for Exemption {
+    type Error = BoxedError;
+
+    fn try_from(raw: RawExemption) -> Result<Self, Self::Error> {
+        let area_filter = AreaFilter::new(
+            &raw.include_regex,
+            &raw.include,
+            &raw.exclude_regex,
+            &raw.exclude,
+            raw.selector.clone(),
+        );
+        let parse = |time: &str| {
+            DateTime::parse_from_rfc3339(&format!("{time}+02:00"))
+                .map_err(|_| format!("Failed to parse time {time:?} in exemption {raw:?}"))
+        };
+        let (start, finsh) = (parse(&raw.start)?, parse(&raw.finsh)?);
+        if start >= finsh {
+            return Err(format!("Exemption must start before it ends: {raw:?}").into());
+        }
+        Ok(Exemption {
+            start,
+            finsh,
+            source: raw.source,
+            reason: raw.reason,
+            area_filter,
+        })
+    }
+}
+
+/// The areas which a change or exemption applies to. An area must match the include regex, not
+/// match the exclude regex and be one of the areas chosen by the selectors (if there are any).
+#[derive(Debug, Clone)]
+pub struct AreaFilter {
     /// Optionally specify a rust-regex pattern which the area name must match in order for this
     /// shedding to be applied to it. For example, `include_regex: city-of-cape-town-area-\d{1,2}`
     /// will include all city of cape town areas. If `include_regex` and `exclude_regex` conflict
@@ -177,6 +895,175 @@ pub struct Change {
     /// with each other, the area *will* be included. If no include/exclude are specified,
     /// `include_regex: .*` is used by default (so the loadshedding is applied to all areas.
     pub exclude_regex: Regex,
+    /// Structured selectors for the areas, as written in `manually_specified.yaml`
+    pub selector: AreaSelector,
+    /// The areas chosen by `selector`, once it's been resolved with `area_metadata.yaml` by
+    /// `ManuallyInputSchedule::resolve_selectors`. `None` if there aren't any selectors.
+    pub areas: Option<BTreeSet<String>>,
+}
+
+impl AreaFilter {
+    /// The filter for the regexes, shorthands and selectors of a change or exemption. This will
+    /// first try to use the explicit regex. If there is no explicit regex, then try to parse the
+    /// shorthand. If there is no shorthand or if the shorthand is unknown, then use the explicit
+    /// ".*" match everything regex (or the match nothing regex, for excludes).
+    fn new(
+        include_regex: &Option<String>,
+        include: &Option<String>,
+        exclude_regex: &Option<String>,
+        exclude: &Option<String>,
+        selector: AreaSelector,
+    ) -> AreaFilter {
+        let regex_or = |regex: &Option<String>, shorthand: &Option<String>, default: &str| {
+            let regex_str = regex.clone().unwrap_or_else(|| {
+                shorthand
+                    .as_deref()
+                    .and_then(shorthand_to_regex)
+                    .unwrap_or(default.to_string())
+            });
+            Regex::new(&regex_str).unwrap_or_else(|_| Regex::new(r".*").unwrap())
+        };
+        AreaFilter {
+            include_regex: regex_or(include_regex, include, r".*"),
+            exclude_regex: regex_or(exclude_regex, exclude, r"matchnothing^"),
+            selector,
+            areas: None,
+        }
+    }
+
+    /// Whether `area_name` is one of the areas chosen by this filter.
+    pub fn applies_to(&self, area_name: &str) -> bool {
+        !self.exclude_regex.is_match(area_name)
+            && self.include_regex.is_match(area_name)
+            && self
+                .areas
+                .as_ref()
+                .is_none_or(|areas| areas.contains(area_name))
+    }
+
+    /// Resolve the selectors with `metadata`, if there are any.
+    fn resolve_selectors(&mut self, metadata: &AreaMetadata) -> Result<(), BoxedError> {
+        if !self.selector.is_empty() {
+            self.areas = Some(self.selector.resolve(metadata)?);
+        }
+        Ok(())
+    }
+}
+
+impl PartialEq for AreaFilter {
+    fn eq(&self, other: &Self) -> bool {
+        self.include_regex.as_str() == other.include_regex.as_str()
+            && self.exclude_regex.as_str() == other.exclude_regex.as_str()
+            && self.areas == other.areas
+    }
+}
+
+/// The regex for a shorthand like `coct`, so you don't have to write out the full regex.
+fn shorthand_to_regex(shorthand: &str) -> Option<String> {
+    match shorthand.to_lowercase().as_str() {
+        "citypower" | "cp" => Some(r"city-power-\d{1,2}".to_string()),
+        "capetown" | "cpt" | "coct" => Some(r"city-of-cape-town(-area-\d{1,2})?".to_string()),
+        "ekurhuleni" => Some(r"gauteng-ekurhuleni-block-\d{1,2}".to_string()),
+        "eskom" => Some(r"^(eskom)|(eastern-cape-)|(free-state-)|(kwazulu-natal-)|(limpopo-)|(mpumalanga-)|(north-west-)|(northern-cape-)|(western-cape-)".to_string()),
+        "tshwane" => Some(r"gauteng-tshwane-group-\d{1,2}".to_string()),
+        _ => None,
+    }
+}
+
+/// A single duration of loadshedding that only has one stage.
+#[derive(Debug, Clone)]
+pub struct Change {
+    /// The time when LoadShedding *should* start
+    pub start: DateTime<FixedOffset>,
+    /// The time when LoadShedding *should* end
+    pub finsh: DateTime<FixedOffset>,
+    /// The stage of loadshedding
+    pub stage: u8,
+    /// The source of information for this loadshedding event
+    pub source: String,
+    /// The areas which this change applies to
+    pub area_filter: AreaFilter,
+    /// Whether this is a national change, which doesn't have an `include`, `include_regex` or
+    /// any selectors. Stage rules are only applied to national changes.
+    pub national: bool,
+    /// Whether the stage continues after `finsh` until further notice, in which case the change
+    /// is projected past `finsh` by `ManuallyInputSchedule::project_until`
+    pub until_further_notice: bool,
+    /// Whether this change was projected from a change which continues until further notice,
+    /// instead of being announced
+    pub tentative: bool,
+}
+
+/// Structured ways for a change to choose its areas, as an alternative to (or combined with)
+/// regexes over the area names. They're resolved with `area_metadata.yaml`, and every selector
+/// which is given must match for an area to be chosen. For example:
+///
+/// ```yaml
+/// - stage: 2
+///   start: 2023-01-01T16:00:00
+///   finsh: 2023-01-01T22:00:00
+///   source: https://example.com
+///   province: western-cape
+///   provider: eskom
+/// ```
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct AreaSelector {
+    /// Only areas in this province, like `western-cape`
+    pub province: Option<String>,
+    /// Only areas in this municipality, like `stellenbosch`
+    pub municipality: Option<String>,
+    /// Only areas whose schedule comes from this provider, like `eskom`
+    pub provider: Option<String>,
+    /// Only these areas, like `[western-cape-stellenbosch, city-of-cape-town-area-1]`
+    pub areas: Option<Vec<String>>,
+}
+
+impl AreaSelector {
+    /// Whether there aren't any selectors, so every area is chosen.
+    pub fn is_empty(&self) -> bool {
+        self == &AreaSelector::default()
+    }
+
+    /// The names of the areas in `metadata` which are chosen by every given selector. It's an
+    /// error if no areas are chosen, or if an explicitly listed area isn't in `metadata`, since
+    /// that's almost certainly a typo.
+    pub fn resolve(&self, metadata: &AreaMetadata) -> Result<BTreeSet<String>, BoxedError> {
+        let unknown = self
+            .areas
+            .iter()
+            .flatten()
+            .filter(|area| {
+                !metadata
+                    .area_details
+                    .iter()
+                    .any(|detail| detail.area_name() == area.as_str())
+            })
+            .collect::<Vec<_>>();
+        if !unknown.is_empty() {
+            return Err(format!("The areas {unknown:?} aren't in area_metadata.yaml").into());
+        }
+        let matches = |wanted: &Option<String>, actual: &[String]| {
+            wanted.as_ref().is_none_or(|wanted| actual.contains(wanted))
+        };
+        let resolved = metadata
+            .area_details
+            .iter()
+            .filter(|detail| {
+                matches(&self.province, &detail.provinces())
+                    && matches(&self.municipality, &detail.municipalities())
+                    && matches(&self.provider, detail.provider.as_slice())
+                    && self
+                        .areas
+                        .as_ref()
+                        .is_none_or(|areas| areas.iter().any(|area| area == detail.area_name()))
+            })
+            .map(|detail| detail.area_name().to_owned())
+            .collect::<BTreeSet<_>>();
+        if resolved.is_empty() {
+            return Err(format!("The selectors {self:?} don't choose any areas").into());
+        }
+        Ok(resolved)
+    }
 }
 
 /// A single duration of loadshedding that only has one stage.
@@ -211,62 +1098,44 @@ pub struct RawChange {
     /// `exclude_regex: city-of-cape-town-area-\d{1,2}`. If no include/exclude are specified,
     /// `include_regex: .*` is used by default (so the loadshedding is applied to all areas.
     pub exclude: Option<String>,
+    /// Structured selectors for the areas, like `province: western-cape`. See `AreaSelector`.
+    #[serde(flatten)]
+    pub selector: AreaSelector,
+    /// Whether the stage continues after `finsh` until further notice. If it does, tentative
+    /// outages are projected after `finsh` until the next change (or the configured horizon).
+    #[serde(default)]
+    pub until_further_notice: bool,
 }
 
-impl From<RawChange> for Change {
-    fn from(raw: RawChange) -> Self {
-        let shorthand_to_regex = |shorthand: String, default: String| {
-            match shorthand.to_lowercase().as_str() {
-            "citypower" | "cp" => r"city-power-\d{1,2}".to_string(),
-            "capetown" | "cpt" | "coct" => r"city-of-cape-town(-area-\d{1,2})?".to_string(),
-            "ekurhuleni" => r"gauteng-ekurhuleni-block-\d{1,2}".to_string(),
-            "eskom" => r"^(eskom)|(eastern-cape-)|(free-state-)|(kwazulu-natal-)|(limpopo-)|(mpumalanga-)|(north-west-)|(northern-cape-)|(western-cape-)".to_string(),
-            "tshwane" => r"gauteng-tshwane-group-\d{1,2}".to_string(),
-            _ => default,
-        }
-        };
-        // This will first try to use the explicit regex. If there is no explicit regex, then try
-        // to parse the shorthand. If there is no shorthand or if the shorthand is unknown, then
-        // use the explicit ".*" match everything regex
-        let include_str = raw.include_regex.clone().unwrap_or_else(|| {
-            raw.include.clone().map_or(r".*".to_string(), |shorthand| {
-                shorthand_to_regex(shorthand, r".*".to_string())
-            })
-        });
-        let include_regex = Regex::new(&include_str).unwrap_or_else(|_| Regex::new(r".*").unwrap());
-
-        let exclude_str = raw.exclude_regex.clone().unwrap_or_else(|| {
-            raw.exclude
-                .clone()
-                .map_or(r"matchnothing^".to_string(), |shorthand| {
-                    shorthand_to_regex(shorthand, r"matchnothing^".to_string())
-                })
-        });
-        let exclude_regex = Regex::new(&exclude_str).unwrap_or_else(|_| Regex::new(r".*").unwrap());
+impl TryFrom<RawChange> for Change {
+    type Error = BoxedError;
+
+    fn try_from(raw: RawChange) -> Result<Self, Self::Error> {
+        let national =
+            raw.include.is_none() && raw.include_regex.is_none() && raw.selector.is_empty();
+        let area_filter = AreaFilter::new(
+            &raw.include_regex,
+            &raw.include,
+            &raw.exclude_regex,
+            &raw.exclude,
+            raw.selector.clone(),
+        );
 
         // TODO remove this string parsing nonsense
-        Change {
-            start: DateTime::parse_from_rfc3339(&format!("{}+02:00", raw.start)).unwrap_or_else(
-                |_| {
-                    panic!(
-                        "Failed to parse start time 1970-01-01T{}:00+02:00 as RFC3339, {raw:?}",
-                        raw.start
-                    )
-                },
-            ),
-            finsh: DateTime::parse_from_rfc3339(&format!("{}+02:00", raw.finsh)).unwrap_or_else(
-                |_| {
-                    panic!(
-                        "Failed to parse finsh time 1970-01-01T{}:00+02:00 as RFC3339, {raw:?}",
-                        raw.finsh
-                    )
-                },
-            ),
+        let parse = |time: &str| {
+            DateTime::parse_from_rfc3339(&format!("{time}+02:00"))
+                .map_err(|_| format!("Failed to parse time {time:?} as RFC3339 in change {raw:?}"))
+        };
+        Ok(Change {
+            start: parse(&raw.start)?,
+            finsh: parse(&raw.finsh)?,
             stage: raw.stage,
             source: raw.source,
-            exclude_regex,
-            include_regex,
-        }
+            area_filter,
+            national,
+            until_further_notice: raw.until_further_notice,
+            tentative: false,
+        })
     }
 }
 
@@ -276,42 +1145,174 @@ impl PartialEq for Change {
             && self.finsh == other.finsh
             && self.stage == other.stage
             && self.source == other.source
-            && self.include_regex.clone().as_str() == other.include_regex.clone().as_str()
-            && self.exclude_regex.clone().as_str() == other.exclude_regex.clone().as_str()
+            && self.area_filter == other.area_filter
+            && self.until_further_notice == other.until_further_notice
+            && self.tentative == other.tentative
     }
 }
 
-/// A loadshedding event that repeats on the same day every month, parsed into datetimes.
-/// Contains the date of the month, the start time (but the date is always 1 Jan 1970), the end
-/// time (but the date is always 1 Jan 1970 or 2 Jan 1970), a boolean to indicate if the start time and the end
-/// time imply the loadshedding goes over midnight (ie from 22:00 to 00:30) and the stage of the
-/// loadshedding.
+/// A loadshedding event that recurs according to some `Recurrence`. Contains the time of day it
+/// starts, how long it lasts, the stage of the loadshedding and the days it happens on.
 #[derive(Debug, Clone, PartialEq)]
 pub struct RecurringShedding {
-    /// The time when LoadShedding *should* start. The date of this member will always be 1 Jan
-    /// 1970. TODO Convert this to a `chrono::NaiveTime`
-    pub start_time: DateTime<FixedOffset>,
-    /// The time when LoadShedding *should* finish (note the spelling). The date of this member
-    /// will always be 1 Jan 1970, unless the loadshedding is from 22h00 to 00h30 in which case the
-    /// finish date will be 2 Jan 1970. TODO Convert this to a `chrono::NaiveTime`
-    pub finsh_time: DateTime<FixedOffset>,
+    /// The time of day when LoadShedding *should* start (timezone is always UTC+2).
+    pub start_time: NaiveTime,
+    /// How long the LoadShedding *should* last. If the start time plus the duration is after
+    /// midnight, then the LoadShedding finishes on a later day (like from 22h00 to 00h30). This
+    /// can be longer than a day.
+    pub duration: Duration,
     /// The stage of loadshedding.
     pub stage: u8,
-    /// How frequently this recurring loadshedding schedule occurs
+    /// The days that this recurring loadshedding schedule occurs on
     pub recurrence: Recurrence,
-    /// The day of this particular schedule, which is 1-indexed (the first day is 1, the second is
-    /// 2, etc)
-    pub day_of_recurrence: u8,
 }
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+impl RecurringShedding {
+    /// The time of day when LoadShedding *should* finish (note the spelling), which isn't
+    /// necessarily on the same day as it started.
+    pub fn finsh_time(&self) -> NaiveTime {
+        self.start_time + self.duration
+    }
+
+    /// The start and finish times formatted like they're written in a schedule, so that
+    /// `parse_start_and_duration` would give back the same start time and duration.
+    pub fn format_times(&self) -> (String, String) {
+        let format = |time: NaiveTime| {
+            if time.second() == 0 {
+                time.format("%H:%M").to_string()
+            } else {
+                time.format("%H:%M:%S").to_string()
+            }
+        };
+        let (start, finsh) = (self.start_time, self.finsh_time());
+        // The number of midnights between the start and the finish, which only needs to be written
+        // if it isn't implied by the finish being before the start
+        let days = (self.start_time - NaiveTime::MIN + self.duration).num_days();
+        let implied_days = i64::from(finsh < start);
+        if days == implied_days {
+            (format(start), format(finsh))
+        } else {
+            (format(start), format!("{}+{days}", format(finsh)))
+        }
+    }
+}
+
+/// Parse a time of day like `02:30` or `02:30:15`.
+fn parse_time(time: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M:%S"))
+        .ok()
+}
+
+/// Parse the start and finish times of a schedule's row into a start time and a duration. The
+/// finish is on the same day as the start, unless it's before the start (like from 22:00 to
+/// 00:30) in which case it's on the next day. A finish like `06:00+2` is explicitly that many
+/// days after the start, for outages which last longer than a day.
+pub fn parse_start_and_duration(
+    start: &str,
+    finsh: &str,
+) -> Result<(NaiveTime, Duration), BoxedError> {
+    let start_t = parse_time(start).ok_or_else(|| format!("Couldn't parse start time: {start}"))?;
+    let (finsh_t, days) = match finsh.split_once('+') {
+        Some((time, days)) => (
+            parse_time(time),
+            days.parse::<u16>()
+                .map_err(|_| format!("Couldn't parse days after {finsh}"))?
+                .into(),
+        ),
+        None => (parse_time(finsh), None),
+    };
+    let finsh_t = finsh_t.ok_or_else(|| format!("Couldn't parse finsh time: {finsh}"))?;
+    let days = days.unwrap_or(if finsh_t < start_t { 1 } else { 0 });
+    let duration = finsh_t - start_t + Duration::days(days.into());
+    if duration < Duration::zero() {
+        return Err(
+            format!("Loadshedding from {start} to {finsh} finishes before it starts").into(),
+        );
+    }
+    Ok((start_t, duration))
+}
+
+/// The days that a `RecurringShedding` occurs on.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
 pub enum Recurrence {
     /// A weekly recurrence. The week starts on Monday as day 1, and ends on Sunday as day 7.
-    Weekly,
-    /// A monthly recurrence.
-    Monthly,
-    /// A recurrence with an arbitrary period measured in days, and an offset specified by `start_dt`.
-    Periodic { offset: NaiveDate, period: u8 },
+    Weekly { day_of_week: u8 },
+    /// A monthly recurrence, on the given date of every month.
+    Monthly { date_of_month: u8 },
+    /// A recurrence with an arbitrary period measured in days.
+    Periodic(Cycle),
+    /// No recurrence at all: the loadshedding only happens on `date`. This is for schedules which
+    /// are published as explicit calendars a few months at a time, like Nelson Mandela Bay's.
+    Dated { date: NaiveDate },
+    /// A recurrence described by an RFC 5545 recurrence rule, for anything the other recurrences
+    /// can't describe (like "every second Tuesday").
+    Rule(RRule),
+}
+
+impl Recurrence {
+    /// Whether this recurrence occurs on `date`.
+    pub fn occurs_on(&self, date: NaiveDate) -> bool {
+        match self {
+            Recurrence::Weekly { day_of_week } => {
+                date.weekday().number_from_monday() == u32::from(*day_of_week)
+            }
+            Recurrence::Monthly { date_of_month } => date.day() == u32::from(*date_of_month),
+            Recurrence::Periodic(cycle) => cycle.occurs_on(date),
+            Recurrence::Dated { date: dated } => date == *dated,
+            Recurrence::Rule(rule) => rule.occurs_on(date),
+        }
+    }
+}
+
+/// One day of a cycle which is `period` days long, where the cycle starts with day 1 on `start`.
+/// The fields are private so that a `Cycle` can only be made by `Cycle::new`, which makes sure
+/// the day is actually in the cycle.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct Cycle {
+    start: NaiveDate,
+    period: u16,
+    day: u16,
+}
+
+impl Cycle {
+    /// Day `day` of a `period`-day cycle, where day 1 is `start`. Fails unless
+    /// `1 <= day <= period`.
+    pub fn new(start: NaiveDate, period: u16, day: u16) -> Result<Cycle, BoxedError> {
+        if day == 0 || day > period {
+            return Err(format!(
+                "Day of the cycle {day} must be in the range [1, {period}] (the period of the cycle)"
+            )
+            .into());
+        }
+        Ok(Cycle { start, period, day })
+    }
+
+    /// The date of the start of the cycle, which is day 1.
+    pub fn start(&self) -> NaiveDate {
+        self.start
+    }
+
+    /// The number of days in the cycle.
+    pub fn period(&self) -> u16 {
+        self.period
+    }
+
+    /// The day of the cycle, which is 1-indexed.
+    pub fn day(&self) -> u16 {
+        self.day
+    }
+
+    /// Whether `date` is this day of the cycle.
+    pub fn occurs_on(&self, date: NaiveDate) -> bool {
+        // The start of the cycle can be after `date`, so use the Euclidean remainder to always get
+        // a day in [0, period)
+        let cycle_day = (date - self.start)
+            .num_days()
+            .rem_euclid(i64::from(self.period));
+        // The cycle day is actually one-indexed, so add 1 to compensate for this.
+        cycle_day + 1 == i64::from(self.day)
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -323,51 +1324,32 @@ pub struct RawPeriodicShedding {
     /// The stage of loadshedding.
     pub stage: u8,
     /// The day of the cycle, with the first day being 1, the second day being 2, etc
-    pub day_of_cycle: u8,
+    pub day_of_cycle: u16,
     /// The number of days in the cycle. A cycle with 42 days will have the last day being 42 and
     /// the first day being 1
-    pub period_of_cycle: u8,
+    pub period_of_cycle: u16,
     /// The date of the "start" of the cycle, this date will have cycle number 1, the following day
     /// will have cycle number 2, the previous day will have cycle number `period_of_cycle` In
     /// engineering terms this would be the offset of the cycle.
     pub start_of_cycle: String,
 }
 
-// TODO this needs to be tested
-impl From<RawPeriodicShedding> for RecurringShedding {
-    fn from(raw: RawPeriodicShedding) -> Self {
-        assert!(
-            raw.day_of_cycle <= raw.period_of_cycle,
-            "Day of the cycle {} must be <= period of the cycle {}",
-            raw.day_of_cycle,
-            raw.period_of_cycle
-        );
-        let timezone_sast = FixedOffset::east_opt(2 * 60 * 60).unwrap();
-
-        let start_t = NaiveTime::parse_from_str(&raw.start_time, "%H:%M").unwrap();
-        let finsh_t = NaiveTime::parse_from_str(&raw.finsh_time, "%H:%M").unwrap();
+impl TryFrom<RawPeriodicShedding> for RecurringShedding {
+    type Error = BoxedError;
 
-        // TODO this should be a NaiveTime
-        let start_datetime = NaiveDate::from_ymd_opt(1970, 1, 1)
-            .unwrap()
-            .and_time(start_t);
-        // TODO this should be a NaiveTime
-        let finsh_datetime = NaiveDate::from_ymd_opt(1970, 1, 1)
-            .unwrap()
-            .and_time(finsh_t);
+    fn try_from(raw: RawPeriodicShedding) -> Result<Self, Self::Error> {
+        let (start_time, duration) = parse_start_and_duration(&raw.start_time, &raw.finsh_time)?;
 
-        let offset = NaiveDate::parse_from_str(&raw.start_of_cycle, "%Y-%m-%d").unwrap();
+        let offset = NaiveDate::parse_from_str(&raw.start_of_cycle, "%Y-%m-%d")
+            .map_err(|_| format!("Couldn't parse start of cycle: {}", raw.start_of_cycle))?;
+        let cycle = Cycle::new(offset, raw.period_of_cycle, raw.day_of_cycle)?;
 
-        RecurringShedding {
-            start_time: DateTime::<FixedOffset>::from_local(start_datetime, timezone_sast),
-            finsh_time: DateTime::<FixedOffset>::from_local(finsh_datetime, timezone_sast),
+        Ok(RecurringShedding {
+            start_time,
+            duration,
             stage: raw.stage,
-            recurrence: Recurrence::Periodic {
-                offset,
-                period: raw.period_of_cycle,
-            },
-            day_of_recurrence: raw.day_of_cycle,
-        }
+            recurrence: Recurrence::Periodic(cycle),
+        })
     }
 }
 
@@ -383,31 +1365,27 @@ pub struct RawWeeklyShedding {
     pub day_of_week: u8,
 }
 
-impl From<RawWeeklyShedding> for RecurringShedding {
-    fn from(raw: RawWeeklyShedding) -> Self {
-        assert!(
-            0 < raw.day_of_week && raw.day_of_week < 8,
-            "Day of the week must be one of 1, 2, 3, 4, 5, 6, 7"
-        );
-        let timezone_sast = FixedOffset::east_opt(2 * 60 * 60).unwrap();
-
-        let start_t = NaiveTime::parse_from_str(&raw.start_time, "%H:%M").unwrap();
-        let finsh_t = NaiveTime::parse_from_str(&raw.finsh_time, "%H:%M").unwrap();
+impl TryFrom<RawWeeklyShedding> for RecurringShedding {
+    type Error = BoxedError;
 
-        let start_datetime = NaiveDate::from_ymd_opt(1970, 1, 1)
-            .unwrap()
-            .and_time(start_t);
-        let finsh_datetime = NaiveDate::from_ymd_opt(1970, 1, 1)
-            .unwrap()
-            .and_time(finsh_t);
+    fn try_from(raw: RawWeeklyShedding) -> Result<Self, Self::Error> {
+        if !(1..=7).contains(&raw.day_of_week) {
+            return Err(format!(
+                "Day of the week must be one of 1, 2, 3, 4, 5, 6, 7, not {}",
+                raw.day_of_week
+            )
+            .into());
+        }
+        let (start_time, duration) = parse_start_and_duration(&raw.start_time, &raw.finsh_time)?;
 
-        RecurringShedding {
-            start_time: DateTime::<FixedOffset>::from_local(start_datetime, timezone_sast),
-            finsh_time: DateTime::<FixedOffset>::from_local(finsh_datetime, timezone_sast),
+        Ok(RecurringShedding {
+            start_time,
+            duration,
             stage: raw.stage,
-            recurrence: Recurrence::Weekly,
-            day_of_recurrence: raw.day_of_week,
-        }
+            recurrence: Recurrence::Weekly {
+                day_of_week: raw.day_of_week,
+            },
+        })
     }
 }
 
@@ -425,54 +1403,200 @@ pub struct RawMonthlyShedding {
     pub date_of_month: u8,
 }
 
-impl From<RawMonthlyShedding> for RecurringShedding {
-    fn from(raw: RawMonthlyShedding) -> Self {
-        assert!(
-            0 < raw.date_of_month && raw.date_of_month <= 31,
-            "Date of month must be in the range (0, 31]"
-        );
-        let timezone_sast = FixedOffset::east_opt(2 * 60 * 60).unwrap();
+impl TryFrom<RawMonthlyShedding> for RecurringShedding {
+    type Error = BoxedError;
 
-        // We panic here because if the datetimes are poorly formatted, there's no way to recover.
-        let start_t = NaiveTime::parse_from_str(&raw.start_time, "%H:%M")
-            .unwrap_or_else(|_| panic!("Couldn't parse start time: {}", raw.start_time));
-        let finsh_t = NaiveTime::parse_from_str(&raw.finsh_time, "%H:%M")
-            .unwrap_or_else(|_| panic!("Couldn't parse finsh time: {}", raw.finsh_time));
+    fn try_from(raw: RawMonthlyShedding) -> Result<Self, Self::Error> {
+        if !(1..=31).contains(&raw.date_of_month) {
+            return Err(format!(
+                "Date of month must be in the range (0, 31], not {}",
+                raw.date_of_month
+            )
+            .into());
+        }
+        let (start_time, duration) = parse_start_and_duration(&raw.start_time, &raw.finsh_time)?;
 
-        let start_datetime = NaiveDate::from_ymd_opt(1970, 1, 1)
-            .unwrap()
-            .and_time(start_t);
-        let finsh_datetime = NaiveDate::from_ymd_opt(1970, 1, 1)
-            .unwrap()
-            .and_time(finsh_t);
+        Ok(RecurringShedding {
+            start_time,
+            duration,
+            stage: raw.stage,
+            recurrence: Recurrence::Monthly {
+                date_of_month: raw.date_of_month,
+            },
+        })
+    }
+}
+
+/// A loadshedding event that happens on one specific date, not yet parsed.
+#[derive(Deserialize, Debug)]
+pub struct RawDatedShedding {
+    /// The date of the loadshedding, formatted as `YYYY-MM-DD`.
+    pub date: String,
+    /// The time when LoadShedding *should* start.
+    pub start_time: String,
+    /// The time when LoadShedding *should* finish (note the spelling).
+    pub finsh_time: String,
+    /// The stage of loadshedding.
+    pub stage: u8,
+}
 
-        RecurringShedding {
-            start_time: DateTime::<FixedOffset>::from_local(start_datetime, timezone_sast),
-            finsh_time: DateTime::<FixedOffset>::from_local(finsh_datetime, timezone_sast),
+impl TryFrom<RawDatedShedding> for RecurringShedding {
+    type Error = BoxedError;
+
+    fn try_from(raw: RawDatedShedding) -> Result<Self, Self::Error> {
+        let (start_time, duration) = parse_start_and_duration(&raw.start_time, &raw.finsh_time)?;
+
+        let date = NaiveDate::parse_from_str(&raw.date, "%Y-%m-%d")
+            .map_err(|_| format!("Couldn't parse date: {}", raw.date))?;
+
+        Ok(RecurringShedding {
+            start_time,
+            duration,
             stage: raw.stage,
-            recurrence: Recurrence::Monthly,
-            day_of_recurrence: raw.date_of_month,
-        }
+            recurrence: Recurrence::Dated { date },
+        })
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use chrono::{DateTime, FixedOffset};
+/// A loadshedding event that recurs according to an RFC 5545 recurrence rule, not yet parsed. See
+/// `RRule` for the parts of the rules which are supported.
+#[derive(Deserialize, Debug)]
+pub struct RawRuleShedding {
+    /// The recurrence rule, like `FREQ=WEEKLY;INTERVAL=2;BYDAY=TU`
+    pub rrule: String,
+    /// The date that the rule starts on, which its intervals are counted from (`DTSTART` in RFC
+    /// 5545), formatted as `YYYY-MM-DD`
+    pub start_of_rule: String,
+    /// The time when LoadShedding *should* start.
+    pub start_time: String,
+    /// The time when LoadShedding *should* finish (note the spelling).
+    pub finsh_time: String,
+    /// The stage of loadshedding.
+    pub stage: u8,
+}
+
+impl TryFrom<RawRuleShedding> for RecurringShedding {
+    type Error = BoxedError;
+
+    fn try_from(raw: RawRuleShedding) -> Result<Self, Self::Error> {
+        let (start_time, duration) = parse_start_and_duration(&raw.start_time, &raw.finsh_time)?;
+
+        let start_of_rule = NaiveDate::parse_from_str(&raw.start_of_rule, "%Y-%m-%d")
+            .map_err(|_| format!("Couldn't parse start of rule: {}", raw.start_of_rule))?;
+        let rule = RRule::new(&raw.rrule, start_of_rule)?;
+
+        Ok(RecurringShedding {
+            start_time,
+            duration,
+            stage: raw.stage,
+            recurrence: Recurrence::Rule(rule),
+        })
+    }
+}
+
+/// The contents of `area_metadata.yaml`, which maps every calendar to the provinces,
+/// municipalities and suburbs that it covers.
+#[derive(Deserialize, Debug, Default)]
+pub struct AreaMetadata {
+    pub area_details: Vec<AreaDetail>,
+}
+
+/// The metadata for one calendar, as listed in `area_metadata.yaml`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct AreaDetail {
+    /// The name of the calendar file, for example `western-cape-stellenbosch.ics`
+    pub calendar_name: String,
+    /// The province this calendar falls under, if the calendar only covers one province
+    pub province: Option<String>,
+    /// The municipality this calendar falls under, if the calendar only covers one municipality
+    pub municipality: Option<String>,
+    /// The organisation responsible for the loadshedding schedule, for example `eskom`
+    pub provider: Option<String>,
+    /// The suburbs covered by this calendar
+    pub areas: Vec<SuburbGroup>,
+}
+
+impl AreaDetail {
+    /// The calendar name without the `.ics` extension, which lines up with the `generated/` CSV
+    /// file names.
+    pub fn area_name(&self) -> &str {
+        self.calendar_name
+            .strip_suffix(".ics")
+            .unwrap_or(&self.calendar_name)
+    }
+
+    /// All the distinct provinces mentioned by this calendar, sorted alphabetically.
+    pub fn provinces(&self) -> Vec<String> {
+        Self::distinct(
+            self.province.iter().chain(
+                self.areas
+                    .iter()
+                    .filter_map(|group| group.province.as_ref()),
+            ),
+        )
+    }
 
-    fn rfc3339(s: &str) -> DateTime<FixedOffset> {
-        DateTime::parse_from_rfc3339(s).unwrap()
+    /// All the distinct municipalities mentioned by this calendar, sorted alphabetically.
+    pub fn municipalities(&self) -> Vec<String> {
+        Self::distinct(
+            self.municipality.iter().chain(
+                self.areas
+                    .iter()
+                    .filter_map(|group| group.municipality.as_ref()),
+            ),
+        )
+    }
+
+    /// All the suburb names covered by this calendar.
+    pub fn suburbs(&self) -> impl Iterator<Item = &String> {
+        self.areas.iter().flat_map(|group| group.name.iter())
+    }
+
+    fn distinct<'a>(items: impl Iterator<Item = &'a String>) -> Vec<String> {
+        let mut items = items.cloned().collect::<Vec<_>>();
+        items.sort();
+        items.dedup();
+        items
+    }
+}
+
+/// A group of suburbs in `area_metadata.yaml`. Eskom-direct calendars specify the province and
+/// municipality per group, while municipal calendars specify them once for the whole calendar.
+#[derive(Deserialize, Debug, Clone)]
+pub struct SuburbGroup {
+    pub province: Option<String>,
+    pub municipality: Option<String>,
+    pub name: SuburbNames,
+}
+
+/// Suburb names are either given as a single string (`{name: bellville}`) or as a list of strings
+/// (`name: ["aandenking", "alicedale"]`).
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum SuburbNames {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl SuburbNames {
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        match self {
+            SuburbNames::One(name) => std::slice::from_ref(name).iter(),
+            SuburbNames::Many(names) => names.iter(),
+        }
     }
+}
 
+#[cfg(test)]
+mod tests {
     mod raw_shedding_to_shedding {
-        use chrono::NaiveDate;
+        use chrono::{Duration, NaiveDate, NaiveTime};
 
         use crate::structs::{
-            tests::rfc3339, RawMonthlyShedding, RawPeriodicShedding, RawWeeklyShedding, Recurrence,
-            RecurringShedding,
+            Cycle, RawDatedShedding, RawMonthlyShedding, RawPeriodicShedding, RawRuleShedding,
+            RawWeeklyShedding, Recurrence, RecurringShedding,
         };
 
-        #[should_panic]
         #[test]
         fn test_periodic_too_high() {
             let raw_too_high = RawPeriodicShedding {
@@ -483,7 +1607,7 @@ mod tests {
                 period_of_cycle: 19,
                 start_of_cycle: "2023-01-01".to_owned(),
             };
-            let _ = Into::<RecurringShedding>::into(raw_too_high);
+            assert!(RecurringShedding::try_from(raw_too_high).is_err());
         }
 
         #[test]
@@ -497,19 +1621,16 @@ mod tests {
                 start_of_cycle: "2023-01-01".to_owned(),
             };
             let cooked = RecurringShedding {
-                start_time: rfc3339("1970-01-01T12:00:00+02:00"),
-                finsh_time: rfc3339("1970-01-01T14:30:00+02:00"),
+                start_time: NaiveTime::from_hms_opt(12, 0, 0).unwrap(),
+                duration: Duration::minutes(150),
                 stage: 1,
-                recurrence: Recurrence::Periodic {
-                    offset: NaiveDate::from_ymd_opt(2023, 1, 1).unwrap(),
-                    period: 19,
-                },
-                day_of_recurrence: 1,
+                recurrence: Recurrence::Periodic(
+                    Cycle::new(NaiveDate::from_ymd_opt(2023, 1, 1).unwrap(), 19, 1).unwrap(),
+                ),
             };
-            assert_eq!(Into::<RecurringShedding>::into(raw), cooked);
+            assert_eq!(RecurringShedding::try_from(raw).unwrap(), cooked);
         }
 
-        #[should_panic]
         #[test]
         fn test_weekly_oob_too_high() {
             let raw_too_high = RawWeeklyShedding {
@@ -518,10 +1639,9 @@ mod tests {
                 stage: 1,
                 day_of_week: 8,
             };
-            let _ = Into::<RecurringShedding>::into(raw_too_high);
+            assert!(RecurringShedding::try_from(raw_too_high).is_err());
         }
 
-        #[should_panic]
         #[test]
         fn test_weekly_oob_too_low() {
             let raw_too_low = RawWeeklyShedding {
@@ -530,7 +1650,7 @@ mod tests {
                 stage: 1,
                 day_of_week: 0,
             };
-            let _ = Into::<RecurringShedding>::into(raw_too_low);
+            assert!(RecurringShedding::try_from(raw_too_low).is_err());
         }
 
         #[test]
@@ -542,16 +1662,14 @@ mod tests {
                 day_of_week: 1,
             };
             let cooked = RecurringShedding {
-                start_time: rfc3339("1970-01-01T12:00:00+02:00"),
-                finsh_time: rfc3339("1970-01-01T14:30:00+02:00"),
+                start_time: NaiveTime::from_hms_opt(12, 0, 0).unwrap(),
+                duration: Duration::minutes(150),
                 stage: 1,
-                recurrence: Recurrence::Weekly,
-                day_of_recurrence: 1,
+                recurrence: Recurrence::Weekly { day_of_week: 1 },
             };
-            assert_eq!(Into::<RecurringShedding>::into(raw), cooked);
+            assert_eq!(RecurringShedding::try_from(raw).unwrap(), cooked);
         }
 
-        #[should_panic]
         #[test]
         fn test_monthly_oob_too_high() {
             let raw = RawMonthlyShedding {
@@ -560,7 +1678,7 @@ mod tests {
                 stage: 1,
                 date_of_month: 32,
             };
-            let _ = Into::<RecurringShedding>::into(raw);
+            assert!(RecurringShedding::try_from(raw).is_err());
         }
 
         #[test]
@@ -572,17 +1690,156 @@ mod tests {
                 date_of_month: 1,
             };
             let cooked = RecurringShedding {
-                start_time: rfc3339("1970-01-01T12:00:00+02:00"),
-                finsh_time: rfc3339("1970-01-01T14:30:00+02:00"),
+                start_time: NaiveTime::from_hms_opt(12, 0, 0).unwrap(),
+                duration: Duration::minutes(150),
+                stage: 1,
+                recurrence: Recurrence::Monthly { date_of_month: 1 },
+            };
+            assert_eq!(RecurringShedding::try_from(raw).unwrap(), cooked);
+        }
+
+        #[test]
+        fn test_dated_bad_date() {
+            let raw = RawDatedShedding {
+                date: "2023-02-30".to_owned(),
+                start_time: "12:00".to_owned(),
+                finsh_time: "14:30".to_owned(),
+                stage: 1,
+            };
+            assert!(RecurringShedding::try_from(raw).is_err());
+        }
+
+        #[test]
+        fn test_dated() {
+            let raw = RawDatedShedding {
+                date: "2023-09-15".to_owned(),
+                start_time: "22:00".to_owned(),
+                finsh_time: "00:30".to_owned(),
+                stage: 3,
+            };
+            let cooked = RecurringShedding {
+                start_time: NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+                duration: Duration::minutes(150),
+                stage: 3,
+                recurrence: Recurrence::Dated {
+                    date: NaiveDate::from_ymd_opt(2023, 9, 15).unwrap(),
+                },
+            };
+            assert_eq!(RecurringShedding::try_from(raw).unwrap(), cooked);
+        }
+
+        #[test]
+        fn test_rule_unsupported() {
+            let raw = RawRuleShedding {
+                rrule: "FREQ=YEARLY".to_owned(),
+                start_of_rule: "2023-09-01".to_owned(),
+                start_time: "12:00".to_owned(),
+                finsh_time: "14:30".to_owned(),
                 stage: 1,
-                recurrence: Recurrence::Monthly,
-                day_of_recurrence: 1,
             };
-            assert_eq!(Into::<RecurringShedding>::into(raw), cooked);
+            assert!(RecurringShedding::try_from(raw).is_err());
+        }
+
+        #[test]
+        fn test_rule() {
+            let raw = RawRuleShedding {
+                rrule: "FREQ=WEEKLY;INTERVAL=2;BYDAY=TU".to_owned(),
+                start_of_rule: "2023-09-01".to_owned(),
+                start_time: "12:00".to_owned(),
+                finsh_time: "14:30".to_owned(),
+                stage: 1,
+            };
+            let cooked = RecurringShedding::try_from(raw).unwrap();
+            assert_eq!(
+                cooked.start_time,
+                NaiveTime::from_hms_opt(12, 0, 0).unwrap()
+            );
+            assert!(cooked
+                .recurrence
+                .occurs_on(NaiveDate::from_ymd_opt(2023, 9, 12).unwrap()));
+            assert!(!cooked
+                .recurrence
+                .occurs_on(NaiveDate::from_ymd_opt(2023, 9, 19).unwrap()));
+        }
+    }
+    mod times {
+        use chrono::{Duration, NaiveTime};
+
+        use crate::structs::{parse_start_and_duration, RawMonthlyShedding, RecurringShedding};
+
+        fn parsed(start: &str, finsh: &str) -> (NaiveTime, Duration) {
+            parse_start_and_duration(start, finsh).unwrap()
+        }
+
+        #[test]
+        fn finsh_before_start_is_next_day() {
+            let ten_pm = NaiveTime::from_hms_opt(22, 0, 0).unwrap();
+            assert_eq!(parsed("22:00", "00:30"), (ten_pm, Duration::minutes(150)));
+            assert_eq!(parsed("22:00", "23:00"), (ten_pm, Duration::hours(1)));
+            assert_eq!(parsed("22:00", "22:00"), (ten_pm, Duration::zero()));
+        }
+
+        #[test]
+        fn longer_than_a_day_and_seconds() {
+            let midnight = NaiveTime::from_hms_opt(0, 0, 0).unwrap();
+            assert_eq!(parsed("00:00", "00:00+1"), (midnight, Duration::days(1)));
+            assert_eq!(parsed("00:00", "06:00+2"), (midnight, Duration::hours(54)));
+            assert_eq!(
+                parsed("00:00:30", "00:01:15"),
+                (
+                    NaiveTime::from_hms_opt(0, 0, 30).unwrap(),
+                    Duration::seconds(45)
+                )
+            );
+            assert!(parse_start_and_duration("22:00", "06:00+0").is_err());
+            assert!(parse_start_and_duration("22:00", "6am").is_err());
+        }
+
+        #[test]
+        fn formatted_like_they_were_written() {
+            for (start, finsh) in [
+                ("22:00", "00:30"),
+                ("00:00", "00:00+1"),
+                ("08:00", "06:00+3"),
+                ("00:00:30", "00:01:15"),
+            ] {
+                let shedding = RecurringShedding::try_from(RawMonthlyShedding {
+                    start_time: start.to_owned(),
+                    finsh_time: finsh.to_owned(),
+                    stage: 1,
+                    date_of_month: 1,
+                })
+                .unwrap();
+                assert_eq!(
+                    shedding.format_times(),
+                    (start.to_owned(), finsh.to_owned())
+                );
+            }
+        }
+    }
+    mod recurrence {
+        use chrono::NaiveDate;
+
+        use crate::structs::Cycle;
+
+        #[test]
+        fn cycle_day_must_be_in_cycle() {
+            let start = NaiveDate::from_ymd_opt(2023, 2, 18).unwrap();
+            assert!(Cycle::new(start, 1, 2).is_err());
+            assert!(Cycle::new(start, 19, 0).is_err());
+            assert!(Cycle::new(start, 300, 300).is_ok());
+        }
+
+        #[test]
+        fn cycle_can_start_in_the_future() {
+            let cycle = Cycle::new(NaiveDate::from_ymd_opt(2023, 2, 18).unwrap(), 5, 5).unwrap();
+            assert!(cycle.occurs_on(NaiveDate::from_ymd_opt(2023, 2, 17).unwrap()));
+            assert!(cycle.occurs_on(NaiveDate::from_ymd_opt(2023, 2, 22).unwrap()));
+            assert!(!cycle.occurs_on(NaiveDate::from_ymd_opt(2023, 2, 18).unwrap()));
         }
     }
     mod raw_change_to_change {
-        use crate::structs::{Change, RawChange};
+        use crate::structs::{AreaFilter, Change, RawChange};
         use regex::Regex;
 
         fn cooked_with_regex(include_regex: &str, exclude_regex: &str) -> Change {
@@ -591,8 +1848,15 @@ mod tests {
                 finsh: chrono::DateTime::parse_from_rfc3339("2022-01-02T08:00:00+02:00").unwrap(),
                 stage: 1,
                 source: "Test source".to_string(),
-                include_regex: Regex::new(include_regex).unwrap(),
-                exclude_regex: Regex::new(exclude_regex).unwrap(),
+                area_filter: AreaFilter {
+                    include_regex: Regex::new(include_regex).unwrap(),
+                    exclude_regex: Regex::new(exclude_regex).unwrap(),
+                    selector: Default::default(),
+                    areas: None,
+                },
+                national: include_regex == ".*",
+                until_further_notice: false,
+                tentative: false,
             }
         }
 
@@ -606,6 +1870,8 @@ mod tests {
                 exclude_regex: None,
                 include,
                 exclude,
+                selector: Default::default(),
+                until_further_notice: false,
             }
         }
 
@@ -632,26 +1898,486 @@ mod tests {
             ];
             for incl in &shorthand_to_longhand {
                 assert_eq!(
-                    Into::<Change>::into(raw_with_regex(Some(incl.0.to_string()), None)),
+                    Change::try_from(raw_with_regex(Some(incl.0.to_string()), None)).unwrap(),
                     cooked_with_regex(incl.1, "matchnothing^")
                 );
                 let is_first = false;
                 for excl in &shorthand_to_longhand {
                     if is_first {
                         assert_eq!(
-                            Into::<Change>::into(raw_with_regex(None, Some(excl.0.to_string()))),
+                            Change::try_from(raw_with_regex(None, Some(excl.0.to_string())))
+                                .unwrap(),
                             cooked_with_regex(".*", excl.1)
                         );
                     }
                     assert_eq!(
-                        Into::<Change>::into(raw_with_regex(
+                        Change::try_from(raw_with_regex(
                             Some(incl.0.to_string()),
                             Some(excl.0.to_string())
-                        )),
+                        ))
+                        .unwrap(),
                         cooked_with_regex(incl.1, excl.1)
                     );
                 }
             }
         }
     }
+    mod stage_rules {
+        use crate::structs::{Change, ManuallyInputSchedule, RawChange, RawStageRule, StageRule};
+
+        fn change(start: &str, finsh: &str, stage: u8, include: Option<&str>) -> Change {
+            RawChange {
+                start: start.to_string(),
+                finsh: finsh.to_string(),
+                stage,
+                source: "Test source".to_string(),
+                include_regex: None,
+                exclude_regex: None,
+                include: include.map(str::to_string),
+                exclude: None,
+                selector: Default::default(),
+                until_further_notice: false,
+            }
+            .try_into()
+            .unwrap()
+        }
+
+        fn rule(offset: i8, during: Option<&str>, delay: Option<&str>) -> StageRule {
+            RawStageRule {
+                include: Some("coct".to_string()),
+                include_regex: None,
+                offset,
+                during: during.map(str::to_string),
+                delay: delay.map(str::to_string),
+            }
+            .try_into()
+            .unwrap()
+        }
+
+        /// The (start, finsh, stage) of each change, with the times as `DD HH:MM`.
+        fn summary(changes: &[Change]) -> Vec<(String, String, u8)> {
+            changes
+                .iter()
+                .map(|c| {
+                    (
+                        c.start.format("%d %H:%M").to_string(),
+                        c.finsh.format("%d %H:%M").to_string(),
+                        c.stage,
+                    )
+                })
+                .collect()
+        }
+
+        #[test]
+        fn offset_during_window() {
+            let national = change("2023-01-01T00:00:00", "2023-01-03T00:00:00", 4, None);
+            let applied = rule(-1, Some("06:00-22:00"), None).apply(&national);
+            assert_eq!(
+                summary(&applied),
+                vec![
+                    ("01 00:00".into(), "01 06:00".into(), 4),
+                    ("01 06:00".into(), "01 22:00".into(), 3),
+                    ("01 22:00".into(), "02 06:00".into(), 4),
+                    ("02 06:00".into(), "02 22:00".into(), 3),
+                    ("02 22:00".into(), "03 00:00".into(), 4),
+                ]
+            );
+        }
+
+        #[test]
+        fn window_over_midnight_and_stage_zero_dropped() {
+            let national = change("2023-01-01T12:00:00", "2023-01-02T12:00:00", 1, None);
+            let applied = rule(-1, Some("22:00-06:00"), None).apply(&national);
+            assert_eq!(
+                summary(&applied),
+                vec![
+                    ("01 12:00".into(), "01 22:00".into(), 1),
+                    ("02 06:00".into(), "02 12:00".into(), 1),
+                ]
+            );
+            assert!(rule(-2, None, None).apply(&national).is_empty());
+        }
+
+        #[test]
+        fn delayed() {
+            let national = change("2023-01-01T16:00:00", "2023-01-01T22:00:00", 2, None);
+            let applied = rule(0, None, Some("02:00")).apply(&national);
+            assert_eq!(
+                summary(&applied),
+                vec![("01 18:00".into(), "02 00:00".into(), 2)]
+            );
+        }
+
+        #[test]
+        fn only_national_changes_in_matching_areas() {
+            let schedule = ManuallyInputSchedule {
+                changes: vec![
+                    change("2023-01-01T00:00:00", "2023-01-02T00:00:00", 4, None),
+                    change(
+                        "2023-01-02T00:00:00",
+                        "2023-01-03T00:00:00",
+                        2,
+                        Some("coct"),
+                    ),
+                ],
+                historical_changes: vec![],
+                stage_rules: vec![rule(-1, None, None)],
+                exemptions: vec![],
+                projected_until: None,
+            };
+            let stages = |area_name: &str| {
+                schedule
+                    .changes_for_area(area_name)
+                    .iter()
+                    .map(|c| c.stage)
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(stages("city-of-cape-town-area-1"), vec![3, 2]);
+            assert_eq!(stages("western-cape-stellenbosch"), vec![4]);
+        }
+
+        #[test]
+        fn err_if_rule_is_invalid() {
+            let raw =
+                |include: Option<&str>, during: Option<&str>, delay: Option<&str>| RawStageRule {
+                    include: include.map(str::to_string),
+                    include_regex: None,
+                    offset: -1,
+                    during: during.map(str::to_string),
+                    delay: delay.map(str::to_string),
+                };
+            for raw in [
+                raw(None, None, None),
+                raw(Some("nowhere"), None, None),
+                raw(Some("coct"), Some("06:00"), None),
+                raw(Some("coct"), Some("06:00-2200"), None),
+                raw(Some("coct"), None, Some("2h")),
+            ] {
+                let err = StageRule::try_from(raw).unwrap_err().to_string();
+                assert!(err.contains("RawStageRule"), "{err}");
+            }
+        }
+    }
+    mod selectors {
+        use crate::structs::{
+            AreaMetadata, AreaSelector, ManuallyInputSchedule, RawManuallyInputSchedule,
+        };
+
+        const METADATA: &str = "
+area_details:
+- calendar_name: western-cape-stellenbosch.ics
+  provider: eskom
+  areas:
+  - { province: western-cape, municipality: stellenbosch, name: [idas-valley] }
+- calendar_name: western-cape-drakenstein.ics
+  provider: eskom
+  areas:
+  - { province: western-cape, municipality: drakenstein, name: [paarl] }
+- calendar_name: city-of-cape-town-area-1.ics
+  province: western-cape
+  municipality: city-of-cape-town
+  provider: city-of-cape-town
+  areas:
+  - { name: [bellville] }
+";
+
+        fn metadata() -> AreaMetadata {
+            serde_yaml::from_str(METADATA).unwrap()
+        }
+
+        #[test]
+        fn every_selector_must_match() {
+            let selector = AreaSelector {
+                province: Some("western-cape".to_owned()),
+                provider: Some("eskom".to_owned()),
+                ..Default::default()
+            };
+            assert_eq!(
+                selector.resolve(&metadata()).unwrap(),
+                ["western-cape-drakenstein", "western-cape-stellenbosch"]
+                    .map(str::to_owned)
+                    .into()
+            );
+            let selector = AreaSelector {
+                municipality: Some("stellenbosch".to_owned()),
+                areas: Some(vec!["western-cape-drakenstein".to_owned()]),
+                ..Default::default()
+            };
+            assert!(selector.resolve(&metadata()).is_err());
+        }
+
+        #[test]
+        fn err_if_unknown_area() {
+            let selector = AreaSelector {
+                areas: Some(vec![
+                    "western-cape-stellenbosch".to_owned(),
+                    "western-cape-stelenbosch".to_owned(),
+                ]),
+                ..Default::default()
+            };
+            let err = selector.resolve(&metadata()).unwrap_err().to_string();
+            assert!(err.contains("western-cape-stelenbosch"), "{err}");
+            assert!(!err.contains("western-cape-stellenbosch\""), "{err}");
+        }
+
+        #[test]
+        fn selectors_combine_with_regexes() {
+            let yaml = "
+changes:
+- { stage: 2, start: 2023-01-01T16:00:00, finsh: 2023-01-01T22:00:00, source: a, province: western-cape, exclude: coct }
+- { stage: 4, start: 2023-01-02T16:00:00, finsh: 2023-01-02T22:00:00, source: b, areas: [city-of-cape-town-area-1] }
+historical_changes: []
+";
+            let mut schedule: ManuallyInputSchedule =
+                serde_yaml::from_str::<RawManuallyInputSchedule>(yaml)
+                    .unwrap()
+                    .try_into()
+                    .unwrap();
+            schedule.resolve_selectors(&metadata()).unwrap();
+            let stages = |area_name: &str| {
+                schedule
+                    .changes
+                    .iter()
+                    .filter(|c| c.area_filter.applies_to(area_name))
+                    .map(|c| c.stage)
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(stages("western-cape-stellenbosch"), vec![2]);
+            assert_eq!(stages("city-of-cape-town-area-1"), vec![4]);
+            assert_eq!(stages("gauteng-tshwane-group-1"), Vec::<u8>::new());
+            assert!(!schedule.changes[0].national);
+        }
+    }
+    mod exemptions {
+        use crate::structs::{
+            Exemption, ManuallyInputSchedule, PowerOutage, RawExemption, RawManuallyInputSchedule,
+        };
+        use chrono::DateTime;
+
+        fn outage(area_name: &str, start: &str, finsh: &str) -> PowerOutage {
+            PowerOutage {
+                area_name: area_name.to_string(),
+                stage: 2,
+                start: DateTime::parse_from_rfc3339(&format!("{start}:00+02:00")).unwrap(),
+                finsh: DateTime::parse_from_rfc3339(&format!("{finsh}:00+02:00")).unwrap(),
+                source: "Test source".to_string(),
+                cancelled: false,
+                tentative: false,
+            }
+        }
+
+        /// The (start, finsh) of each outage, as `DD HH:MM`.
+        fn summary(outages: &[PowerOutage]) -> Vec<(String, String)> {
+            outages
+                .iter()
+                .map(|o| {
+                    (
+                        o.start.format("%d %H:%M").to_string(),
+                        o.finsh.format("%d %H:%M").to_string(),
+                    )
+                })
+                .collect()
+        }
+
+        fn schedule() -> ManuallyInputSchedule {
+            serde_yaml::from_str::<RawManuallyInputSchedule>(
+                "
+changes: []
+historical_changes: []
+exemptions:
+- start: 2024-05-29T06:00:00
+  finsh: 2024-05-29T20:00:00
+  reason: General elections
+  source: https://example.com
+  exclude: coct
+",
+            )
+            .unwrap()
+            .try_into()
+            .unwrap()
+        }
+
+        #[test]
+        fn outages_are_removed_or_clipped() {
+            let outages = schedule().without_exemptions(vec![
+                outage(
+                    "western-cape-stellenbosch",
+                    "2024-05-29T04:00",
+                    "2024-05-29T06:30",
+                ),
+                outage(
+                    "western-cape-stellenbosch",
+                    "2024-05-29T10:00",
+                    "2024-05-29T12:30",
+                ),
+                outage(
+                    "western-cape-stellenbosch",
+                    "2024-05-29T18:00",
+                    "2024-05-29T20:30",
+                ),
+                outage(
+                    "western-cape-stellenbosch",
+                    "2024-05-29T20:00",
+                    "2024-05-29T22:30",
+                ),
+                outage(
+                    "western-cape-stellenbosch",
+                    "2024-05-28T20:00",
+                    "2024-05-30T02:00",
+                ),
+            ]);
+            assert_eq!(
+                summary(&outages),
+                vec![
+                    ("29 04:00".into(), "29 06:00".into()),
+                    ("29 20:00".into(), "29 20:30".into()),
+                    ("29 20:00".into(), "29 22:30".into()),
+                    ("28 20:00".into(), "29 06:00".into()),
+                    ("29 20:00".into(), "30 02:00".into()),
+                ]
+            );
+        }
+
+        #[test]
+        fn only_in_matching_areas() {
+            let schedule = schedule();
+            let during = outage(
+                "city-of-cape-town-area-1",
+                "2024-05-29T10:00",
+                "2024-05-29T12:30",
+            );
+            assert_eq!(
+                schedule.without_exemptions(vec![during.clone()]),
+                vec![during]
+            );
+            assert!(schedule
+                .exemptions_for_area("city-of-cape-town-area-1")
+                .is_empty());
+            assert_eq!(
+                schedule
+                    .exemptions_for_area("western-cape-stellenbosch")
+                    .len(),
+                1
+            );
+        }
+
+        #[test]
+        fn err_if_exemption_is_invalid() {
+            let raw = |start: &str, finsh: &str| RawExemption {
+                start: start.to_string(),
+                finsh: finsh.to_string(),
+                source: "https://example.com".to_string(),
+                reason: None,
+                include_regex: None,
+                exclude_regex: None,
+                include: None,
+                exclude: None,
+                selector: Default::default(),
+            };
+            for raw in [
+                raw("2024-05-29T06:00", "2024-05-29T20:00:00"),
+                raw("2024-05-29T20:00:00", "2024-05-29T06:00:00"),
+            ] {
+                let err = Exemption::try_from(raw).unwrap_err().to_string();
+                assert!(err.contains("RawExemption"), "{err}");
+            }
+        }
+    }
+    mod until_further_notice {
+        use crate::structs::{ManuallyInputSchedule, RawManuallyInputSchedule};
+        use chrono::DateTime;
+
+        fn schedule() -> ManuallyInputSchedule {
+            serde_yaml::from_str::<RawManuallyInputSchedule>(
+                "
+changes:
+- stage: 4
+  start: 2023-01-01T00:00:00
+  finsh: 2023-01-02T00:00:00
+  source: https://example.com/national
+  until_further_notice: true
+- stage: 2
+  start: 2023-01-04T00:00:00
+  finsh: 2023-01-05T00:00:00
+  source: https://example.com/coct
+  include: coct
+historical_changes: []
+",
+            )
+            .unwrap()
+            .try_into()
+            .unwrap()
+        }
+
+        /// The (start, finsh, stage, tentative) of each change for `area_name`, as `DD HH:MM`.
+        fn summary(schedule: &ManuallyInputSchedule, area_name: &str) -> Vec<String> {
+            schedule
+                .changes_for_area(area_name)
+                .iter()
+                .map(|c| {
+                    format!(
+                        "{} {} {} {}",
+                        c.start.format("%d %H:%M"),
+                        c.finsh.format("%d %H:%M"),
+                        c.stage,
+                        c.tentative
+                    )
+                })
+                .collect()
+        }
+
+        #[test]
+        fn not_projected_unless_asked() {
+            assert_eq!(
+                summary(&schedule(), "western-cape-stellenbosch"),
+                vec!["01 00:00 02 00:00 4 false"]
+            );
+        }
+
+        #[test]
+        fn projected_until_next_change_or_horizon() {
+            let mut schedule = schedule();
+            schedule
+                .project_until(DateTime::parse_from_rfc3339("2023-01-06T00:00:00+02:00").unwrap());
+            assert_eq!(
+                summary(&schedule, "western-cape-stellenbosch"),
+                vec!["01 00:00 02 00:00 4 false", "02 00:00 06 00:00 4 true"]
+            );
+            assert_eq!(
+                summary(&schedule, "city-of-cape-town-area-1"),
+                vec![
+                    "01 00:00 02 00:00 4 false",
+                    "04 00:00 05 00:00 2 false",
+                    "02 00:00 04 00:00 4 true",
+                ]
+            );
+        }
+    }
+    mod args {
+        use crate::structs::{Args, Command};
+        use clap::Parser;
+
+        fn command(args: &[&str]) -> Result<Command, clap::Error> {
+            Args::parse_from([&["eskom-calendar"], args].concat()).command()
+        }
+
+        #[test]
+        fn generate_options_only_without_another_command() {
+            assert!(matches!(
+                command(&["--output-csv-file=false"]),
+                Ok(Command::Generate(args)) if args.output_csv_file == Some(false)
+            ));
+            assert!(matches!(
+                command(&["generate", "--output-csv-file=false"]),
+                Ok(Command::Generate(args)) if args.output_csv_file == Some(false)
+            ));
+            assert!(matches!(
+                command(&["--output-dir", "/tmp", "check"]),
+                Ok(Command::Check)
+            ));
+            assert!(command(&["--output-csv-file=false", "check"]).is_err());
+            assert!(command(&["--include-regex", "coct", "generate"]).is_err());
+            assert!(command(&["--only-check-for-overlaps=true", "check"]).is_err());
+        }
+    }
 }
@@ -0,0 +1,298 @@
+//! A timeline of the loadshedding stage over time, built from the changes in
+//! `manually_specified.yaml`. Each area's calendar only shows that area's outages, so the timeline
+//! is the one place to see which stage the country is at. Providers whose stage differs from the
+//! national stage (like City of Cape Town, which is often a stage lower than Eskom) get a timeline
+//! of their own.
+use crate::fmt;
+use crate::manifest::write_atomically;
+use crate::structs::{AreaMetadata, BuildContext, Change, ManuallyInputSchedule};
+use crate::BoxedError;
+use chrono::{DateTime, FixedOffset};
+use icalendar::Calendar;
+use log::info;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::create_dir_all;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// The name of the timeline which every area follows unless a change or stage rule says otherwise.
+pub const NATIONAL: &str = "national";
+
+/// A period during which the stage stays the same.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct StageBlock {
+    pub start: DateTime<FixedOffset>,
+    pub finsh: DateTime<FixedOffset>,
+    pub stage: u8,
+    /// The source of information for this stage
+    pub source: String,
+    /// Whether this block was projected from a change which continues until further notice
+    pub tentative: bool,
+}
+
+/// The stage over time, either nationally or for a single provider.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Timeline {
+    /// `national`, or the provider as written in `area_metadata.yaml` (like `city-of-cape-town`)
+    pub name: String,
+    pub blocks: Vec<StageBlock>,
+}
+
+/// Convert some changes (sorted by their start) into stage blocks. Blocks which carry on from
+/// each other with the same stage, source and tentativeness are merged, so that an announcement
+/// which was entered as several back-to-back changes is shown as one block.
+pub fn stage_blocks(changes: &[Change]) -> Vec<StageBlock> {
+    let mut blocks: Vec<StageBlock> = vec![];
+    for change in changes {
+        if let Some(last) = blocks.last_mut() {
+            if last.finsh == change.start
+                && last.stage == change.stage
+                && last.source == change.source
+                && last.tentative == change.tentative
+            {
+                last.finsh = change.finsh;
+                continue;
+            }
+        }
+        blocks.push(StageBlock {
+            start: change.start,
+            finsh: change.finsh,
+            stage: change.stage,
+            source: change.source.clone(),
+            tentative: change.tentative,
+        });
+    }
+    blocks
+}
+
+/// The national timeline, followed by a timeline for each provider whose areas have a different
+/// stage to the national stage at some point. If a provider's areas don't all agree with each
+/// other, the timeline which most of them follow is used.
+pub fn timelines(
+    manually_specified: &ManuallyInputSchedule,
+    metadata: &AreaMetadata,
+) -> Vec<Timeline> {
+    let national = stage_blocks(&manually_specified.national_timeline());
+
+    let mut areas_by_provider: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for detail in &metadata.area_details {
+        if let Some(provider) = &detail.provider {
+            areas_by_provider
+                .entry(provider)
+                .or_default()
+                .push(detail.area_name());
+        }
+    }
+
+    let mut timelines = vec![Timeline {
+        name: NATIONAL.to_owned(),
+        blocks: national.clone(),
+    }];
+    for (provider, area_names) in areas_by_provider {
+        let mut counts: Vec<(Vec<StageBlock>, usize)> = vec![];
+        for area_name in area_names {
+            let blocks = stage_blocks(&manually_specified.timeline_for_area(area_name));
+            match counts.iter_mut().find(|(b, _count)| *b == blocks) {
+                Some((_blocks, count)) => *count += 1,
+                None => counts.push((blocks, 1)),
+            }
+        }
+        // `max_by_key` picks the last of equally common timelines, so reverse to prefer the first
+        let most_common = counts
+            .into_iter()
+            .rev()
+            .max_by_key(|(_blocks, count)| *count)
+            .map(|(blocks, _count)| blocks)
+            .unwrap_or_default();
+        if most_common != national {
+            timelines.push(Timeline {
+                name: provider.to_owned(),
+                blocks: most_common,
+            });
+        }
+    }
+    timelines
+}
+
+/// Write the `timelines` into `dir`: an ICS feed for each timeline (like `national.ics`), and
+/// every timeline together in `timeline.csv` and `timeline.json`. Returns the paths of the files
+/// which were written, along with the number of blocks in each.
+pub fn write_timelines(
+    timelines: &[Timeline],
+    dir: &Path,
+    build: &BuildContext,
+) -> Result<Vec<(PathBuf, usize)>, BoxedError> {
+    create_dir_all(dir)?;
+    let mut written = vec![];
+    for timeline in timelines {
+        let path = dir.join(format!("{}.ics", timeline.name));
+        info!("Writing {} stage blocks to {path:?}", timeline.blocks.len());
+        let mut calendar = Calendar::new();
+        for block in &timeline.blocks {
+            calendar.push(fmt::stage_block_event(block, &timeline.name, build)?);
+        }
+        write_atomically(&path, |file| {
+            writeln!(file, "{}", calendar)?;
+            Ok(())
+        })?;
+        written.push((path, timeline.blocks.len()));
+    }
+
+    let block_count = timelines.iter().map(|t| t.blocks.len()).sum();
+    let path = dir.join("timeline.csv");
+    write_atomically(&path, |file| {
+        let mut writer = BufWriter::new(file);
+        writeln!(writer, "timeline,start,finsh,stage,source,tentative")?;
+        for timeline in timelines {
+            for block in &timeline.blocks {
+                writeln!(
+                    writer,
+                    "{},{:?},{:?},{},{:?},{}",
+                    timeline.name,
+                    block.start,
+                    block.finsh,
+                    block.stage,
+                    block.source,
+                    block.tentative
+                )?;
+            }
+        }
+        Ok(writer.flush()?)
+    })?;
+    written.push((path, block_count));
+
+    let path = dir.join("timeline.json");
+    write_atomically(&path, |file| {
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, timelines)?;
+        writeln!(writer)?;
+        Ok(writer.flush()?)
+    })?;
+    written.push((path, block_count));
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::structs::{AreaMetadata, ManuallyInputSchedule, RawManuallyInputSchedule};
+    use crate::timeline::{timelines, NATIONAL};
+
+    fn schedule(yaml: &str) -> ManuallyInputSchedule {
+        serde_yaml::from_str::<RawManuallyInputSchedule>(yaml)
+            .unwrap()
+            .try_into()
+            .unwrap()
+    }
+
+    fn metadata() -> AreaMetadata {
+        serde_yaml::from_str(
+            "
+area_details:
+- calendar_name: western-cape-stellenbosch.ics
+  provider: eskom
+  areas: []
+- calendar_name: city-of-cape-town-area-1.ics
+  provider: city-of-cape-town
+  areas: []
+- calendar_name: city-of-cape-town-area-2.ics
+  provider: city-of-cape-town
+  areas: []
+",
+        )
+        .unwrap()
+    }
+
+    /// The name of each timeline, and its blocks as `stage: DD HH:MM-DD HH:MM`.
+    fn summary(yaml: &str) -> Vec<(String, Vec<String>)> {
+        timelines(&schedule(yaml), &metadata())
+            .into_iter()
+            .map(|t| {
+                let blocks = t
+                    .blocks
+                    .iter()
+                    .map(|b| {
+                        format!(
+                            "{}: {}-{}",
+                            b.stage,
+                            b.start.format("%d %H:%M"),
+                            b.finsh.format("%d %H:%M")
+                        )
+                    })
+                    .collect();
+                (t.name, blocks)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn providers_only_when_they_diverge() {
+        let national = "
+changes:
+- stage: 4
+  start: 2023-01-02T00:00:00
+  finsh: 2023-01-03T00:00:00
+  source: https://example.com
+historical_changes:
+- stage: 2
+  start: 2023-01-01T00:00:00
+  finsh: 2023-01-02T00:00:00
+  source: https://example.com
+";
+        assert_eq!(
+            summary(national),
+            vec![(
+                NATIONAL.to_owned(),
+                vec!["2: 01 00:00-02 00:00".into(), "4: 02 00:00-03 00:00".into()]
+            )]
+        );
+
+        let cape_town_lower = format!(
+            "{national}
+stage_rules:
+- include: coct
+  offset: -1
+"
+        );
+        assert_eq!(
+            summary(&cape_town_lower)[1],
+            (
+                "city-of-cape-town".to_owned(),
+                vec!["1: 01 00:00-02 00:00".into(), "3: 02 00:00-03 00:00".into()]
+            )
+        );
+    }
+
+    #[test]
+    fn contiguous_blocks_are_merged() {
+        let yaml = "
+changes:
+- stage: 2
+  start: 2023-01-01T00:00:00
+  finsh: 2023-01-01T05:00:00
+  source: https://example.com
+  exclude: coct
+- stage: 2
+  start: 2023-01-01T05:00:00
+  finsh: 2023-01-01T16:00:00
+  source: https://example.com
+  exclude: coct
+- stage: 1
+  start: 2023-01-01T00:00:00
+  finsh: 2023-01-01T16:00:00
+  source: https://example.com
+  include: coct
+historical_changes: []
+";
+        assert_eq!(
+            summary(yaml),
+            vec![
+                (NATIONAL.to_owned(), vec!["2: 01 00:00-01 16:00".into()]),
+                (
+                    "city-of-cape-town".to_owned(),
+                    vec!["1: 01 00:00-01 16:00".into()]
+                ),
+            ]
+        );
+    }
+}